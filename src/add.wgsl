@@ -2,9 +2,81 @@ struct U128 {
     parts: array<u32, 4>  // Little-endian: [low, mid_low, mid_high, high]
 }
 
+// Everything `collatz` works out for one number. Only steps and max are
+// tracked unless full_record is set, see `store_result` for what the host
+// reads back.
 struct CollatzResult {
     steps: u32,
     max: U128,
+    // step and value where bit_length(value) - bit_length(start) first peaks
+    height_step: u32,
+    height: U128,
+    // halvings, which is the step count under the shortcut map
+    // (3n + 1) / 2 since every 3n + 1 is followed by one
+    steps_shortcut: u32,
+    // step where the value is first a power of two, NO_POWER_OF_TWO if it
+    // never was
+    power_step: u32,
+    // longest run of halvings in the low 16 bits, longest run of odd steps
+    // under the shortcut map in the high 16
+    runs: u32,
+    // every value from the start to the first 1 added up, or to the last
+    // value before an overflow, stuck at MAX_U128 once it doesn't fit
+    sum: U128,
+}
+
+struct ParityEncoding {
+    // bit i is the parity of the i-th value, least significant bit first
+    bits: U128,
+    length: u32,
+    // 1 if 3n + 1 overflowed before length bits were recorded
+    overflowed: u32,
+}
+
+struct Trajectory {
+    length: u32,
+    // 1 if the trajectory needed more values than the buffer holds
+    truncated: u32,
+    // 1 if 3n + 1 overflowed 128 bits, the last value is the one before that
+    overflowed: u32,
+    values: array<U128>,
+}
+
+// What the rest of the trajectory from a number below lookup_below adds,
+// built on the host (see `lookup_table` in cpu.rs)
+struct LookupEntry {
+    // steps to 1 in the low 16 bits, halvings in the high 16
+    steps_halvings: u32,
+    max: u32,
+    // first value with the largest bit length and how many steps after the
+    // number it comes
+    height: u32,
+    height_offset: u32,
+    // steps from the number to its first power of two
+    power_offset: u32,
+    // from the number: longest run of halvings in bits 0-7, longest run of
+    // shortcut odd steps in bits 8-15 and the length of the first such run
+    // in bits 16-23
+    runs: u32,
+    // the values after the number down to 1 added up, below 2^64
+    sum_low: u32,
+    sum_high: u32,
+}
+
+struct ModOrbit {
+    // steps before the first value that is part of the cycle
+    tail: u32,
+    cycle_length: u32,
+}
+
+struct SignedOrbit {
+    // index into the known cycles (see `signed_cycle`), NO_SIGNED_CYCLE if
+    // it overflowed or hit the step limit first
+    cycle: u32,
+    // steps before the first value on the cycle
+    tail: u32,
+    // 1 if 3n + 1 left the i128 range
+    overflowed: u32,
 }
 
 struct U128AddResult {
@@ -13,10 +85,58 @@ struct U128AddResult {
 };
 
 @group(0) @binding(0) var<storage, read> input: array<U128>;
-@group(0) @binding(1) var<storage, read_write> output: array<CollatzResult>;
+@group(0) @binding(1) var<storage, read_write> output: array<u32>;
+@group(0) @binding(2) var<storage, read_write> encodings: array<ParityEncoding>;
+@group(0) @binding(3) var<storage, read_write> trajectory: Trajectory;
+@group(0) @binding(4) var<storage, read_write> hard: array<u32>;
+@group(0) @binding(5) var<storage, read_write> reach_step: array<i32>;
+@group(0) @binding(6) var<storage, read_write> peaks: array<u32>;
+@group(0) @binding(7) var<storage, read_write> orbits: array<ModOrbit>;
+@group(0) @binding(8) var<storage, read> lookup: array<LookupEntry>;
+@group(0) @binding(9) var<storage, read_write> group_counts: array<u32>;
+@group(0) @binding(10) var<storage, read_write> signed_results: array<SignedOrbit>;
+@group(0) @binding(11) var<storage, read_write> dropping_bins: array<atomic<u32>>;
+@group(0) @binding(12) var<storage, read_write> champion_keys: array<atomic<u32>>;
+@group(0) @binding(13) var<storage, read_write> failure_key: atomic<u32>;
+@group(0) @binding(14) var<storage, read_write> growth_key: atomic<u32>;
+
+// By default steps stop at the first 1, set by the host for
+// StepConvention::ThroughTrivialCycle to count 1 -> 4 -> 2 -> 1 as well
+override count_trivial_cycle: bool = false;
+
+// Set by the host for RunConfig::saturate_overflow: a 3n + 1 past 128 bits
+// clamps to MAX_U128 and the trajectory stops there, flagged with
+// SATURATED_FLAG, instead of reporting a max of 0
+override saturate_overflow: bool = false;
+
+// Set in the steps word of a trajectory that was clamped by
+// saturate_overflow. Steps never get near it, the top bit is for cycles.
+const SATURATED_FLAG: u32 = 1u << 30u;
+
+// Set by the host for RunConfig::full_record: `collatz` also tracks the
+// height record, shortcut steps, first power of two, longest runs and sum,
+// and `main` writes FULL_RECORD_WORDS per number instead of
+// SHORT_RECORD_WORDS
+override full_record: bool = false;
+
+// Steps and max, then a word that is always 1 so a written record is never
+// all zeros (an odd start whose first 3n + 1 overflows has 0 for both),
+// padded to a 32 byte stride
+const SHORT_RECORD_WORDS = 8u;
+// Every field of CollatzResult, in order
+const FULL_RECORD_WORDS = 17u;
+
+// Set by the host for InputLayout::StructOfArrays, where input holds every
+// number's lowest limb, then every number's second limb and so on
+override soa_input: bool = false;
+
+// Set by the host for InputLayout::PackedU32, where input holds one u32 per
+// number, four to a U128 slot
+override packed_input: bool = false;
 
 const ZERO_U128 = U128(array<u32, 4>(0u, 0u, 0u, 0u));
 const ONE_U128 = U128(array<u32, 4>(1u, 0u, 0u, 0u));
+const MAX_U128 = U128(array<u32, 4>(0xffffffffu, 0xffffffffu, 0xffffffffu, 0xffffffffu));
 
 fn is_one(n: U128) -> bool {
     return n.parts[0] == 1u && n.parts[1] == 0u && n.parts[2] == 0u && n.parts[3] == 0u;
@@ -64,6 +184,15 @@ fn add_u128(a: U128, b: U128) -> U128AddResult {
 }
 
 
+// a + b, or MAX_U128 if that overflows
+fn saturating_add(a: U128, b: U128) -> U128 {
+    let total = add_u128(a, b);
+    if (total.carry == 1u) {
+        return MAX_U128;
+    }
+    return total.value;
+}
+
 fn mul_3_add_1(n: U128) -> U128AddResult {
 
     let doubled = add_u128(n, n);
@@ -90,25 +219,134 @@ fn greater_than(a: U128, b: U128) -> bool {
     return a.parts[0] > b.parts[0];
 }
 
+fn bit_length(n: U128) -> u32 {
+    for (var i = 3i; i >= 0i; i--) {
+        if (n.parts[i] != 0u) {
+            return u32(i) * 32u + 32u - countLeadingZeros(n.parts[i]);
+        }
+    }
+    return 0u;
+}
+
+// Exactly one bit set across all four limbs
+fn is_power_of_two(n: U128) -> bool {
+    return countOneBits(n.parts[0]) + countOneBits(n.parts[1]) +
+           countOneBits(n.parts[2]) + countOneBits(n.parts[3]) == 1u;
+}
+
+const NO_POWER_OF_TWO: u32 = 0xffffffffu;
+
 fn equals(a: U128, b: U128) -> bool {
     return a.parts[0] == b.parts[0] && a.parts[1] == b.parts[1] && 
            a.parts[2] == b.parts[2] && a.parts[3] == b.parts[3];
 }
 
+// Set by the host for `main_lookup` to the length of the lookup table, so
+// `collatz` stops as soon as it gets below it. 0 never stops early.
+override lookup_below: u32 = 0u;
+
+// Where `collatz` stopped early for lookup_below, for `main_lookup` to
+// finish the result off from the table
+var<private> stopped_early: bool;
+var<private> stopped_at: u32;
+var<private> stopped_height_bits: u32;
+// the runs `collatz` was in the middle of when it stopped, see `runs_step`
+var<private> stopped_even_run: u32;
+var<private> stopped_odd_run: u32;
+
+// Longest runs so far and the ones in progress: halvings since the last odd
+// step, and odd steps each followed by exactly one halving, which are
+// consecutive odd steps of the shortcut map (3n + 1) / 2. Classic odd steps
+// are never consecutive since 3n + 1 is always even.
+struct Runs {
+    max_even: u32,
+    max_odd: u32,
+    even: u32,
+    odd: u32,
+}
+
+// `runs` after one more step, a halving or a 3n + 1
+fn runs_step(runs: Runs, halving: bool) -> Runs {
+    var r = runs;
+    if (halving) {
+        r.even++;
+        r.max_even = max(r.max_even, r.even);
+    } else {
+        // odd > 0 means there was an odd step before this one
+        if (r.odd > 0u && r.even == 1u) {
+            r.odd++;
+        } else {
+            r.odd = 1u;
+        }
+        r.max_odd = max(r.max_odd, r.odd);
+        r.even = 0u;
+    }
+    return r;
+}
+
+fn pack_runs(runs: Runs) -> u32 {
+    return runs.max_even | (runs.max_odd << 16u);
+}
+
+// Go once round 1 -> 4 -> 2 -> 1: 3 more steps, 2 of them halvings, 7 more
+// on the sum and a peak of at least 4. 1 is never reached right after an odd step and a
+// halving, so the odd step starts a new run and the 2 halvings make one.
+fn add_trivial_cycle(result: CollatzResult) -> CollatzResult {
+    var r = result;
+    r.steps += 3u;
+    r.steps_shortcut += 2u;
+    r.runs = max(r.runs & 0xffffu, 2u) | (max(r.runs >> 16u, 1u) << 16u);
+    r.sum = saturating_add(r.sum, U128(array<u32, 4>(7u, 0u, 0u, 0u)));
+    let four = U128(array<u32, 4>(4u, 0u, 0u, 0u));
+    if (greater_than(four, r.max)) {
+        r.max = four;
+    }
+    return r;
+}
+
+// steps is how many iterations it takes to first reach 1, plus 3 for the
+// trivial cycle when count_trivial_cycle is set (2 of them halvings)
 fn collatz(n_input: U128) -> CollatzResult {
     var n = n_input;
     var steps = 0u;
+    var halvings = 0u;
     var max = n;
+    var sum = n;
     
     var tortoise = n;
     var tortoise_steps = 0u;
 
+    // value / start approximated by the difference in bit length, start is
+    // fixed so only the bit length of the current value needs tracking
+    var height_bits = bit_length(n);
+    var height = n;
+    var height_step = 0u;
+
+    // from a power of two it only ever halves, so once found this is final
+    var power_step = select(NO_POWER_OF_TWO, 0u, is_power_of_two(n));
+
+    var runs = Runs(0u, 0u, 0u, 0u);
+
     var result: CollatzResult;
+    stopped_early = false;
+    var saturated = false;
     
     loop {
         if (is_one(n)) {
             break;
         }
+
+        // 0 and 1 never get here from anything but themselves, leave them
+        // to the loop
+        if (n.parts[3] == 0u && n.parts[2] == 0u && n.parts[1] == 0u &&
+            n.parts[0] > 1u && n.parts[0] < lookup_below) {
+            stopped_early = true;
+            stopped_at = n.parts[0];
+            stopped_height_bits = height_bits;
+            stopped_even_run = runs.even;
+            stopped_odd_run = runs.odd;
+            break;
+        }
         
         // Safety limit to prevent GPU hangs
         if (steps >= 100000u) {
@@ -117,16 +355,33 @@ fn collatz(n_input: U128) -> CollatzResult {
         
         if (is_even(n)) {
             n = div_by_2(n);
+            if (full_record) {
+                halvings++;
+                runs = runs_step(runs, true);
+            }
         } else {
             let a = mul_3_add_1(n);
-            if a.carry == 1u {
+            if (a.carry == 1u && saturate_overflow) {
+                // MAX_U128 is odd and would only clamp to itself again, so
+                // this is the last step
+                saturated = true;
+                n = MAX_U128;
+            } else if a.carry == 1u {
                 result.steps = steps;
                 result.max = ZERO_U128;
+                result.height_step = height_step;
+                result.height = height;
+                result.steps_shortcut = halvings;
+                result.power_step = NO_POWER_OF_TWO;
+                result.runs = pack_runs(runs);
+                result.sum = sum;
                 return result;
+            } else {
+                n = a.value;
+            }
+            if (full_record) {
+                runs = runs_step(runs, false);
             }
-
-            n = a.value;
-
         }
         
         if (greater_than(n, max)) {
@@ -134,6 +389,26 @@ fn collatz(n_input: U128) -> CollatzResult {
         }
         
         steps++;
+
+        if (full_record) {
+            sum = saturating_add(sum, n);
+
+            let bits = bit_length(n);
+            if (bits > height_bits) {
+                height_bits = bits;
+                height = n;
+                height_step = steps;
+            }
+
+            if (power_step == NO_POWER_OF_TWO && is_power_of_two(n)) {
+                power_step = steps;
+            }
+        }
+
+        if (saturated) {
+            steps = steps | SATURATED_FLAG;
+            break;
+        }
         
         if (steps % 2u == 0u) {
             if (is_even(tortoise)) {
@@ -154,13 +429,751 @@ fn collatz(n_input: U128) -> CollatzResult {
     
     result.steps = steps;
     result.max = max;
+    result.height_step = height_step;
+    result.height = height;
+    result.steps_shortcut = halvings;
+    result.power_step = power_step;
+    result.runs = pack_runs(runs);
+    result.sum = sum;
+
+    // go once round 1 -> 4 -> 2 -> 1 if the host asked for that convention
+    if (count_trivial_cycle && is_one(n)) {
+        result = add_trivial_cycle(result);
+    }
     return result;
 }
 
-@compute @workgroup_size(64)
+// Number idx out of input in whichever layout the host uploaded
+fn load_input(idx: u32) -> U128 {
+    if (packed_input) {
+        return U128(array<u32, 4>(input[idx / 4u].parts[idx % 4u], 0u, 0u, 0u));
+    }
+    if (!soa_input) {
+        return input[idx];
+    }
+
+    let count = arrayLength(&input);
+    var n: U128;
+    for (var limb = 0u; limb < 4u; limb++) {
+        let word = limb * count + idx;
+        n.parts[limb] = input[word / 4u].parts[word % 4u];
+    }
+    return n;
+}
+
+// Set by the host from WORKGROUP_SIZE in gpu.rs so the dispatch math always matches
+override workgroup_size: u32 = 64u;
+
+// Consecutive numbers each `main` or `main_lookup` invocation runs, so a
+// dispatch needs that many times fewer threads. Set by the host, which
+// dispatches to match.
+override numbers_per_thread: u32 = 1u;
+
+// Write `r` out as record idx: SHORT_RECORD_WORDS with steps and max, or
+// with full_record FULL_RECORD_WORDS holding every field
+fn store_result(idx: u32, r: CollatzResult) {
+    if (!full_record) {
+        let at = idx * SHORT_RECORD_WORDS;
+        output[at] = r.steps;
+        for (var i = 0u; i < 4u; i++) {
+            output[at + 1u + i] = r.max.parts[i];
+        }
+        output[at + 5u] = 1u;
+        output[at + 6u] = 0u;
+        output[at + 7u] = 0u;
+        return;
+    }
+
+    let at = idx * FULL_RECORD_WORDS;
+    output[at] = r.steps;
+    for (var i = 0u; i < 4u; i++) {
+        output[at + 1u + i] = r.max.parts[i];
+        output[at + 6u + i] = r.height.parts[i];
+        output[at + 13u + i] = r.sum.parts[i];
+    }
+    output[at + 5u] = r.height_step;
+    output[at + 10u] = r.steps_shortcut;
+    output[at + 11u] = r.power_step;
+    output[at + 12u] = r.runs;
+}
+
+// Records output has room for
+fn output_records() -> u32 {
+    return arrayLength(&output) / select(SHORT_RECORD_WORDS, FULL_RECORD_WORDS, full_record);
+}
+
+// Both bound by output, which holds exactly one result per number in any
+// layout. A PackedU32 input can end in up to three padding numbers.
+@compute @workgroup_size(workgroup_size)
 fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    for (var i = 0u; i < numbers_per_thread; i++) {
+        let idx = id.x * numbers_per_thread + i;
+        if (idx >= output_records()) {
+            break;
+        }
+        store_result(idx, collatz(load_input(idx)));
+    }
+}
+
+// Same results as `main`, but trajectories that get below lookup_below take
+// the rest from the lookup table instead of iterating down to 1
+@compute @workgroup_size(workgroup_size)
+fn main_lookup(@builtin(global_invocation_id) id: vec3<u32>) {
+    for (var i = 0u; i < numbers_per_thread; i++) {
+        let idx = id.x * numbers_per_thread + i;
+        if (idx >= output_records()) {
+            break;
+        }
+        store_result(idx, collatz_with_lookup(load_input(idx)));
+    }
+}
+
+// `collatz`, finishing off from the lookup table if it stopped early
+fn collatz_with_lookup(n: U128) -> CollatzResult {
+    var result = collatz(n);
+    if (stopped_early) {
+        let entry = lookup[stopped_at];
+        let max = U128(array<u32, 4>(entry.max, 0u, 0u, 0u));
+        if (greater_than(max, result.max)) {
+            result.max = max;
+        }
+        let height = U128(array<u32, 4>(entry.height, 0u, 0u, 0u));
+        if (bit_length(height) > stopped_height_bits) {
+            result.height = height;
+            result.height_step = result.steps + entry.height_offset;
+        }
+        // a power of two before the table number was already recorded
+        if (result.power_step == NO_POWER_OF_TWO) {
+            result.power_step = result.steps + entry.power_offset;
+        }
+        result.steps += entry.steps_halvings & 0xffffu;
+        result.steps_shortcut += entry.steps_halvings >> 16u;
+
+        // the run of halvings in progress goes on through the table
+        // number's trailing zeros, and the odd run in progress goes on into
+        // the table's first one if that makes exactly one halving between
+        // them and the value after the halvings isn't 1
+        let zeros = countTrailingZeros(stopped_at);
+        var max_even = max(max(result.runs & 0xffffu, entry.runs & 0xffu), stopped_even_run + zeros);
+        var max_odd = max(result.runs >> 16u, (entry.runs >> 8u) & 0xffu);
+        if (stopped_odd_run > 0u && stopped_even_run + zeros == 1u && (stopped_at >> zeros) != 1u) {
+            max_odd = max(max_odd, stopped_odd_run + (entry.runs >> 16u));
+        }
+        result.runs = max_even | (max_odd << 16u);
+        result.sum = saturating_add(result.sum, U128(array<u32, 4>(entry.sum_low, entry.sum_high, 0u, 0u)));
+
+        if (count_trivial_cycle) {
+            result = add_trivial_cycle(result);
+        }
+    }
+    return result;
+}
+
+// Parity bits `encode` records, 1 to 128. Set by the host for each call.
+override encoding_bits: u32 = 128u;
+
+// Parity vector under the shortcut map, (3n + 1) / 2 for odd n and n / 2 for
+// even, until it reaches 1 or encoding_bits bits are recorded. The first k
+// bits of it pick out n mod 2^k exactly.
+fn parity_encoding(n_input: U128) -> ParityEncoding {
+    var n = n_input;
+
+    var result: ParityEncoding;
+    result.bits = ZERO_U128;
+    result.length = 0u;
+    result.overflowed = 0u;
+
+    loop {
+        if (is_one(n) || result.length >= encoding_bits) {
+            break;
+        }
+
+        if (is_even(n)) {
+            n = div_by_2(n);
+        } else {
+            let a = mul_3_add_1(n);
+            if a.carry == 1u {
+                result.overflowed = 1u;
+                break;
+            }
+            n = div_by_2(a.value);
+            result.bits.parts[result.length / 32u] |= 1u << (result.length % 32u);
+        }
+
+        result.length++;
+    }
+
+    return result;
+}
+
+@compute @workgroup_size(workgroup_size)
+fn encode(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        encodings[idx] = parity_encoding(input[idx]);
+    }
+}
+
+// Every value from n_input down to 1, as many as fit in trajectory.values
+fn write_trajectory(n_input: U128) {
+    let capacity = arrayLength(&trajectory.values);
+    var n = n_input;
+    var length = 0u;
+
+    trajectory.truncated = 0u;
+    trajectory.overflowed = 0u;
+
+    loop {
+        if (length == capacity) {
+            trajectory.truncated = 1u;
+            break;
+        }
+
+        trajectory.values[length] = n;
+        length++;
+
+        if (is_one(n)) {
+            break;
+        }
+
+        if (is_even(n)) {
+            n = div_by_2(n);
+        } else {
+            let a = mul_3_add_1(n);
+            if a.carry == 1u {
+                trajectory.overflowed = 1u;
+                break;
+            }
+            n = a.value;
+        }
+    }
+
+    trajectory.length = length;
+}
+
+// One trajectory is inherently serial, so only the first invocation works
+// and only on the first input
+@compute @workgroup_size(workgroup_size)
+fn trace(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x == 0u && arrayLength(&input) > 0u) {
+        write_trajectory(input[0]);
+    }
+}
+
+// Steps `quick_check` gives a number to drop below its start, at least 1.
+// Set by the host for each call.
+override quick_steps: u32 = 10u;
+
+// 1 if n_input neither drops below itself nor reaches 1 within quick_steps
+// steps, overflowing counts as not dropping
+fn is_hard(n_input: U128) -> u32 {
+    var n = n_input;
+    for (var step = 0u; step < quick_steps; step++) {
+        if (is_one(n)) {
+            return 0u;
+        }
+
+        if (is_even(n)) {
+            n = div_by_2(n);
+        } else {
+            let a = mul_3_add_1(n);
+            if a.carry == 1u {
+                return 1u;
+            }
+            n = a.value;
+        }
+
+        if (greater_than(n_input, n)) {
+            return 0u;
+        }
+    }
+    return 1u;
+}
+
+@compute @workgroup_size(workgroup_size)
+fn quick_check(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        hard[idx] = is_hard(input[idx]);
+    }
+}
+
+// Value `reaches` looks for, little-endian limbs like U128. Set by the host
+// for each call.
+override target_0: u32 = 1u;
+override target_1: u32 = 0u;
+override target_2: u32 = 0u;
+override target_3: u32 = 0u;
+
+// Step at which n_input's trajectory first equals the target, 0 if n_input
+// is the target, -1 if it reaches 1, overflows or hits the step cap first
+fn reach_target(n_input: U128) -> i32 {
+    let wanted = U128(array<u32, 4>(target_0, target_1, target_2, target_3));
+    var n = n_input;
+    for (var step = 0u; step < 100000u; step++) {
+        if (equals(n, wanted)) {
+            return i32(step);
+        }
+        if (is_one(n)) {
+            return -1;
+        }
+
+        if (is_even(n)) {
+            n = div_by_2(n);
+        } else {
+            let a = mul_3_add_1(n);
+            if a.carry == 1u {
+                return -1;
+            }
+            n = a.value;
+        }
+    }
+    return -1;
+}
+
+@compute @workgroup_size(workgroup_size)
+fn reaches(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        reach_step[idx] = reach_target(input[idx]);
+    }
+}
+
+// Value `until_exceeds` waits for the trajectory to go above, little-endian
+// limbs like U128. Set by the host for each call.
+override ceiling_0: u32 = 0u;
+override ceiling_1: u32 = 0u;
+override ceiling_2: u32 = 0u;
+override ceiling_3: u32 = 0u;
+
+// Step at which n_input's trajectory first goes above the ceiling, 0 if
+// n_input already is, -1 if it reaches 1 or hits the step cap first. A 3n + 1
+// that overflows is above any ceiling, so it counts as exceeding on that step.
+fn exceed_step(n_input: U128) -> i32 {
+    let ceiling = U128(array<u32, 4>(ceiling_0, ceiling_1, ceiling_2, ceiling_3));
+    var n = n_input;
+    for (var step = 0u; step < 100000u; step++) {
+        if (greater_than(n, ceiling)) {
+            return i32(step);
+        }
+        if (is_one(n)) {
+            return -1;
+        }
+
+        if (is_even(n)) {
+            n = div_by_2(n);
+        } else {
+            let a = mul_3_add_1(n);
+            if a.carry == 1u {
+                return i32(step + 1u);
+            }
+            n = a.value;
+        }
+    }
+    return -1;
+}
+
+// Writes to reach_step like `reaches`, the two aren't used together
+@compute @workgroup_size(workgroup_size)
+fn until_exceeds(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        reach_step[idx] = exceed_step(input[idx]);
+    }
+}
+
+// Largest peak_count the host may ask `top_peaks` for, the size of its
+// sorted buffer
+const MAX_PEAKS = 8u;
+
+// Peaks `top_peaks` keeps per number, 1 to MAX_PEAKS. Set by the host for
+// each call.
+override peak_count: u32 = 3u;
+
+// Record `idx` of peaks: overflowed (1 word) then peak_count U128 peaks,
+// largest first and zero past the last one found. A peak is a value larger
+// than the ones either side of it, n_input counts if larger than the next.
+fn write_top_peaks(idx: u32, n_input: U128) {
+    var best: array<U128, MAX_PEAKS>;
+    var overflowed = 0u;
+    var n = n_input;
+    var rising = true;
+
+    for (var step = 0u; step < 100000u; step++) {
+        if (is_one(n)) {
+            break;
+        }
+
+        var next: U128;
+        if (is_even(n)) {
+            next = div_by_2(n);
+        } else {
+            let a = mul_3_add_1(n);
+            if a.carry == 1u {
+                overflowed = 1u;
+                break;
+            }
+            next = a.value;
+        }
+
+        // insert n into best, which stays sorted largest first, unless it's
+        // too small or already there from going round a cycle
+        if (rising && greater_than(n, next) && greater_than(n, best[peak_count - 1u])) {
+            var seen = false;
+            for (var i = 0u; i < peak_count; i++) {
+                seen = seen || equals(best[i], n);
+            }
+            if (!seen) {
+                var j = peak_count - 1u;
+                while (j > 0u && greater_than(n, best[j - 1u])) {
+                    best[j] = best[j - 1u];
+                    j--;
+                }
+                best[j] = n;
+            }
+        }
+
+        rising = greater_than(next, n);
+        n = next;
+    }
+
+    let base = idx * (1u + 4u * peak_count);
+    peaks[base] = overflowed;
+    for (var i = 0u; i < peak_count; i++) {
+        for (var limb = 0u; limb < 4u; limb++) {
+            peaks[base + 1u + 4u * i + limb] = best[i].parts[limb];
+        }
+    }
+}
+
+@compute @workgroup_size(workgroup_size)
+fn top_peaks(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        write_top_peaks(idx, input[idx]);
+    }
+}
+
+// Modulus `mod_orbit` works in, at most 65536. An orbit can't be longer
+// than modulus, so this keeps every thread's walk short. Set by the host
+// for each call.
+override modulus: u32 = 2u;
+
+// The Collatz map on residues mod modulus: halve even ones, 3x + 1 mod
+// modulus for odd ones
+fn mod_step(x: u32) -> u32 {
+    if ((x & 1u) == 0u) {
+        return x / 2u;
+    }
+    return (3u * x + 1u) % modulus;
+}
+
+// Tail and cycle length of x0's orbit, which always ends in a cycle since
+// there are only modulus residues. Floyd's algorithm, so no visited set.
+fn mod_orbit(x0: u32) -> ModOrbit {
+    var tortoise = mod_step(x0);
+    var hare = mod_step(mod_step(x0));
+    while (tortoise != hare) {
+        tortoise = mod_step(tortoise);
+        hare = mod_step(mod_step(hare));
+    }
+
+    var orbit: ModOrbit;
+    orbit.tail = 0u;
+    tortoise = x0;
+    while (tortoise != hare) {
+        tortoise = mod_step(tortoise);
+        hare = mod_step(hare);
+        orbit.tail++;
+    }
+
+    orbit.cycle_length = 1u;
+    hare = mod_step(tortoise);
+    while (tortoise != hare) {
+        hare = mod_step(hare);
+        orbit.cycle_length++;
+    }
+    return orbit;
+}
+
+// input holds residues below modulus, only their low limb is used
+@compute @workgroup_size(workgroup_size)
+fn mod_orbits(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        orbits[idx] = mod_orbit(input[idx].parts[0]);
+    }
+}
+
+// Steps a number has to go past to be counted by `count_above`. Set by the
+// host for each call.
+override step_threshold: u32 = 0u;
+
+var<workgroup> above_in_group: atomic<u32>;
+
+// How many numbers in each workgroup take more than step_threshold steps,
+// one word per workgroup so the host only reads back the counts. Cycles are
+// never counted, overflowed numbers only if they got past it first.
+@compute @workgroup_size(workgroup_size)
+fn count_above(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        let steps = collatz(input[idx]).steps;
+        if ((steps & (1u << 31u)) == 0u && steps > step_threshold) {
+            atomicAdd(&above_in_group, 1u);
+        }
+    }
+
+    workgroupBarrier();
+    if (local == 0u) {
+        group_counts[group.x] = atomicLoad(&above_in_group);
+    }
+}
+
+var<workgroup> steps_in_group: atomic<u32>;
+
+// Each workgroup's step counts added up, one word per workgroup so the host
+// only reads back the sums. 64 numbers of fewer than 100000 steps each fit
+// in a word. Overflowed and capped numbers add the steps they got through.
+@compute @workgroup_size(workgroup_size)
+fn step_sum(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        let steps = collatz(input[idx]).steps & ~((1u << 31u) | SATURATED_FLAG);
+        atomicAdd(&steps_in_group, steps);
+    }
+
+    workgroupBarrier();
+    if (local == 0u) {
+        group_counts[group.x] = atomicLoad(&steps_in_group);
+    }
+}
+
+// Marks the first number that doesn't reach 1, whether it overflowed, ran
+// out of steps or cycled, so the host reads back one word instead of every
+// result. The key is the number's index inverted, so atomicMax keeps the
+// smallest, and stays 0 if every number converged.
+@compute @workgroup_size(workgroup_size)
+fn first_failure(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx >= arrayLength(&input)) {
+        return;
+    }
+
+    let result = collatz(input[idx]);
+    let converged = (result.steps & ((1u << 31u) | SATURATED_FLAG)) == 0u
+        && result.steps < 100000u
+        && !equals(result.max, ZERO_U128);
+    if (!converged) {
+        atomicMax(&failure_key, 0xffffffffu - idx);
+    }
+}
+
+var<workgroup> growth_in_group: atomic<u32>;
+
+// Which number's peak has the most bits over its start, ties going to the
+// larger n, as one word so the host reads back a single key per dispatch.
+// The key is growth + 1 above the low 17 bits, which hold the index, and 0
+// if every number overflowed. The host's RANGE sized chunks keep the index
+// below 2^17.
+@compute @workgroup_size(workgroup_size)
+fn max_growth(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let idx = id.x;
+    if (idx < arrayLength(&input)) {
+        let start = input[idx];
+        let result = collatz(start);
+        let overflowed = (result.steps & SATURATED_FLAG) != 0u || equals(result.max, ZERO_U128);
+        if (!overflowed) {
+            let growth = bit_length(result.max) - bit_length(start);
+            atomicMax(&growth_in_group, ((growth + 1u) << 17u) | idx);
+        }
+    }
+
+    workgroupBarrier();
+    if (local == 0u) {
+        atomicMax(&growth_key, atomicLoad(&growth_in_group));
+    }
+}
+
+// `champion` keeps one key per segment of 2^CHAMPION_SEGMENT_BITS numbers, so
+// a position in the segment fits next to a step count in 32 bits
+const CHAMPION_SEGMENT_BITS = 15u;
+
+var<workgroup> best_in_group: atomic<u32>;
+
+// Which converged number in each segment takes the most steps, one word per
+// segment so the host only reads back the winners. The key is steps + 1
+// above the low CHAMPION_SEGMENT_BITS bits, which hold the position in the
+// segment inverted so ties go to the smaller n, and 0 if nothing in the
+// segment converged. Steps are below 100000 < 2^17, so both fit.
+@compute @workgroup_size(workgroup_size)
+fn champion(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let idx = id.x;
+    let segment_mask = (1u << CHAMPION_SEGMENT_BITS) - 1u;
+    if (idx < arrayLength(&input)) {
+        let result = collatz(input[idx]);
+        let converged = (result.steps & (1u << 31u)) == 0u
+            && result.steps < 100000u
+            && !equals(result.max, ZERO_U128);
+        if (converged) {
+            let key = ((result.steps + 1u) << CHAMPION_SEGMENT_BITS)
+                | (segment_mask - (idx & segment_mask));
+            atomicMax(&best_in_group, key);
+        }
+    }
+
+    // a workgroup never straddles two segments, WORKGROUP_SIZE divides 2^15
+    workgroupBarrier();
+    if (local == 0u) {
+        atomicMax(&champion_keys[idx >> CHAMPION_SEGMENT_BITS], atomicLoad(&best_in_group));
+    }
+}
+
+// Bin each number's dropping time, the steps until the value is first below
+// the start. With B = arrayLength(&dropping_bins) - 2, bins below B - 1 are
+// single dropping times, bin B - 1 holds every time from B - 1 up, bin B the
+// numbers that never dropped (1, or MAX_STEPS ran out) and bin B + 1 those
+// that overflowed first.
+@compute @workgroup_size(workgroup_size)
+fn dropping_histogram(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx >= arrayLength(&input)) {
+        return;
+    }
+
+    let start = input[idx];
+    let bins = arrayLength(&dropping_bins) - 2u;
+    var n = start;
+    var steps = 0u;
+    var bin = bins;
+    if (greater_than(start, ONE_U128)) {
+        loop {
+            if (steps >= 100000u) {
+                break;
+            }
+            if (is_even(n)) {
+                n = div_by_2(n);
+            } else {
+                let a = mul_3_add_1(n);
+                if (a.carry == 1u) {
+                    bin = bins + 1u;
+                    break;
+                }
+                n = a.value;
+            }
+            steps++;
+            if (greater_than(start, n)) {
+                bin = min(steps, bins - 1u);
+                break;
+            }
+        }
+    }
+    atomicAdd(&dropping_bins[bin], 1u);
+}
+
+// Signed mode: input holds i128s in two's complement and the map runs on all
+// of Z, where every number seen so far ends in one of five cycles: {0},
+// {1, 4, 2} and three negative ones. Negative cycle members by magnitude,
+// -1's cycle first, then -5's and -17's.
+const NEGATIVE_CYCLES = array<u32, 25>(
+    1u, 2u,
+    5u, 14u, 7u, 20u, 10u,
+    17u, 50u, 25u, 74u, 37u, 110u, 55u, 164u, 82u, 41u, 122u, 61u, 182u, 91u,
+    272u, 136u, 68u, 34u,
+);
+const NO_SIGNED_CYCLE: u32 = 0xffffffffu;
+
+// Most negative and most positive n whose 3n + 1 still fits in an i128
+const MIN_SIGNED_TRIPLE = U128(array<u32, 4>(0x55555555u, 0x55555555u, 0x55555555u, 0xd5555555u));
+const MAX_SIGNED_TRIPLE = U128(array<u32, 4>(0xaaaaaaaau, 0xaaaaaaaau, 0xaaaaaaaau, 0x2aaaaaaau));
+
+fn is_negative(n: U128) -> bool {
+    return (n.parts[3] >> 31u) == 1u;
+}
+
+// Which known cycle n is on: 0 for {0}, 1 for {1, 4, 2}, 2, 3 and 4 for the
+// cycles of -1, -5 and -17, NO_SIGNED_CYCLE for anything else
+fn signed_cycle(n: U128) -> u32 {
+    if (n.parts[3] == 0u && n.parts[2] == 0u && n.parts[1] == 0u) {
+        switch n.parts[0] {
+            case 0u: { return 0u; }
+            case 1u, 2u, 4u: { return 1u; }
+            default: { return NO_SIGNED_CYCLE; }
+        }
+    }
+    if (n.parts[3] != 0xffffffffu || n.parts[2] != 0xffffffffu || n.parts[1] != 0xffffffffu) {
+        return NO_SIGNED_CYCLE;
+    }
+    let magnitude = 0u - n.parts[0];
+    for (var i = 0u; i < 25u; i++) {
+        if (NEGATIVE_CYCLES[i] == magnitude) {
+            return select(select(4u, 3u, i < 7u), 2u, i < 2u);
+        }
+    }
+    return NO_SIGNED_CYCLE;
+}
+
+// 3n + 1 mod 2^128, for negative n in two's complement where it always
+// carries out of the top limb
+fn wrapping_mul_3_add_1(n: U128) -> U128 {
+    let doubled = add_u128(n, n).value;
+    let tripled = add_u128(doubled, n).value;
+    return add_u128(tripled, ONE_U128).value;
+}
+
+// Follow a signed n until it lands on a known cycle. Two's complement
+// compares like unsigned between numbers of the same sign, which is all the
+// overflow checks need.
+fn signed_orbit(n_input: U128) -> SignedOrbit {
+    var n = n_input;
+    var orbit: SignedOrbit;
+    orbit.tail = 0u;
+    orbit.overflowed = 0u;
+
+    loop {
+        orbit.cycle = signed_cycle(n);
+        if (orbit.cycle != NO_SIGNED_CYCLE || orbit.tail >= 100000u) {
+            break;
+        }
+
+        let negative = is_negative(n);
+        if (is_even(n)) {
+            // arithmetic shift, exact for even n
+            n = div_by_2(n);
+            if (negative) {
+                n.parts[3] |= 1u << 31u;
+            }
+        } else {
+            if ((negative && greater_than(MIN_SIGNED_TRIPLE, n)) ||
+                (!negative && greater_than(n, MAX_SIGNED_TRIPLE))) {
+                orbit.overflowed = 1u;
+                break;
+            }
+            n = wrapping_mul_3_add_1(n);
+        }
+        orbit.tail++;
+    }
+    return orbit;
+}
+
+@compute @workgroup_size(workgroup_size)
+fn signed_orbits(@builtin(global_invocation_id) id: vec3<u32>) {
     let idx = id.x;
     if (idx < arrayLength(&input)) {
-        output[idx] = collatz(input[idx]);
+        signed_results[idx] = signed_orbit(input[idx]);
     }
 }
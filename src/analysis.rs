@@ -0,0 +1,546 @@
+use wasm_bindgen::prelude::*;
+
+use crate::result::{CollatzResult, Outcome};
+
+/// Counts of values falling into each bucket
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    boundaries: Vec<f64>,
+    counts: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl Histogram {
+    /// Lower edge of each bucket, the last bucket has no upper edge
+    #[wasm_bindgen(getter)]
+    pub fn boundaries(&self) -> Vec<f64> {
+        self.boundaries.clone()
+    }
+
+    /// Number of values in each bucket
+    #[wasm_bindgen(getter)]
+    pub fn counts(&self) -> Vec<u32> {
+        self.counts.clone()
+    }
+}
+
+impl Histogram {
+    // `boundaries` must be ascending and non-empty
+    pub(crate) fn with_boundaries(boundaries: Vec<f64>) -> Self {
+        let counts = vec![0; boundaries.len()];
+        Histogram { boundaries, counts }
+    }
+
+    // Count value in the last bucket whose lower edge is at or below it.
+    // Values below the first edge go in the first bucket.
+    pub(crate) fn add(&mut self, value: f64) {
+        let bucket = self
+            .boundaries
+            .partition_point(|&edge| edge <= value)
+            .saturating_sub(1);
+        self.counts[bucket] += 1;
+    }
+}
+
+// Buckets for max / n ratios: [1, 2), [2, 4), ... with the last one open
+pub(crate) fn ratio_histogram(buckets: u32) -> Histogram {
+    Histogram::with_boundaries((0..buckets as i32).map(|i| 2f64.powi(i)).collect())
+}
+
+// Add the max / n ratio of each result, overflowed and saturated results have
+// no known peak and are left out
+pub(crate) fn add_peak_ratios(histogram: &mut Histogram, results: &[CollatzResult]) {
+    for result in results {
+        if !matches!(result.outcome(), Outcome::Overflowed | Outcome::Saturated) {
+            histogram.add(result.max() as f64 / result.n() as f64);
+        }
+    }
+}
+
+/// The first result that did not converge to 1, if any
+pub fn first_failure(results: &[CollatzResult]) -> Option<&CollatzResult> {
+    results
+        .iter()
+        .find(|result| result.outcome() != Outcome::Converged)
+}
+
+/// The first result that reached 1 in at least `min_steps` steps, if any.
+/// Over an ascending range that is the smallest such n.
+pub fn first_with_steps(results: &[CollatzResult], min_steps: u32) -> Option<&CollatzResult> {
+    results
+        .iter()
+        .find(|result| result.outcome() == Outcome::Converged && result.steps() >= min_steps)
+}
+
+/// Starting numbers of the results that reached 1 in exactly `steps` steps
+pub fn with_steps(results: &[CollatzResult], steps: u32) -> impl Iterator<Item = u128> + '_ {
+    results
+        .iter()
+        .filter(move |result| result.outcome() == Outcome::Converged && result.steps() == steps)
+        .map(|result| result.n())
+}
+
+/// Starting numbers in `results`, taken in order, that reach 1 in more steps
+/// than `best` and every converged result before them. `best` is updated to
+/// the new record, `None` means there is no record yet.
+pub fn delay_records(best: &mut Option<u32>, results: &[CollatzResult]) -> Vec<u128> {
+    let mut records = Vec::new();
+    for result in results {
+        if result.outcome() == Outcome::Converged && best.is_none_or(|best| result.steps() > best) {
+            *best = Some(result.steps());
+            records.push(result.n());
+        }
+    }
+    records
+}
+
+/// Total of every result's step count
+pub fn step_sum(results: &[CollatzResult]) -> u128 {
+    results.iter().map(|result| result.steps() as u128).sum()
+}
+
+/// How many results ended each way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConvergenceSummary {
+    pub converged: u32,
+    pub capped: u32,
+    pub overflowed: u32,
+    pub saturated: u32,
+    pub cycles: u32,
+}
+
+impl ConvergenceSummary {
+    pub fn add(&mut self, results: &[CollatzResult]) {
+        for result in results {
+            match result.outcome() {
+                Outcome::Converged => self.converged += 1,
+                Outcome::Capped => self.capped += 1,
+                Outcome::Overflowed => self.overflowed += 1,
+                Outcome::Saturated => self.saturated += 1,
+                Outcome::Cycle => self.cycles += 1,
+            }
+        }
+    }
+}
+
+/// Estimated cost of computing each result, for handing out equal-cost
+/// rather than equal-count assignments.
+///
+/// One unit per iteration of the shader loop plus one for the number's share
+/// of the upload and readback, so it only ever grows with the step count.
+pub fn work_units(results: &[CollatzResult]) -> Vec<u64> {
+    results
+        .iter()
+        .map(|result| result.steps() as u64 + 1)
+        .collect()
+}
+
+// Most significant decimal digit of n, n must not be 0
+fn leading_digit(mut n: u128) -> usize {
+    while n >= 10 {
+        n /= 10;
+    }
+    n as usize
+}
+
+/// Add how many peaks start with each decimal digit, `counts[0]` is for 1
+/// and `counts[8]` for 9. Overflowed and saturated results have no known
+/// peak and are skipped.
+pub fn add_leading_digits(counts: &mut [u32; 9], results: &[CollatzResult]) {
+    for result in results {
+        if !matches!(result.outcome(), Outcome::Overflowed | Outcome::Saturated) {
+            counts[leading_digit(result.max()) - 1] += 1;
+        }
+    }
+}
+
+/// Keep whichever has the largest `bit_growth` out of `best` and `results`,
+/// ties going to the larger n. Overflowed and saturated results are skipped.
+pub fn max_growth(best: Option<CollatzResult>, results: &[CollatzResult]) -> Option<CollatzResult> {
+    results
+        .iter()
+        .filter(|result| !matches!(result.outcome(), Outcome::Overflowed | Outcome::Saturated))
+        .copied()
+        .chain(best)
+        .max_by_key(|result| (result.bit_growth(), result.n()))
+}
+
+/// Keep whichever converged result takes the most steps out of `best` and
+/// `results`, ties going to the smaller n
+pub fn most_steps(best: Option<CollatzResult>, results: &[CollatzResult]) -> Option<CollatzResult> {
+    best.into_iter()
+        .chain(
+            results
+                .iter()
+                .filter(|result| result.outcome() == Outcome::Converged)
+                .copied(),
+        )
+        .max_by_key(|result| (result.steps(), std::cmp::Reverse(result.n())))
+}
+
+/// Indices where two workers' results for the same range differ, empty if
+/// they agree on everything.
+///
+/// Results are compared on everything but `source`, and on the extra fields
+/// only when both are full records. If one side has more results, every
+/// index past the end of the shorter one is a disagreement.
+pub fn results_agree(a: &[CollatzResult], b: &[CollatzResult]) -> Vec<usize> {
+    let same = |x: &CollatzResult, y: &CollatzResult| {
+        if (x.n(), x.steps(), x.max(), x.outcome()) != (y.n(), y.steps(), y.max(), y.outcome()) {
+            return false;
+        }
+        if !(x.is_full_record() && y.is_full_record()) {
+            return true;
+        }
+        (
+            x.steps_shortcut(),
+            x.height_step(),
+            x.height(),
+            x.steps_to_power_of_two(),
+            x.max_even_run(),
+            x.max_odd_run(),
+            x.trajectory_sum(),
+        ) == (
+            y.steps_shortcut(),
+            y.height_step(),
+            y.height(),
+            y.steps_to_power_of_two(),
+            y.max_even_run(),
+            y.max_odd_run(),
+            y.trajectory_sum(),
+        )
+    };
+    let mut differing: Vec<usize> = a
+        .iter()
+        .zip(b)
+        .enumerate()
+        .filter(|(_, (x, y))| !same(x, y))
+        .map(|(i, _)| i)
+        .collect();
+    differing.extend(a.len().min(b.len())..a.len().max(b.len()));
+    differing
+}
+
+/// Indices of results that can't be right whatever the trajectory was: a
+/// peak below the start, or 0 steps for a start above 1. These can only come
+/// from a corrupted readback or a kernel bug, so this is a cheap check that
+/// doesn't recompute anything. Overflowed results have no peak and can
+/// overflow on the very first step, so they are never flagged.
+pub fn inconsistent_results(results: &[CollatzResult]) -> Vec<usize> {
+    results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| {
+            result.outcome() != Outcome::Overflowed
+                && (result.max() < result.n() || (result.steps() == 0 && result.n() > 1))
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Parallel `(n, steps)` columns for plotting. n is converted to the nearest
+/// f64, so values above 2^53 lose their low bits.
+pub fn scatter_points(results: &[CollatzResult]) -> (Vec<f64>, Vec<u32>) {
+    results
+        .iter()
+        .map(|result| (result.n() as f64, result.steps()))
+        .unzip()
+}
+
+/// Every result's step count as an f64, which holds any u32 exactly
+pub fn steps_f64(results: &[CollatzResult]) -> impl Iterator<Item = f64> + '_ {
+    results.iter().map(|result| result.steps() as f64)
+}
+
+/// Population variance of the step counts in the window of `window` counts
+/// starting at each index, one per count. The last `window - 1` windows run
+/// off the end and only cover the counts that are left. `window` must be at
+/// least 1.
+pub fn window_variance(steps: &[u32], window: usize) -> Vec<f64> {
+    // running sums over steps[i..end], exact in integers so a long range
+    // doesn't pile up rounding error
+    let (mut sum, mut squares, mut end) = (0u64, 0u128, 0);
+    let mut variances = Vec::with_capacity(steps.len());
+    for i in 0..steps.len() {
+        while end < steps.len() && end < i.saturating_add(window) {
+            sum += steps[end] as u64;
+            squares += steps[end] as u128 * steps[end] as u128;
+            end += 1;
+        }
+        let len = (end - i) as u128;
+        // len^2 times the variance, which can't be negative
+        let scaled = squares * len - sum as u128 * sum as u128;
+        variances.push(scaled as f64 / (len * len) as f64);
+        sum -= steps[i] as u64;
+        squares -= steps[i] as u128 * steps[i] as u128;
+    }
+    variances
+}
+
+/// One RGBA pixel per step count, on a black -> red -> yellow -> white scale
+/// from the smallest to the largest count in `steps`. Every pixel is black
+/// if they're all the same.
+pub fn heatmap_rgba(steps: &[u32]) -> Vec<u8> {
+    let min = steps.iter().copied().min().unwrap_or(0);
+    let max = steps.iter().copied().max().unwrap_or(0);
+    let span = (max - min).max(1) as f64;
+
+    let channel = |t: f64| (t.clamp(0.0, 1.0) * 255.0).round() as u8;
+    steps
+        .iter()
+        .flat_map(|&count| {
+            let t = 3.0 * (count - min) as f64 / span;
+            [channel(t), channel(t - 1.0), channel(t - 2.0), 255]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::walk;
+    use crate::cpu_collatz;
+    use crate::result::RESULT_WORDS;
+
+    #[test]
+    fn ratio_buckets_match_brute_force() {
+        let results: Vec<_> = (1..3000).map(cpu_collatz).collect();
+        let mut histogram = ratio_histogram(8);
+        add_peak_ratios(&mut histogram, &results);
+        let mut want = vec![0; 8];
+        for n in 1..3000u128 {
+            let max = *walk(n).iter().max().unwrap();
+            let mut bucket = 0;
+            while bucket < 7 && max >= n << (bucket + 1) {
+                bucket += 1;
+            }
+            want[bucket] += 1;
+        }
+        assert_eq!(histogram.counts(), want);
+    }
+
+    #[test]
+    fn convergence_summary_counts_markers() {
+        let mut results: Vec<_> = (1..1000).map(cpu_collatz).collect();
+        let mut summary = ConvergenceSummary::default();
+        summary.add(&results);
+        assert_eq!(
+            summary,
+            ConvergenceSummary {
+                converged: 999,
+                ..Default::default()
+            }
+        );
+
+        // records as the shader writes them: the cycle flag, the saturated
+        // flag, an overflow (max 0) and a run out of steps
+        let mut words = [0; RESULT_WORDS];
+        for (i, steps) in [1 << 31, 1 << 30, 0, crate::MAX_STEPS]
+            .into_iter()
+            .enumerate()
+        {
+            words[0] = steps;
+            words[1] = if i == 2 { 0 } else { 7 };
+            results[i] = CollatzResult::from_gpu_words(7, &words);
+        }
+        let mut summary = ConvergenceSummary::default();
+        summary.add(&results);
+        let counts = (summary.converged, summary.cycles, summary.saturated);
+        assert_eq!(counts, (995, 1, 1));
+        assert_eq!((summary.overflowed, summary.capped), (1, 1));
+    }
+
+    #[test]
+    fn work_units_grow_with_steps() {
+        let results: Vec<_> = (1..3000).chain([u128::MAX]).map(cpu_collatz).collect();
+        let units = work_units(&results);
+        assert_eq!(units.len(), results.len());
+        for (a, &units_a) in results.iter().zip(&units) {
+            for (b, &units_b) in results.iter().zip(&units).step_by(7) {
+                if a.steps() < b.steps() {
+                    assert!(units_a < units_b, "{} {}", a.n(), b.n());
+                }
+            }
+        }
+        assert_eq!(units[0], 1);
+    }
+
+    #[test]
+    fn leading_digits_hand_counted() {
+        // peaks of 1..=10 are 1, 2, 16, 4, 16, 16, 52, 8, 52, 16, and
+        // u128::MAX overflows so has none
+        let results: Vec<_> = (1..=10).chain([u128::MAX]).map(cpu_collatz).collect();
+        let mut counts = [0; 9];
+        add_leading_digits(&mut counts, &results);
+        assert_eq!(counts, [5, 1, 0, 1, 2, 0, 0, 1, 0]);
+        add_leading_digits(&mut counts, &results[6..7]);
+        assert_eq!(counts[4], 3);
+    }
+
+    #[test]
+    fn max_growth_matches_host_scan() {
+        let bits = |n: u128| u128::BITS - n.leading_zeros();
+        let results: Vec<_> = (1..2000).map(cpu_collatz).collect();
+        let best = max_growth(None, &results[..700]);
+        let best = max_growth(best, &results[700..]).unwrap();
+        let mut want = (0, 0);
+        for n in 1..2000 {
+            let growth = bits(*walk(n).iter().max().unwrap()) - bits(n);
+            want = want.max((growth, n));
+        }
+        assert_eq!((best.bit_growth(), best.n()), want);
+    }
+
+    #[test]
+    fn exact_step_counts_in_small_range() {
+        let results: Vec<_> = (1..200).chain([u128::MAX]).map(cpu_collatz).collect();
+        assert_eq!(
+            with_steps(&results, 7).collect::<Vec<_>>(),
+            [3, 20, 21, 128]
+        );
+        assert_eq!(with_steps(&results, 0).collect::<Vec<_>>(), [1]);
+        // u128::MAX overflows after 0 steps, so it doesn't count
+        assert_eq!(results.last().unwrap().steps(), 0);
+    }
+
+    #[test]
+    fn results_agree_cases() {
+        let a: Vec<_> = (1..50).map(cpu_collatz).collect();
+        let b = a.clone();
+        assert!(results_agree(&a, &b).is_empty());
+        let mut c = b.clone();
+        c[3] = cpu_collatz(5);
+        c[10] = cpu_collatz(99);
+        assert_eq!(results_agree(&a, &c), [3, 10]);
+        assert_eq!(results_agree(&a, &b[..45]), [45, 46, 47, 48]);
+        assert_eq!(results_agree(&a[..47], &b), [47, 48]);
+    }
+
+    #[test]
+    fn heatmap_tracks_steps() {
+        // a 6 by 6 image
+        let steps: Vec<u32> = (1..=36).map(|n| cpu_collatz(n).steps()).collect();
+        let pixels = heatmap_rgba(&steps);
+        assert_eq!(pixels.len(), 6 * 6 * 4);
+        let brightness = |i: usize| {
+            pixels[i * 4..i * 4 + 3]
+                .iter()
+                .map(|&c| c as u32)
+                .sum::<u32>()
+        };
+        for i in 0..36 {
+            for j in 0..36 {
+                if steps[i] < steps[j] {
+                    assert!(brightness(i) < brightness(j), "{i} {j}");
+                }
+            }
+        }
+        // 1 takes the fewest steps and 27 the most
+        assert_eq!(pixels[..4], [0, 0, 0, 255]);
+        assert_eq!(pixels[26 * 4..27 * 4], [255, 255, 255, 255]);
+        assert_eq!(heatmap_rgba(&[5, 5]), [0, 0, 0, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn scatter_columns_line_up() {
+        let results: Vec<_> = (1..1000).chain([(1 << 60) + 1]).map(cpu_collatz).collect();
+        let (xs, ys) = scatter_points(&results);
+        assert_eq!((xs.len(), ys.len()), (results.len(), results.len()));
+        assert_eq!((xs[26], ys[26]), (27.0, 111));
+        // 2^60 + 1 rounds to the nearest f64
+        assert_eq!(xs[999], (1u64 << 60) as f64);
+        assert!(ys
+            .iter()
+            .zip(&results)
+            .all(|(&y, result)| y == result.steps()));
+    }
+
+    #[test]
+    fn delay_records_from_one() {
+        let results: Vec<_> = (1..1000).map(cpu_collatz).collect();
+        let mut best = None;
+        let first = delay_records(&mut best, &results[..500]);
+        let more = delay_records(&mut best, &results[500..]);
+        let want = [
+            1, 2, 3, 6, 7, 9, 18, 25, 27, 54, 73, 97, 129, 171, 231, 313, 327,
+        ];
+        assert_eq!(first, want);
+        assert_eq!(more, [649, 703, 871]);
+        assert_eq!(best, Some(178));
+        let mut after_27 = Some(111);
+        assert_eq!(delay_records(&mut after_27, &results[27..77]), [54, 73]);
+    }
+
+    #[test]
+    fn inconsistent_results_flags_corruption() {
+        let mut results: Vec<_> = (1..2000).chain([u128::MAX]).map(cpu_collatz).collect();
+        assert!(inconsistent_results(&results).is_empty());
+        // a peak of 10 below the start of 100
+        let mut words = [0; RESULT_WORDS];
+        (words[0], words[1]) = (5, 10);
+        results[99] = CollatzResult::from_gpu_words(100, &words);
+        // 0 steps for 7
+        let mut words = [0; RESULT_WORDS];
+        words[1] = 7;
+        results[6] = CollatzResult::from_gpu_words(7, &words);
+        assert_eq!(inconsistent_results(&results), [6, 99]);
+    }
+
+    #[test]
+    fn steps_f64_round_trip() {
+        let results: Vec<_> = (1..5000)
+            .chain([u128::MAX, 1 << 100])
+            .map(cpu_collatz)
+            .collect();
+        let floats: Vec<f64> = steps_f64(&results).collect();
+        assert_eq!(floats.len(), results.len());
+        for (&float, result) in floats.iter().zip(&results) {
+            assert_eq!((float as u32, float.fract()), (result.steps(), 0.0));
+        }
+        assert_eq!(floats[26], 111.0);
+    }
+
+    #[test]
+    fn window_variance_matches_direct() {
+        let steps: Vec<u32> = (1..=500).map(|n| cpu_collatz(n).steps()).collect();
+        for window in [1, 2, 7, 50, 500, 10_000, usize::MAX] {
+            let variances = window_variance(&steps, window);
+            assert_eq!(variances.len(), steps.len());
+            for (i, &variance) in variances.iter().enumerate() {
+                let counts = &steps[i..steps.len().min(i.saturating_add(window))];
+                let len = counts.len() as f64;
+                let mean = counts.iter().map(|&x| x as f64).sum::<f64>() / len;
+                let want = counts
+                    .iter()
+                    .map(|&x| (x as f64 - mean).powi(2))
+                    .sum::<f64>()
+                    / len;
+                assert!(
+                    (variance - want).abs() <= 1e-9 * want.max(1.0),
+                    "window {window} at {i}"
+                );
+            }
+        }
+        assert!(window_variance(&steps, 1)
+            .iter()
+            .all(|&variance| variance == 0.0));
+        assert!(window_variance(&[], 3).is_empty());
+        assert_eq!(window_variance(&[1, 3, 5], 2), [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn first_with_steps_in_order() {
+        let results: Vec<_> = (1..100).chain([u128::MAX]).map(cpu_collatz).collect();
+        assert_eq!(
+            first_with_steps(&results, 111).map(|result| result.n()),
+            Some(27)
+        );
+        assert_eq!(
+            first_with_steps(&results, 0).map(|result| result.n()),
+            Some(1)
+        );
+        // u128::MAX overflows, so its step count doesn't count
+        assert!(first_with_steps(&results, 1000).is_none());
+    }
+}
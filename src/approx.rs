@@ -0,0 +1,122 @@
+use crate::error::CollatzError;
+use crate::gpu::{buffer_size, create_approx_pipeline, max_records_per_dispatch, submit};
+use crate::result::DecodeError;
+use crate::session::CollatzSession;
+
+// A start number of any size, reduced to what approx.wgsl needs
+pub(crate) struct ApproxStart {
+    low: u64,
+    value: f64,
+}
+
+impl ApproxStart {
+    // Parse a decimal start of any length. Its low 64 bits are kept exactly,
+    // the rest only as an f64, so starts past f64::MAX are rejected.
+    pub(crate) fn parse(start_n: &str) -> Result<Self, CollatzError> {
+        let digits = start_n.trim();
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CollatzError::InvalidNumber);
+        }
+        let low = digits.bytes().fold(0u64, |low, digit| {
+            low.wrapping_mul(10).wrapping_add((digit - b'0') as u64)
+        });
+        let value: f64 = digits.parse().map_err(|_| CollatzError::InvalidNumber)?;
+        if value.is_infinite() {
+            return Err(CollatzError::InvalidArgument(
+                "start_n overflows an f64, it must be below about 1.8e308",
+            ));
+        }
+        if value == 0.0 {
+            return Err(CollatzError::InvalidArgument("start_n must be at least 1"));
+        }
+        Ok(ApproxStart { low, value })
+    }
+
+    // The 16 byte `ApproxInput` for start + offset
+    fn input_bytes(&self, offset: u64) -> [u8; 16] {
+        let low = self.low.wrapping_add(offset);
+        let log2 = (self.value + offset as f64).log2() as f32;
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&low.to_le_bytes());
+        bytes[8..12].copy_from_slice(&log2.to_le_bytes());
+        bytes
+    }
+}
+
+// Estimated steps for each of `count` numbers from `start`, see approx.wgsl
+pub(crate) async fn approx_steps(
+    session: &CollatzSession,
+    start: &ApproxStart,
+    count: u64,
+) -> Result<Vec<f32>, CollatzError> {
+    let device = &session.device;
+    let pipeline = create_approx_pipeline(device);
+    let chunk_size = max_records_per_dispatch(device, 4).min(crate::RANGE as u64);
+
+    let mut estimates = Vec::with_capacity(count as usize);
+    let mut offset = 0;
+    while offset < count {
+        let len = (count - offset).min(chunk_size);
+        let input: Vec<u8> = (offset..offset + len)
+            .flat_map(|i| start.input_bytes(i))
+            .collect();
+
+        let output_size = buffer_size(len as usize, 4)?;
+        let words = submit(device, &session.queue, &pipeline, &input, 1, output_size)
+            .read(device)
+            .await?;
+        if words.len() != len as usize {
+            return Err(DecodeError::WrongLength {
+                expected: output_size as usize,
+                actual: words.len() * 4,
+            }
+            .into());
+        }
+
+        estimates.extend(words.iter().map(|&word| f32::from_bits(word)));
+        offset += len;
+    }
+
+    Ok(estimates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_collatz;
+    use crate::result::Outcome;
+
+    #[test]
+    fn approx_close_to_exact() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        // small starts are walked exactly
+        let start = ApproxStart::parse("1").unwrap();
+        let estimates = pollster::block_on(approx_steps(&session, &start, 10_000)).unwrap();
+        for (i, &estimate) in estimates.iter().enumerate() {
+            let n = i as u128 + 1;
+            assert_eq!(estimate, cpu_collatz(n).steps() as f32, "n {n}");
+        }
+
+        for base in [1 << 70, 1 << 100, u128::MAX / 3 - 20_000] {
+            let start = ApproxStart::parse(&base.to_string()).unwrap();
+            let estimates = pollster::block_on(approx_steps(&session, &start, 10_000)).unwrap();
+            let (mut error, mut converged) = (0.0, 0);
+            for (i, &estimate) in estimates.iter().enumerate() {
+                let exact = cpu_collatz(base + i as u128);
+                if exact.outcome() == Outcome::Converged {
+                    let steps = exact.steps() as f64;
+                    error += (estimate as f64 - steps).abs() / steps;
+                    converged += 1;
+                }
+            }
+            assert!(error / (converged as f64) < 0.25, "base {base}");
+        }
+
+        let huge = ApproxStart::parse(&format!("1{}", "0".repeat(300))).unwrap();
+        let estimates = pollster::block_on(approx_steps(&session, &huge, 4)).unwrap();
+        assert!(estimates.iter().all(|e| e.is_finite() && *e > 5000.0));
+        assert!(ApproxStart::parse(&format!("1{}", "0".repeat(310))).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+// Approximate step counts for numbers too large for add.wgsl, for ranking
+// candidates only. WGSL has no f64, so the host works out log2 n in f64 and
+// the shader carries it on in f32 alongside the exact low 64 bits of n.
+//
+// The low bits decide parity for as long as they are known. 3n + 1 keeps
+// every known bit known, each halving of a value larger than the known bits
+// loses the top one. Once the value fits below the known bits it is exact
+// again, and once no bits are known the rest of the trajectory is estimated
+// as the average for a value of that size.
+
+struct ApproxInput {
+    low: vec2<u32>,
+    log2_n: f32,
+    _pad: u32,
+}
+
+@group(0) @binding(0) var<storage, read> input: array<ApproxInput>;
+@group(0) @binding(1) var<storage, read_write> estimate: array<f32>;
+
+override workgroup_size: u32 = 64;
+
+const LOG2_3: f32 = 1.5849625;
+// Average classic steps per bit for a random trajectory, 3 / log2(4 / 3):
+// half the shortcut steps multiply by 3 / 2 and half by 1 / 2, and each odd
+// one is two classic steps
+const STEPS_PER_BIT: f32 = 7.2282977;
+// Gives up on an exact trajectory that runs this long, add.wgsl's MAX_STEPS
+const MAX_STEPS: u32 = 100000u;
+
+fn log2_of(low: vec2<u32>) -> f32 {
+    return log2(f32(low.y) * 4294967296.0 + f32(low.x));
+}
+
+// The low `known` bits of low, the rest cleared
+fn keep_known(low: vec2<u32>, known: u32) -> vec2<u32> {
+    if known >= 64u {
+        return low;
+    }
+    if known >= 32u {
+        return vec2(low.x, low.y & ((1u << (known - 32u)) - 1u));
+    }
+    return vec2(low.x & ((1u << known) - 1u), 0u);
+}
+
+// x + y and whether it carried
+fn add_carry(x: u32, y: u32) -> vec2<u32> {
+    let sum = x + y;
+    return vec2(sum, select(0u, 1u, sum < x));
+}
+
+// 3n + 1 on the low 64 bits, with the carry out of them in z
+fn triple_plus_one(low: vec2<u32>) -> vec3<u32> {
+    let x = add_carry(low.x, low.x << 1u);
+    let x1 = add_carry(x.x, 1u);
+    let y = add_carry(low.y, low.y << 1u);
+    let y1 = add_carry(y.x, (low.x >> 31u) + x.y + x1.y);
+    return vec3(x1.x, y1.x, (low.y >> 31u) + y.y + y1.y);
+}
+
+@compute @workgroup_size(workgroup_size)
+fn approx(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= arrayLength(&input) {
+        return;
+    }
+
+    var low = input[index].low;
+    var bits = input[index].log2_n;
+    var known = 64u;
+    var steps = 0u;
+    loop {
+        // a bit of slack so f32 rounding in `bits` can't claim a value fits
+        let exact = bits < f32(known) - 1.0;
+        if exact && low.x == 1u && low.y == 0u {
+            estimate[index] = f32(steps);
+            return;
+        }
+        if known == 0u || steps >= MAX_STEPS {
+            break;
+        }
+
+        if (low.x & 1u) == 1u {
+            let next = triple_plus_one(low);
+            low = next.xy;
+            if exact && next.z == 0u {
+                bits = log2_of(low);
+            } else {
+                bits = bits + LOG2_3;
+            }
+        } else {
+            low = vec2((low.x >> 1u) | (low.y << 31u), low.y >> 1u);
+            if exact {
+                bits = log2_of(low);
+            } else {
+                known = known - 1u;
+                bits = bits - 1.0;
+            }
+        }
+        low = keep_known(low, known);
+        steps = steps + 1u;
+    }
+
+    estimate[index] = f32(steps) + STEPS_PER_BIT * max(bits, 0.0);
+}
@@ -0,0 +1,200 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{InputLayout, ReadbackStrategy};
+use crate::progress::ByteReader;
+use crate::result::{CollatzResult, StepConvention, ARCHIVE_RECORD_SIZE};
+use crate::scan::RunConfig;
+
+// Start of every exported sweep, then the format version
+const SWEEP_MAGIC: &[u8; 4] = b"CLZR";
+const SWEEP_VERSION: u8 = 6;
+
+const BAD_SWEEP: CollatzError = CollatzError::InvalidArgument("not an exported sweep");
+
+/// A sweep read back by `import_sweep`: the config it ran with and every
+/// result, in the order they were exported
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepArchive {
+    config: RunConfig,
+    results: Vec<CollatzResult>,
+}
+
+#[wasm_bindgen]
+impl SweepArchive {
+    #[wasm_bindgen(getter)]
+    pub fn config(&self) -> RunConfig {
+        self.config
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn results(&self) -> Vec<CollatzResult> {
+        self.results.clone()
+    }
+}
+
+impl SweepArchive {
+    pub fn into_parts(self) -> (RunConfig, Vec<CollatzResult>) {
+        (self.config, self.results)
+    }
+}
+
+/// The config a sweep ran with and its results as one self-describing
+/// blob that `import_sweep` reads back without any other metadata.
+///
+/// "CLZR", version (1 byte), then the config: chunk_size (4 bytes),
+/// max_in_flight (4), descending, step_convention, input_layout,
+/// lookup_table, saturate_overflow and full_record (1 each), throttle_ms
+/// (4). Then the result count (8 bytes) and that many 91 byte records, each
+/// holding every field of a result: n (16), steps (4), max (16), outcome
+/// (1: converged, overflowed, capped, cycle, saturated), height_step (4),
+/// height (16), steps_shortcut (4), source (1: gpu, cpu, table, derived),
+/// steps_to_power_of_two (4, u32::MAX for none), max_even_run (4),
+/// max_odd_run (4), trajectory_sum (16) and whether the result is a full
+/// record (1), with the optional fields 0 if it isn't. Everything
+/// little-endian.
+pub fn export_sweep(config: &RunConfig, results: &[CollatzResult]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(31 + results.len() * ARCHIVE_RECORD_SIZE);
+    bytes.extend_from_slice(SWEEP_MAGIC);
+    bytes.push(SWEEP_VERSION);
+
+    bytes.extend_from_slice(&config.chunk_size.to_le_bytes());
+    bytes.extend_from_slice(&config.max_in_flight.to_le_bytes());
+    bytes.push(config.descending as u8);
+    bytes.push(config.step_convention as u8);
+    bytes.push(config.input_layout as u8);
+    bytes.push(config.lookup_table as u8);
+    bytes.push(config.saturate_overflow as u8);
+    bytes.push(config.full_record as u8);
+    bytes.extend_from_slice(&config.throttle_ms.to_le_bytes());
+
+    bytes.extend_from_slice(&(results.len() as u64).to_le_bytes());
+    for result in results {
+        bytes.extend_from_slice(&result.archive_bytes());
+    }
+    bytes
+}
+
+/// Read back what `export_sweep` wrote
+pub fn import_sweep(bytes: &[u8]) -> Result<SweepArchive, CollatzError> {
+    let mut reader = ByteReader(bytes);
+    if reader.take::<4>()? != *SWEEP_MAGIC {
+        return Err(BAD_SWEEP);
+    }
+    if reader.take::<1>()?[0] != SWEEP_VERSION {
+        return Err(CollatzError::InvalidArgument(
+            "sweep was exported in an unknown format version",
+        ));
+    }
+
+    let flag = |byte: u8| match byte {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(BAD_SWEEP),
+    };
+    let chunk_size = u32::from_le_bytes(reader.take()?);
+    let max_in_flight = u32::from_le_bytes(reader.take()?);
+    let [descending, convention, layout, lookup_table, saturate_overflow, full_record] =
+        reader.take()?;
+    let config = RunConfig {
+        chunk_size,
+        max_in_flight,
+        descending: flag(descending)?,
+        step_convention: match convention {
+            0 => StepConvention::UntilOne,
+            1 => StepConvention::ThroughTrivialCycle,
+            _ => return Err(BAD_SWEEP),
+        },
+        input_layout: match layout {
+            0 => InputLayout::ArrayOfStructs,
+            1 => InputLayout::StructOfArrays,
+            2 => InputLayout::PackedU32,
+            _ => return Err(BAD_SWEEP),
+        },
+        lookup_table: flag(lookup_table)?,
+        saturate_overflow: flag(saturate_overflow)?,
+        full_record: flag(full_record)?,
+        throttle_ms: u32::from_le_bytes(reader.take()?),
+        // neither changes any result, so they aren't archived
+        readback_strategy: ReadbackStrategy::Auto,
+        numbers_per_thread: 1,
+    };
+
+    // check the count against what's there before allocating for it
+    let count = u64::from_le_bytes(reader.take()?);
+    if reader.0.len() as u64 != count.saturating_mul(ARCHIVE_RECORD_SIZE as u64) {
+        return Err(BAD_SWEEP);
+    }
+    let results = reader
+        .0
+        .chunks_exact(ARCHIVE_RECORD_SIZE)
+        .map(|record| {
+            CollatzResult::from_archive_bytes(record.try_into().unwrap()).ok_or(BAD_SWEEP)
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(SweepArchive { config, results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::{Outcome, ResultSource, FULL_RESULT_WORDS, RESULT_WORDS};
+
+    #[test]
+    fn sweep_round_trips() {
+        let mut results: Vec<_> = (1..3000)
+            .chain([(1 << 127) + 1, u128::MAX])
+            .map(crate::cpu_collatz)
+            .collect();
+        let mut full = [0; FULL_RESULT_WORDS];
+        (full[0], full[1], full[10]) = (111, 9232, 70);
+        results.push(CollatzResult::from_gpu_words(27, &full));
+        let mut short = [0; RESULT_WORDS];
+        (short[0], short[1], short[5]) = (111, 9232, 1);
+        results.push(CollatzResult::from_gpu_words(27, &short));
+        let config = RunConfig {
+            chunk_size: 1234,
+            max_in_flight: 5,
+            descending: true,
+            step_convention: StepConvention::ThroughTrivialCycle,
+            input_layout: InputLayout::StructOfArrays,
+            lookup_table: true,
+            saturate_overflow: true,
+            throttle_ms: 9,
+            readback_strategy: ReadbackStrategy::Auto,
+            numbers_per_thread: 1,
+            full_record: true,
+        };
+
+        let bytes = export_sweep(&config, &results);
+        assert_eq!(bytes.len(), 31 + results.len() * ARCHIVE_RECORD_SIZE);
+        let (got_config, got) = import_sweep(&bytes).unwrap().into_parts();
+        assert_eq!(got_config, config);
+        assert_eq!(got, results);
+        assert_eq!(got[0].source(), ResultSource::Cpu);
+        assert_eq!(got.last().unwrap().source(), ResultSource::Gpu);
+        assert!(got[got.len() - 2].is_full_record() && !got.last().unwrap().is_full_record());
+        assert!(got
+            .iter()
+            .any(|result| result.outcome() == Outcome::Overflowed));
+
+        assert!(import_sweep(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 9;
+        assert!(import_sweep(&bad_version).is_err());
+        // the first record's outcome byte
+        let mut bad_outcome = bytes.clone();
+        bad_outcome[31 + 36] = 7;
+        assert!(import_sweep(&bad_outcome).is_err());
+        // and its full record flag
+        let mut bad_flag = bytes;
+        bad_flag[31 + ARCHIVE_RECORD_SIZE - 1] = 2;
+        assert!(import_sweep(&bad_flag).is_err());
+
+        let empty = export_sweep(&RunConfig::DEFAULT, &[]);
+        let (config, results) = import_sweep(&empty).unwrap().into_parts();
+        assert_eq!((config, results), (RunConfig::DEFAULT, vec![]));
+    }
+}
@@ -0,0 +1,547 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::error::CollatzError;
+use crate::result::{CollatzResult, Outcome, StepConvention, MAX_STEPS};
+
+fn next(n: u128) -> Option<u128> {
+    if n.is_multiple_of(2) {
+        Some(n / 2)
+    } else {
+        n.checked_mul(3)?.checked_add(1)
+    }
+}
+
+// Every value from n (at least 1) down to 1, or up to the last one before
+// the walk overflows. The reference the tests check results against, with
+// none of the bookkeeping `cpu_collatz` does.
+#[cfg(test)]
+pub(crate) fn walk(n: u128) -> Vec<u128> {
+    let mut values = vec![n];
+    while let Some(value) = values
+        .last()
+        .filter(|&&value| value != 1)
+        .and_then(|&v| next(v))
+    {
+        values.push(value);
+    }
+    values
+}
+
+fn bit_length(n: u128) -> u32 {
+    128 - n.leading_zeros()
+}
+
+// Longest runs of halvings and of shortcut odd steps so far and the ones in
+// progress, tracked the same way as `Runs` in add.wgsl
+#[derive(Default)]
+struct Runs {
+    max_even: u32,
+    max_odd: u32,
+    even: u32,
+    odd: u32,
+}
+
+impl Runs {
+    fn step(&mut self, halving: bool) {
+        if halving {
+            self.even += 1;
+            self.max_even = self.max_even.max(self.even);
+        } else {
+            self.odd = if self.odd > 0 && self.even == 1 {
+                self.odd + 1
+            } else {
+                1
+            };
+            self.max_odd = self.max_odd.max(self.odd);
+            self.even = 0;
+        }
+    }
+}
+
+/// The result for n computed on the host step for step the way `collatz`
+/// in add.wgsl does it, including its cycle check and `MAX_STEPS` cap, so
+/// it can stand in for a GPU result.
+pub fn cpu_collatz(n: u128) -> CollatzResult {
+    let mut value = n;
+    let mut steps = 0;
+    let mut halvings = 0;
+    let mut max = n;
+    let mut sum = n;
+    let mut tortoise = n;
+
+    let mut height_bits = bit_length(n);
+    let mut height = n;
+    let mut height_step = 0;
+    let mut power_step = n.is_power_of_two().then_some(0);
+    let mut runs = Runs::default();
+
+    let outcome = loop {
+        if value == 1 {
+            break Outcome::Converged;
+        }
+        if steps >= MAX_STEPS {
+            break Outcome::Capped;
+        }
+
+        let halving = value.is_multiple_of(2);
+        if halving {
+            halvings += 1;
+        }
+        value = match next(value) {
+            Some(value) => value,
+            None => {
+                return CollatzResult::from_cpu(
+                    n,
+                    steps,
+                    0,
+                    Outcome::Overflowed,
+                    height_step,
+                    height,
+                    halvings,
+                    None,
+                    (runs.max_even, runs.max_odd),
+                    sum,
+                )
+            }
+        };
+        runs.step(halving);
+        max = max.max(value);
+        sum = sum.saturating_add(value);
+        steps += 1;
+
+        if bit_length(value) > height_bits {
+            height_bits = bit_length(value);
+            height = value;
+            height_step = steps;
+        }
+        if power_step.is_none() && value.is_power_of_two() {
+            power_step = Some(steps);
+        }
+
+        if steps.is_multiple_of(2) {
+            // never overflows, the hare already got through every value it visits
+            if let Some(next_tortoise) = next(tortoise) {
+                tortoise = next_tortoise;
+            }
+            if value == tortoise && steps > 2 {
+                break Outcome::Cycle;
+            }
+        }
+    };
+
+    CollatzResult::from_cpu(
+        n,
+        steps,
+        max,
+        outcome,
+        height_step,
+        height,
+        halvings,
+        power_step,
+        (runs.max_even, runs.max_odd),
+        sum,
+    )
+}
+
+/// Steps n takes to reach 1 counted by `convention`, computed on the host
+/// the same way the shader does. `None` if 3n + 1 overflows u128 or it
+/// doesn't reach 1 within `MAX_STEPS`.
+pub fn cpu_steps(n: u128, convention: StepConvention) -> Option<u32> {
+    let result = cpu_collatz(n);
+    if result.outcome() != Outcome::Converged {
+        return None;
+    }
+
+    match convention {
+        StepConvention::UntilOne => Some(result.steps()),
+        StepConvention::ThroughTrivialCycle => Some(result.steps() + 3),
+    }
+}
+
+// Classic and shortcut-map steps until the first value below n, `(0, 0)`
+// for 1 (and 0) which has nothing below it. `None` if 3n + 1 overflows or
+// there are `MAX_STEPS` steps first.
+pub(crate) fn drop_below(n: u128) -> Option<(u32, u32)> {
+    if n <= 1 {
+        return Some((0, 0));
+    }
+
+    let mut value = n;
+    let (mut steps, mut halvings) = (0, 0);
+    while value >= n {
+        if steps >= MAX_STEPS {
+            return None;
+        }
+        if value.is_multiple_of(2) {
+            halvings += 1;
+        }
+        value = next(value)?;
+        steps += 1;
+    }
+    Some((steps, halvings))
+}
+
+// Parity of each value n steps from under the classic map, n itself first,
+// packed 32 to a word from the lowest bit up with 1 for odd. Stops at 1,
+// after MAX_STEPS steps, or at a 3n + 1 that would overflow, which isn't
+// counted as a step. Also returns the number of bits and whether it
+// overflowed.
+pub(crate) fn parity_vector(n: u128) -> (Vec<u32>, u32, bool) {
+    let mut words = Vec::new();
+    let mut value = n;
+    let mut length = 0;
+    while value != 1 && length < MAX_STEPS {
+        let Some(next_value) = next(value) else {
+            return (words, length, true);
+        };
+        if length % 32 == 0 {
+            words.push(0);
+        }
+        if !value.is_multiple_of(2) {
+            words[length as usize / 32] |= 1 << (length % 32);
+        }
+        value = next_value;
+        length += 1;
+    }
+    (words, length, false)
+}
+
+// Most ancestors `predecessors` collects before giving up, the tree grows
+// by about 4 / 3 a level so from 1 this is reached at depth 55
+pub(crate) const MAX_PREDECESSORS: usize = 1 << 20;
+
+// Every number whose trajectory reaches `value` within `depth` steps,
+// ascending and without `value` itself. Going backwards, n comes from 2n
+// and, when it is an odd integer, from (n - 1) / 3. Predecessors past
+// u128::MAX are left out.
+pub(crate) fn predecessors(value: u128, depth: u32) -> Result<Vec<u128>, CollatzError> {
+    let mut ancestors = BTreeSet::new();
+    let mut level = vec![value];
+    for _ in 0..depth {
+        let mut next_level = Vec::new();
+        for &n in &level {
+            let doubled = n.checked_mul(2);
+            let third = (n % 6 == 4).then(|| (n - 1) / 3);
+            for parent in doubled.into_iter().chain(third) {
+                if parent != value && ancestors.insert(parent) {
+                    next_level.push(parent);
+                }
+            }
+        }
+        if ancestors.len() > MAX_PREDECESSORS {
+            return Err(CollatzError::InvalidArgument(
+                "depth gives more than 2^20 predecessors",
+            ));
+        }
+        level = next_level;
+    }
+    Ok(ancestors.into_iter().collect())
+}
+
+// Numbers the `main_lookup` kernel's table covers, every trajectory from
+// below this stays under 2^32 so entries fit in u32s
+pub(crate) const LOOKUP_LEN: u32 = 1 << 16;
+
+// The `LookupEntry` words for every m below LOOKUP_LEN, in order: steps to 1
+// and halvings (packed in one word), max, the first value with the largest
+// bit length with its offset from m, the steps to m's first power of two,
+// m's longest runs with the length of its first odd run (packed in one
+// word, all of them are below 2^8) and the values after m added up (two
+// words, low first). 0 and 1 are left as all zeros.
+pub(crate) fn lookup_table() -> Vec<u32> {
+    let mut words = vec![0; LOOKUP_LEN as usize * 8];
+    for m in 2..LOOKUP_LEN as u64 {
+        let (mut value, mut steps, mut halvings, mut max) = (m, 0u32, 0u32, m);
+        let (mut height, mut height_offset) = (m, 0);
+        let mut power_offset = None;
+        let mut runs = Runs::default();
+        let mut first_odd_run = None;
+        let mut sum = 0;
+        while value != 1 {
+            if power_offset.is_none() && value.is_power_of_two() {
+                power_offset = Some(steps);
+            }
+            if value.is_multiple_of(2) {
+                value /= 2;
+                halvings += 1;
+                runs.step(true);
+            } else {
+                value = 3 * value + 1;
+                let odd_before = runs.odd;
+                runs.step(false);
+                if first_odd_run.is_none() && runs.odd == 1 && odd_before > 0 {
+                    first_odd_run = Some(odd_before);
+                }
+            }
+            steps += 1;
+            max = max.max(value);
+            sum += value;
+            if value.ilog2() > height.ilog2() {
+                height = value;
+                height_offset = steps;
+            }
+        }
+        let first_odd_run = first_odd_run.unwrap_or(runs.odd);
+        let entry = &mut words[m as usize * 8..][..8];
+        entry.copy_from_slice(&[
+            steps | halvings << 16,
+            max as u32,
+            height as u32,
+            height_offset,
+            power_offset.unwrap_or(steps),
+            runs.max_even | runs.max_odd << 8 | first_odd_run << 16,
+            sum as u32,
+            (sum >> 32) as u32,
+        ]);
+    }
+    words
+}
+
+/// Host-side memo of `(steps, max)` for values below a threshold, so CPU
+/// runs over many numbers can stop as soon as they reach a value whose
+/// trajectory is already known.
+///
+/// Holds at most `capacity` values, evicting the least recently used.
+pub struct TrajectoryCache {
+    threshold: u128,
+    capacity: usize,
+    // value -> (steps to 1, max from it, last use)
+    entries: HashMap<u128, (u32, u128, u64)>,
+    // last use -> value, oldest first
+    by_use: BTreeMap<u64, u128>,
+    clock: u64,
+}
+
+impl TrajectoryCache {
+    /// Cache values below `threshold`, up to `capacity` of them
+    pub fn new(threshold: u128, capacity: usize) -> Self {
+        TrajectoryCache {
+            threshold,
+            capacity,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Steps n takes to reach 1 and the highest value on the way, the same
+    /// as `cpu_collatz` gives for converged numbers. `None` if it overflows
+    /// or doesn't reach 1 within `MAX_STEPS`.
+    pub fn steps_and_max(&mut self, n: u128) -> Option<(u32, u128)> {
+        let mut path = Vec::new();
+        let mut value = n;
+        let (tail_steps, tail_max) = loop {
+            if value == 1 {
+                break (0, 1);
+            }
+            if let Some(known) = self.get(value) {
+                break known;
+            }
+            if path.len() >= MAX_STEPS as usize {
+                return None;
+            }
+            path.push(value);
+            value = next(value)?;
+        };
+
+        let steps = path.len() as u32 + tail_steps;
+        if steps >= MAX_STEPS {
+            return None;
+        }
+
+        // walk back so each value on the path gets its own steps and max
+        let mut max = tail_max;
+        for (i, &value) in path.iter().enumerate().rev() {
+            max = max.max(value);
+            self.insert(value, steps - i as u32, max);
+        }
+        Some((steps, max.max(n)))
+    }
+
+    fn get(&mut self, value: u128) -> Option<(u32, u128)> {
+        let (steps, max, used) = self.entries.get_mut(&value)?;
+        self.by_use.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.by_use.insert(self.clock, value);
+        Some((*steps, *max))
+    }
+
+    fn insert(&mut self, value: u128, steps: u32, max: u128) {
+        if value >= self.threshold || self.capacity == 0 || self.entries.contains_key(&value) {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(value, (steps, max, self.clock));
+        self.by_use.insert(self.clock, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_conventions_for_one_and_two() {
+        use StepConvention::*;
+        assert_eq!(
+            (cpu_steps(1, UntilOne), cpu_steps(2, UntilOne)),
+            (Some(0), Some(1))
+        );
+        let through = (
+            cpu_steps(1, ThroughTrivialCycle),
+            cpu_steps(2, ThroughTrivialCycle),
+        );
+        assert_eq!(through, (Some(3), Some(4)));
+        assert_eq!(cpu_steps(u128::MAX, UntilOne), None);
+    }
+
+    #[test]
+    fn cpu_matches_gpu_batch() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let mut numbers: Vec<u128> = (0..20_000).collect();
+        numbers.extend([
+            (1 << 127) + 1,
+            u128::MAX,
+            u128::MAX - 1,
+            (1 << 100) + 7,
+            1 << 127,
+        ]);
+        numbers.extend((0..2000).map(|i| (1 << 126) + i));
+        let gpu = pollster::block_on(session.run(&numbers)).unwrap();
+        let cpu: Vec<_> = numbers.iter().map(|&n| cpu_collatz(n)).collect();
+        assert!(crate::results_agree(&gpu, &cpu).is_empty());
+        // 0 never leaves itself, which the cycle check catches
+        assert_eq!(cpu[0].outcome(), Outcome::Cycle);
+    }
+
+    #[test]
+    fn cached_results_match_uncached() {
+        let mut cache = TrajectoryCache::new(1 << 20, 500);
+        for n in (1..20_000).chain([(1 << 100) + 3, 0, u128::MAX, 27, 27]) {
+            let result = cpu_collatz(n);
+            let want =
+                (result.outcome() == Outcome::Converged).then(|| (result.steps(), result.max()));
+            assert_eq!(cache.steps_and_max(n), want, "n {n}");
+            assert!(cache.len() <= 500);
+        }
+        let mut uncached = TrajectoryCache::new(1000, 0);
+        assert_eq!(uncached.steps_and_max(27), Some((111, 9232)));
+        assert!(uncached.is_empty());
+    }
+
+    #[test]
+    fn report_matches_batch() {
+        assert_eq!(drop_below(27), Some((96, 59)));
+        assert_eq!(drop_below(1), Some((0, 0)));
+        assert_eq!(drop_below(3), Some((6, 4)));
+        assert_eq!(drop_below(u128::MAX), None);
+
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let numbers: Vec<u128> = (1..3000).chain([(1 << 100) + 7, (1 << 127) + 1]).collect();
+        let gpu = crate::session::run_full(&session, &numbers);
+        for result in &gpu {
+            let cpu = cpu_collatz(result.n());
+            assert_eq!(
+                (cpu.steps(), cpu.max(), cpu.steps_shortcut(), cpu.outcome()),
+                (
+                    result.steps(),
+                    result.max(),
+                    result.steps_shortcut(),
+                    result.outcome()
+                ),
+                "n {}",
+                result.n()
+            );
+            if result.n() == 1 {
+                continue;
+            }
+            let path = walk(result.n());
+            let want = path
+                .iter()
+                .position(|&value| value < result.n())
+                .map(|steps| {
+                    let halvings = path[..steps].iter().filter(|v| v.is_multiple_of(2)).count();
+                    (steps as u32, halvings as u32)
+                });
+            assert_eq!(drop_below(result.n()), want, "n {}", result.n());
+        }
+    }
+
+    #[test]
+    fn predecessor_tree_levels() {
+        assert_eq!(predecessors(16, 2).unwrap(), [5, 10, 32, 64]);
+        assert!(predecessors(16, 0).unwrap().is_empty());
+        assert_eq!(predecessors(1, 3).unwrap(), [2, 4, 8]);
+        let tree = predecessors(10, 12).unwrap();
+        for n in 1..2000 {
+            let reaches = walk(n).iter().take(13).skip(1).any(|&value| value == 10);
+            assert_eq!(tree.contains(&n), reaches, "n {n}");
+        }
+        assert!(tree
+            .iter()
+            .all(|&n| walk(n).iter().take(13).any(|&value| value == 10)));
+        assert!(predecessors(1, 100).is_err());
+    }
+
+    #[test]
+    fn parity_vector_matches_walk() {
+        for n in 1..3000 {
+            let (bits, length, overflowed) = parity_vector(n);
+            let path = walk(n);
+            let odd: Vec<bool> = path[..path.len() - 1].iter().map(|v| v % 2 == 1).collect();
+            assert!(!overflowed);
+            assert_eq!(length as usize, odd.len());
+            assert_eq!(bits.len(), odd.len().div_ceil(32));
+            for (i, &odd) in odd.iter().enumerate() {
+                assert_eq!(bits[i / 32] >> (i % 32) & 1 == 1, odd, "n {n} bit {i}");
+            }
+            // the odd bits are the 3n + 1 steps
+            let result = cpu_collatz(n);
+            let odd_steps: u32 = bits.iter().map(|word| word.count_ones()).sum();
+            assert_eq!(
+                Some(odd_steps),
+                result.steps_shortcut().map(|even| result.steps() - even)
+            );
+        }
+        assert_eq!(parity_vector(3), (vec![5], 7, false));
+        assert_eq!(parity_vector(u128::MAX), (vec![], 0, true));
+    }
+
+    #[test]
+    fn height_matches_walk() {
+        for n in 1..3000 {
+            // the first value with the largest bit length and its step
+            let path = walk(n);
+            let (mut step, mut height) = (0, n);
+            for (i, &value) in path.iter().enumerate() {
+                if bit_length(value) > bit_length(height) {
+                    (step, height) = (i as u32, value);
+                }
+            }
+            let result = cpu_collatz(n);
+            assert_eq!(
+                (result.height_step(), result.height()),
+                (Some(step), Some(height)),
+                "n {n}"
+            );
+        }
+    }
+}
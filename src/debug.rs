@@ -24,3 +24,73 @@ macro_rules! console_log {
     // `bare_bones`
     ($($t:tt)*) => (web_sys::console::log_1(&format!($($t)*).into()))
 }
+
+/// How a dispatch of `count` invocations fills workgroups of `workgroup_size`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Occupancy {
+    pub workgroups: u32,
+    /// Workgroups with fewer than `workgroup_size` real invocations
+    pub underfilled_workgroups: u32,
+    /// Invocations past `count` that only run the shader's bounds check
+    pub tail_invocations: u32,
+}
+
+/// `workgroup_size` must be at least 1
+pub fn workgroup_occupancy(count: u32, workgroup_size: u32) -> Occupancy {
+    let workgroups = count.div_ceil(workgroup_size);
+    // the last workgroup can reach past u32::MAX, but its tail is always
+    // shorter than one workgroup
+    let tail_invocations = (workgroups as u64 * workgroup_size as u64 - count as u64) as u32;
+    Occupancy {
+        workgroups,
+        underfilled_workgroups: (tail_invocations > 0) as u32,
+        tail_invocations,
+    }
+}
+
+/// Log how `count` numbers map onto workgroups of `workgroup_size`
+#[wasm_bindgen]
+pub fn log_workgroup_occupancy(count: u32, workgroup_size: u32) {
+    if workgroup_size == 0 {
+        console_log!("workgroup size must be at least 1");
+        return;
+    }
+    let occupancy = workgroup_occupancy(count, workgroup_size);
+
+    console_log!(
+        "{count} invocations in {} workgroups of {workgroup_size}",
+        occupancy.workgroups
+    );
+    if occupancy.workgroups > 0 {
+        let last = occupancy.workgroups - 1;
+        console_log!(
+            "workgroup 0 runs n 0..{}, workgroup {last} runs n {}..{count}",
+            count.min(workgroup_size),
+            last * workgroup_size
+        );
+    }
+    console_log!(
+        "{} under-filled workgroups, {} tail invocations only run the bounds check",
+        occupancy.underfilled_workgroups,
+        occupancy.tail_invocations
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occupancy_of_partial_last_workgroup() {
+        let occupancy = workgroup_occupancy(100, 64);
+        assert_eq!((occupancy.workgroups, occupancy.tail_invocations), (2, 28));
+        assert_eq!(occupancy.underfilled_workgroups, 1);
+        assert_eq!(workgroup_occupancy(128, 64).underfilled_workgroups, 0);
+        assert_eq!(workgroup_occupancy(0, 64).workgroups, 0);
+        let occupancy = workgroup_occupancy(u32::MAX, 64);
+        assert_eq!(
+            (occupancy.workgroups, occupancy.tail_invocations),
+            (1 << 26, 1)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{
+    buffer_size, create_encoding_pipeline, input_data, max_numbers_per_dispatch, submit,
+    InputLayout,
+};
+use crate::result::DecodeError;
+use crate::session::CollatzSession;
+
+// Each `encode` record is bits (4 words) + length (1 word) + overflowed (1 word)
+const ENCODING_WORDS: usize = 6;
+
+/// The first bits of a number's parity vector under the shortcut map
+/// `(3n + 1) / 2` for odd n and `n / 2` for even n.
+///
+/// Bit i of `bits` is 1 if the i-th value is odd, starting from n itself at
+/// bit 0, and bits from `length` up are 0. The first k bits determine
+/// `n mod 2^k` and the other way round, so different numbers below `2^k`
+/// never share a full k bit encoding.
+///
+/// ```js
+/// const e = encodings[0];
+/// BigInt(e.start); // 3
+/// BigInt(e.bits);  // 3n, 3 -> 5 -> 8 -> 4 -> 2 -> 1 is odd, odd, even, even, even
+/// e.length;        // 5, fewer than max_bits since it reached 1
+/// e.overflowed;    // false
+/// ```
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParityEncoding {
+    n: u128,
+    bits: u128,
+    length: u32,
+    overflowed: bool,
+}
+
+#[wasm_bindgen]
+impl ParityEncoding {
+    #[wasm_bindgen(getter = start)]
+    pub fn start_string(&self) -> String {
+        self.n.to_string()
+    }
+
+    #[wasm_bindgen(getter = bits)]
+    pub fn bits_string(&self) -> String {
+        self.bits.to_string()
+    }
+
+    /// Number of bits recorded, less than max_bits if the trajectory reached
+    /// 1 or overflowed first
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// 3n + 1 overflowed 128 bits before `length` bits could be recorded
+    #[wasm_bindgen(getter)]
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl ParityEncoding {
+    pub fn n(&self) -> u128 {
+        self.n
+    }
+
+    pub fn bits(&self) -> u128 {
+        self.bits
+    }
+
+    // Decode one `encode` output record (ENCODING_WORDS u32s)
+    fn from_gpu_words(n: u128, words: &[u32]) -> Self {
+        ParityEncoding {
+            n,
+            bits: crate::u32_array_to_u128(&[words[0], words[1], words[2], words[3]]),
+            length: words[4],
+            overflowed: words[5] != 0,
+        }
+    }
+}
+
+// Run the `encode` entry point over `numbers`, recording `bits` (1 to 128)
+// parity bits for each
+pub(crate) async fn encode(
+    session: &CollatzSession,
+    numbers: &[u128],
+    bits: u32,
+) -> Result<Vec<ParityEncoding>, CollatzError> {
+    let device = &session.device;
+    let limit = max_numbers_per_dispatch(device);
+    if numbers.len() as u64 > limit {
+        return Err(CollatzError::TooManyNumbers {
+            count: numbers.len() as u64,
+            limit,
+        });
+    }
+
+    let pipeline = create_encoding_pipeline(device, bits);
+    let output_size = buffer_size(numbers.len(), ENCODING_WORDS * 4)?;
+    let input = input_data(numbers, InputLayout::ArrayOfStructs);
+    let words = submit(device, &session.queue, &pipeline, &input, 2, output_size)
+        .read(device)
+        .await?;
+    if words.len() != numbers.len() * ENCODING_WORDS {
+        return Err(DecodeError::WrongLength {
+            expected: output_size as usize,
+            actual: words.len() * 4,
+        }
+        .into());
+    }
+
+    Ok(numbers
+        .iter()
+        .zip(words.chunks_exact(ENCODING_WORDS))
+        .map(|(&n, words)| ParityEncoding::from_gpu_words(n, words))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::test_session;
+
+    // The first `max_bits` parity bits of n under the shortcut map, and how
+    // many there are
+    fn host_encoding(mut n: u128, max_bits: u32) -> (u128, u32) {
+        let (mut bits, mut length) = (0, 0);
+        while n != 1 && length < max_bits {
+            if n.is_multiple_of(2) {
+                n /= 2;
+            } else {
+                bits |= 1 << length;
+                n = (3 * n + 1) >> 1;
+            }
+            length += 1;
+        }
+        (bits, length)
+    }
+
+    #[test]
+    fn encodings_match_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let known = pollster::block_on(encode(&session, &[3, 7], 128)).unwrap();
+        // 3 -> 5 -> 8 -> 4 -> 2 -> 1 is odd, odd, even, even, even
+        assert_eq!((known[0].bits(), known[0].length()), (0b11, 5));
+        // 7 -> 11 -> 17 -> 26 -> 13 -> 20 -> 10 -> 5 -> 8 -> 4 -> 2 -> 1
+        assert_eq!((known[1].bits(), known[1].length()), (0b10010111, 11));
+
+        let numbers: Vec<u128> = (1..3000).collect();
+        for max_bits in [1, 8, 33, 128] {
+            for e in pollster::block_on(encode(&session, &numbers, max_bits)).unwrap() {
+                let want = host_encoding(e.n(), max_bits);
+                assert_eq!((e.bits(), e.length()), want, "n {} bits {max_bits}", e.n());
+                assert!(!e.overflowed());
+            }
+        }
+        // the first 8 bits tell apart 256 consecutive numbers
+        let numbers: Vec<u128> = (1000..1256).collect();
+        let encodings = pollster::block_on(encode(&session, &numbers, 8)).unwrap();
+        let distinct: std::collections::HashSet<_> = encodings.iter().map(|e| e.bits()).collect();
+        assert_eq!(distinct.len(), 256);
+        assert!(pollster::block_on(encode(&session, &[u128::MAX], 8)).unwrap()[0].overflowed());
+    }
+}
@@ -0,0 +1,104 @@
+use wasm_bindgen::prelude::*;
+
+use crate::result::DecodeError;
+
+/// Everything that can go wrong setting up the GPU or running numbers on it
+#[derive(Debug)]
+pub enum CollatzError {
+    /// A start n that isn't a decimal u128
+    InvalidNumber,
+    /// A start of 0, which only ever goes to 0 and never reaches 1
+    InvalidStart,
+    /// A range with no numbers in it
+    EmptyRange,
+    /// A range that runs past u128::MAX
+    RangeOverflow,
+    /// A descending range that runs below 1
+    RangeUnderflow,
+    /// Some other argument out of range, with what was wrong with it
+    InvalidArgument(&'static str),
+    /// No adapter available, usually no WebGPU in this browser
+    NoAdapter,
+    /// The adapter (usually a software one) can't run compute shaders, or
+    /// the probe dispatch `CollatzSession::new` runs came back wrong
+    NoComputeSupport,
+    /// The adapter refused to hand out a device
+    Device(wgpu::RequestDeviceError),
+    /// More numbers than a single dispatch can cover on this device
+    TooManyNumbers { count: u64, limit: u64 },
+    /// A buffer for `count` records of `stride` bytes is too large to
+    /// address on this target, usually 32-bit wasm
+    RequestTooLarge { count: u64, stride: u64 },
+    /// Polling the device while waiting for a submission failed
+    Poll(wgpu::PollError),
+    /// The mapping callback was dropped without reporting back
+    ChannelClosed,
+    /// The output buffer couldn't be mapped for reading
+    Mapping(wgpu::BufferAsyncError),
+    /// The output buffer didn't hold what the shader should have written
+    Decode(DecodeError),
+    /// Results couldn't be written out to the caller's writer
+    Write(std::io::Error),
+}
+
+impl std::fmt::Display for CollatzError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollatzError::InvalidNumber => write!(f, "Could not parse n"),
+            CollatzError::InvalidStart => {
+                write!(f, "start_n must be at least 1, 0 never reaches 1")
+            }
+            CollatzError::EmptyRange => write!(f, "count must be at least 1"),
+            CollatzError::RangeOverflow => write!(f, "Range runs past the largest u128"),
+            CollatzError::RangeUnderflow => write!(f, "Descending range goes below 1"),
+            CollatzError::InvalidArgument(reason) => write!(f, "{reason}"),
+            CollatzError::NoAdapter => write!(
+                f,
+                "No GPU adapter found. Try Chrome WebGPU enabled. Safari Does not support WebGPU"
+            ),
+            CollatzError::NoComputeSupport => {
+                write!(f, "This GPU adapter can't run compute shaders")
+            }
+            CollatzError::Device(e) => write!(f, "{e}"),
+            CollatzError::TooManyNumbers { count, limit } => write!(
+                f,
+                "{count} numbers is more than the device can run in one dispatch ({limit})"
+            ),
+            CollatzError::RequestTooLarge { count, stride } => write!(
+                f,
+                "{count} records of {stride} bytes is more than this platform can address"
+            ),
+            CollatzError::Poll(e) => write!(f, "Waiting for the GPU failed: {e}"),
+            CollatzError::ChannelClosed => write!(f, "Channel error: mapping callback dropped"),
+            CollatzError::Mapping(e) => write!(f, "Buffer mapping failed: {e:?}"),
+            CollatzError::Decode(e) => write!(f, "{e}"),
+            CollatzError::Write(e) => write!(f, "Writing results failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CollatzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CollatzError::Device(e) => Some(e),
+            CollatzError::Poll(e) => Some(e),
+            CollatzError::Mapping(e) => Some(e),
+            CollatzError::Decode(e) => Some(e),
+            CollatzError::Write(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for CollatzError {
+    fn from(e: DecodeError) -> Self {
+        CollatzError::Decode(e)
+    }
+}
+
+// JS callers get the message as a string, same as before this type existed
+impl From<CollatzError> for JsValue {
+    fn from(e: CollatzError) -> Self {
+        JsValue::from_str(&e.to_string())
+    }
+}
@@ -0,0 +1,1253 @@
+use std::ops::ControlFlow;
+
+use wasm_bindgen::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::console_log;
+use crate::cpu::{lookup_table, LOOKUP_LEN};
+use crate::error::CollatzError;
+use crate::input_bytes;
+use crate::result::{
+    decode_results, result_words, CollatzResult, DecodeError, ResultSource, StepConvention,
+    FULL_RESULT_WORDS,
+};
+use crate::session::{poll_strategy, sleep_ms, PollStrategy};
+
+/// How the input buffer lays out each number's four u32 limbs
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InputLayout {
+    /// Each number's limbs next to each other, lowest first
+    #[default]
+    ArrayOfStructs = 0,
+    /// Every number's lowest limb, then every number's second limb and so
+    /// on, so neighbouring invocations read neighbouring words
+    StructOfArrays = 1,
+    /// One u32 per number, four to a 16 byte slot, for a quarter of the
+    /// upload when every number is below 2^32. A scan whose range doesn't
+    /// fit in u32 uses `ArrayOfStructs` instead.
+    PackedU32 = 2,
+}
+
+impl InputLayout {
+    // Numbers in `bytes` of input laid out this way, counting the padding
+    // at the end of the last PackedU32 slot
+    fn numbers_in(self, bytes: usize) -> usize {
+        match self {
+            InputLayout::ArrayOfStructs | InputLayout::StructOfArrays => bytes / 16,
+            InputLayout::PackedU32 => bytes / 4,
+        }
+    }
+}
+
+/// How a dispatch's output gets from the GPU back to the CPU
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ReadbackStrategy {
+    /// Whatever `ReadbackStrategy::for_adapter` picks for the session's
+    /// adapter, `Direct` when that isn't known
+    #[default]
+    Auto = 0,
+    /// Copy the whole output into one staging buffer as large as it and
+    /// map that once
+    Direct = 1,
+    /// Copy the output through a staging buffer of at most 1 MiB, mapping
+    /// it once per window. Slower where mapping is cheap, but never asks for
+    /// a large mappable buffer, which some mobile backends handle badly.
+    /// This is the fallback for adapters that can't hand out a staging
+    /// buffer the size of the output. It still maps a small one, WebGPU
+    /// has no readback without `MAP_READ` at the end.
+    Windowed = 2,
+}
+
+impl ReadbackStrategy {
+    /// The strategy `Auto` uses on an adapter: `Windowed` on GL backends and
+    /// integrated GPUs, where large `MAP_READ` buffers tend to be slow,
+    /// `Direct` everywhere else. In the browser wgpu can't see any adapter
+    /// details, so it's always `Direct` there.
+    pub fn for_adapter(info: &wgpu::AdapterInfo) -> ReadbackStrategy {
+        match (info.backend, info.device_type) {
+            (wgpu::Backend::Gl, _) | (_, wgpu::DeviceType::IntegratedGpu) => {
+                ReadbackStrategy::Windowed
+            }
+            _ => ReadbackStrategy::Direct,
+        }
+    }
+
+    // `Auto` resolved for an adapter, if it's known
+    pub(crate) fn resolve(self, adapter: Option<&wgpu::AdapterInfo>) -> ReadbackStrategy {
+        match self {
+            ReadbackStrategy::Auto => adapter.map_or(ReadbackStrategy::Direct, Self::for_adapter),
+            strategy => strategy,
+        }
+    }
+}
+
+// Largest staging buffer a `Windowed` readback maps at once. A multiple of
+// 4, so every window's copy stays word aligned.
+const READBACK_WINDOW: u64 = 1 << 20;
+
+// The WGSL every pipeline is built from
+pub(crate) const SHADER_SOURCE: &str = include_str!("add.wgsl");
+
+// The separate shader behind `do_gpu_collatz_approx`, not covered by
+// KERNEL_HASH since its results are estimates anyway
+const APPROX_SHADER_SOURCE: &str = include_str!("approx.wgsl");
+
+/// FNV-1a hash of the embedded WGSL source, computed at compile time, so it
+/// only changes when the shader does
+pub const KERNEL_HASH: u64 = fnv1a(SHADER_SOURCE.as_bytes());
+
+// Names of the @compute functions in `source`, in the order they appear
+pub(crate) fn entry_points(source: &str) -> Vec<&str> {
+    source
+        .split("@compute")
+        .skip(1)
+        .filter_map(|rest| {
+            let name = rest.split_once("fn ")?.1;
+            name.split(['(', ' ']).next()
+        })
+        .collect()
+}
+
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+// Passed to the shader as its `workgroup_size` override, so the dispatch
+// math below and the shader's @workgroup_size can't disagree
+pub(crate) const WORKGROUP_SIZE: u32 = 64;
+
+// Whether an adapter with these limits and flags can run the shader at all
+pub(crate) fn supports_compute(limits: &wgpu::Limits, flags: wgpu::DownlevelFlags) -> bool {
+    flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        && limits.max_compute_workgroups_per_dimension > 0
+        && limits.max_compute_workgroup_size_x >= WORKGROUP_SIZE
+        && limits.max_compute_invocations_per_workgroup >= WORKGROUP_SIZE
+        && limits.max_storage_buffers_per_shader_stage >= 2
+}
+
+// Get a high performance adapter and a device from it, along with what the
+// adapter says about itself
+pub(crate) async fn request_device(
+) -> Result<(wgpu::Device, wgpu::Queue, wgpu::AdapterInfo), CollatzError> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::BROWSER_WEBGPU,
+        ..Default::default()
+    });
+    console_log!("made it here 0");
+
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        })
+        .await;
+
+    let adapter = match adapter {
+        Ok(a) => {
+            console_log!("Adapter found: {:?}", a.get_info().name);
+            a
+        }
+        Err(e) => {
+            console_log!(
+                "ERROR: No GPU adapter found. WebGPU may not be supported in this browser. {:?}",
+                e
+            );
+            return Err(CollatzError::NoAdapter);
+        }
+    };
+    console_log!("made it here 1");
+
+    if !supports_compute(
+        &adapter.limits(),
+        adapter.get_downlevel_capabilities().flags,
+    ) {
+        console_log!("ERROR: Adapter can't run compute shaders");
+        return Err(CollatzError::NoComputeSupport);
+    }
+
+    match adapter
+        .request_device(&wgpu::DeviceDescriptor::default())
+        .await
+    {
+        Ok((device, queue)) => Ok((device, queue, adapter.get_info())),
+        Err(e) => {
+            console_log!("{e}");
+            Err(CollatzError::Device(e))
+        }
+    }
+}
+
+// A device on whatever adapter this machine has, for the tests that need a
+// GPU. `None` without one, and those tests then return early.
+#[cfg(test)]
+pub(crate) fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let options = wgpu::RequestAdapterOptions::default();
+    let Ok(adapter) = pollster::block_on(instance.request_adapter(&options)) else {
+        eprintln!("no GPU adapter, skipping");
+        return None;
+    };
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).ok()
+}
+
+pub(crate) fn create_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    create_pipeline_for(
+        device,
+        StepConvention::UntilOne,
+        InputLayout::ArrayOfStructs,
+        false,
+        1,
+        false,
+        false,
+    )
+}
+
+// Pipeline for `main` counting steps by `convention` and reading input laid
+// out as `layout`, or for `main_lookup` if `lookup` is set. That one needs
+// the buffer from `create_lookup_buffer` at LOOKUP_BINDING. Each invocation
+// runs `numbers_per_thread` numbers, which dispatches have to be told too.
+// `saturate` clamps 3n + 1 overflows instead of reporting them.
+pub(crate) fn create_pipeline_for(
+    device: &wgpu::Device,
+    convention: StepConvention,
+    layout: InputLayout,
+    lookup: bool,
+    numbers_per_thread: u32,
+    saturate: bool,
+    full_record: bool,
+) -> wgpu::ComputePipeline {
+    let through_cycle = convention == StepConvention::ThroughTrivialCycle;
+    let soa = layout == InputLayout::StructOfArrays;
+    let packed = layout == InputLayout::PackedU32;
+    let mut constants = vec![
+        ("count_trivial_cycle", through_cycle as u32 as f64),
+        ("soa_input", soa as u32 as f64),
+        ("packed_input", packed as u32 as f64),
+        ("numbers_per_thread", numbers_per_thread.max(1) as f64),
+        ("saturate_overflow", saturate as u32 as f64),
+        ("full_record", full_record as u32 as f64),
+    ];
+    if lookup {
+        constants.push(("lookup_below", LOOKUP_LEN as f64));
+    }
+    let entry_point = if lookup { "main_lookup" } else { "main" };
+    entry_point_pipeline(device, entry_point, &constants)
+}
+
+// Binding `main_lookup` reads the lookup table from
+const LOOKUP_BINDING: u32 = 8;
+
+// Upload the table `main_lookup` finishes trajectories below LOOKUP_LEN from
+pub(crate) fn create_lookup_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Lookup Buffer"),
+        contents: bytemuck::cast_slice(&lookup_table()),
+        usage: wgpu::BufferUsages::STORAGE,
+    })
+}
+
+// Pipeline for `encode` recording `bits` parity bits per number
+pub(crate) fn create_encoding_pipeline(device: &wgpu::Device, bits: u32) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "encode", &[("encoding_bits", bits as f64)])
+}
+
+// Pipeline for `trace`, writing out a single trajectory
+pub(crate) fn create_trajectory_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "trace", &[])
+}
+
+// Pipeline for `quick_check` giving each number `steps` steps to drop
+pub(crate) fn create_quick_check_pipeline(
+    device: &wgpu::Device,
+    steps: u32,
+) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "quick_check", &[("quick_steps", steps as f64)])
+}
+
+// Pipeline for the `reaches` entry point looking for `target`
+pub(crate) fn create_reaches_pipeline(
+    device: &wgpu::Device,
+    target: u128,
+) -> wgpu::ComputePipeline {
+    let limbs = crate::u128_to_u32_array(target);
+    entry_point_pipeline(
+        device,
+        "reaches",
+        &[
+            ("target_0", limbs[0] as f64),
+            ("target_1", limbs[1] as f64),
+            ("target_2", limbs[2] as f64),
+            ("target_3", limbs[3] as f64),
+        ],
+    )
+}
+
+// Pipeline for the `until_exceeds` entry point watching for `ceiling`
+pub(crate) fn create_until_exceeds_pipeline(
+    device: &wgpu::Device,
+    ceiling: u128,
+) -> wgpu::ComputePipeline {
+    let limbs = crate::u128_to_u32_array(ceiling);
+    entry_point_pipeline(
+        device,
+        "until_exceeds",
+        &[
+            ("ceiling_0", limbs[0] as f64),
+            ("ceiling_1", limbs[1] as f64),
+            ("ceiling_2", limbs[2] as f64),
+            ("ceiling_3", limbs[3] as f64),
+        ],
+    )
+}
+
+// Pipeline for the `top_peaks` entry point keeping `count` peaks per number
+pub(crate) fn create_top_peaks_pipeline(
+    device: &wgpu::Device,
+    count: u32,
+) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "top_peaks", &[("peak_count", count as f64)])
+}
+
+// Pipeline for the `mod_orbits` entry point working mod `modulus`
+pub(crate) fn create_mod_orbits_pipeline(
+    device: &wgpu::Device,
+    modulus: u32,
+) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "mod_orbits", &[("modulus", modulus as f64)])
+}
+
+// Pipeline for the `count_above` entry point counting numbers that take
+// more than `threshold` steps
+pub(crate) fn create_count_above_pipeline(
+    device: &wgpu::Device,
+    threshold: u32,
+) -> wgpu::ComputePipeline {
+    entry_point_pipeline(
+        device,
+        "count_above",
+        &[("step_threshold", threshold as f64)],
+    )
+}
+
+// Pipeline for the `step_sum` entry point, adding up each workgroup's steps
+pub(crate) fn create_step_sum_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "step_sum", &[])
+}
+
+// Pipeline for the `first_failure` entry point, marking the first number
+// that doesn't converge
+pub(crate) fn create_first_failure_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "first_failure", &[])
+}
+
+// Pipeline for the `max_growth` entry point, keeping the number whose peak
+// grew the most bits
+pub(crate) fn create_max_growth_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "max_growth", &[])
+}
+
+// Pipeline for the `dropping_histogram` entry point
+pub(crate) fn create_dropping_histogram_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "dropping_histogram", &[])
+}
+
+// Pipeline for the `champion` entry point, reducing each segment of numbers
+// to its longest trajectory
+pub(crate) fn create_champion_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "champion", &[])
+}
+
+// Pipeline for the `signed_orbits` entry point, following i128s on Z
+pub(crate) fn create_signed_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    entry_point_pipeline(device, "signed_orbits", &[])
+}
+
+// Pipeline for approx.wgsl, estimating step counts from each number's low
+// 64 bits and log2
+pub(crate) fn create_approx_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    module_pipeline(device, APPROX_SHADER_SOURCE, "approx", &[])
+}
+
+// Pipeline for one of the shader's entry points, with `constants` set on
+// top of the workgroup size
+fn entry_point_pipeline(
+    device: &wgpu::Device,
+    entry_point: &str,
+    constants: &[(&str, f64)],
+) -> wgpu::ComputePipeline {
+    module_pipeline(device, SHADER_SOURCE, entry_point, constants)
+}
+
+// Same as `entry_point_pipeline` for an entry point in `source`
+fn module_pipeline(
+    device: &wgpu::Device,
+    source: &str,
+    entry_point: &str,
+    constants: &[(&str, f64)],
+) -> wgpu::ComputePipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Collatz Shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    let mut overrides = vec![("workgroup_size", WORKGROUP_SIZE as f64)];
+    overrides.extend_from_slice(constants);
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Compute Pipeline"),
+        layout: None,
+        module: &shader,
+        entry_point: Some(entry_point),
+        compilation_options: wgpu::PipelineCompilationOptions {
+            constants: &overrides,
+            ..Default::default()
+        },
+        cache: None,
+    })
+}
+
+// Most numbers one dispatch can cover: the output has to fit in one storage
+// binding and the workgroups in one dispatch dimension
+pub(crate) fn max_numbers_per_dispatch(device: &wgpu::Device) -> u64 {
+    max_records_per_dispatch(device, FULL_RESULT_WORDS as u64 * 4)
+}
+
+// Same as `max_numbers_per_dispatch` for entry points writing
+// `record_size` bytes per number
+pub(crate) fn max_records_per_dispatch(device: &wgpu::Device, record_size: u64) -> u64 {
+    let limits = device.limits();
+    let by_binding = limits.max_storage_buffer_binding_size as u64 / record_size;
+    let by_workgroups = limits.max_compute_workgroups_per_dimension as u64 * WORKGROUP_SIZE as u64;
+    by_binding.min(by_workgroups)
+}
+
+/// Output of a dispatch that has been submitted to the GPU and is waiting to
+/// be read back
+pub(crate) struct PendingOutput {
+    queue: wgpu::Queue,
+    output_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    // bytes at the start of the output buffer being read back, in windows
+    // of up to the staging buffer's size
+    size: u64,
+    // the window currently being copied and mapped
+    submission: wgpu::SubmissionIndex,
+    receiver: flume::Receiver<Result<(), wgpu::BufferAsyncError>>,
+    // capacity of the mapping channels for later windows, see
+    // `mapping_channel`
+    in_flight: usize,
+}
+
+// Bytes taken by `count` records of `stride` bytes. Worked out in 64 bits so
+// it can't wrap where usize is 32 bits, and refused if the output couldn't
+// be read back into a usize-indexed Vec.
+pub(crate) fn buffer_size(count: usize, stride: usize) -> Result<u64, CollatzError> {
+    (count as u64)
+        .checked_mul(stride as u64)
+        .filter(|&size| size <= usize::MAX as u64)
+        .ok_or(CollatzError::RequestTooLarge {
+            count: count as u64,
+            stride: stride as u64,
+        })
+}
+
+// The input buffer's contents for `numbers` laid out as `layout`
+pub(crate) fn input_data(numbers: &[u128], layout: InputLayout) -> Vec<u8> {
+    match layout {
+        // Convert to GPU format (4 × u32 per number)
+        InputLayout::ArrayOfStructs => numbers.iter().flat_map(|&n| input_bytes(n)).collect(),
+        InputLayout::StructOfArrays => (0..4)
+            .flat_map(|limb| {
+                numbers
+                    .iter()
+                    .flat_map(move |&n| ((n >> (32 * limb)) as u32).to_le_bytes())
+            })
+            .collect(),
+        // numbers have to be below 2^32, the last slot is padded with 0s
+        InputLayout::PackedU32 => {
+            let mut bytes: Vec<u8> = numbers
+                .iter()
+                .flat_map(|&n| (n as u32).to_le_bytes())
+                .collect();
+            bytes.resize(bytes.len().next_multiple_of(16), 0);
+            bytes
+        }
+    }
+}
+
+/// Input, output and staging buffers for a dispatch. Later dispatches that
+/// fit in them can reuse them, binding and mapping only the part they need.
+pub(crate) struct KernelBuffers {
+    input: wgpu::Buffer,
+    output: wgpu::Buffer,
+    staging: wgpu::Buffer,
+}
+
+impl KernelBuffers {
+    pub(crate) fn new(device: &wgpu::Device, input_size: u64, output_size: u64) -> Self {
+        Self::with_readback(device, input_size, output_size, ReadbackStrategy::Direct)
+    }
+
+    // Same as `new`, with a staging buffer sized for `readback`, which has
+    // to have been resolved already
+    pub(crate) fn with_readback(
+        device: &wgpu::Device,
+        input_size: u64,
+        output_size: u64,
+        readback: ReadbackStrategy,
+    ) -> Self {
+        let staging_size = match readback {
+            ReadbackStrategy::Windowed => output_size.min(READBACK_WINDOW),
+            ReadbackStrategy::Auto | ReadbackStrategy::Direct => output_size,
+        };
+        let input = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Input Buffer"),
+            size: input_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Output Buffer"),
+            size: output_size,
+            // COPY_DST for the clear `set_readback_checks` asks for
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Staging Buffer"),
+            size: staging_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        KernelBuffers {
+            input,
+            output,
+            staging,
+        }
+    }
+
+    // Whether a dispatch with this much input and output can use these
+    pub(crate) fn fits(&self, input_size: u64, output_size: u64) -> bool {
+        self.input.size() >= input_size && self.output.size() >= output_size
+    }
+}
+
+// Upload `input_data` to binding 0, run one invocation of `compute_pipeline`
+// per 16 byte record in it and start mapping the `output_size` bytes it writes to
+// `output_binding`
+pub(crate) fn submit(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    compute_pipeline: &wgpu::ComputePipeline,
+    input_data: &[u8],
+    output_binding: u32,
+    output_size: u64,
+) -> PendingOutput {
+    let buffers = KernelBuffers::new(device, input_data.len() as u64, output_size);
+    submit_with(
+        device,
+        queue,
+        compute_pipeline,
+        &buffers,
+        input_data,
+        InputLayout::ArrayOfStructs,
+        1,
+        output_binding,
+        output_size,
+        None,
+        1,
+    )
+}
+
+// Run `compute_pipeline` over `count` numbers from `start` with one `submit`
+// per chunk of at most RANGE numbers, each reading back the
+// `output_words(len)` words a chunk of `len` numbers writes to
+// `output_binding`. `on_chunk` gets each chunk's first number and words, in
+// order. The range must already have been checked.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn dispatch_chunked(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    compute_pipeline: &wgpu::ComputePipeline,
+    start: u128,
+    count: u128,
+    output_binding: u32,
+    output_words: impl Fn(usize) -> usize,
+    mut on_chunk: impl FnMut(u128, &[u32]),
+) -> Result<(), CollatzError> {
+    dispatch_chunked_until(
+        device,
+        queue,
+        compute_pipeline,
+        start,
+        count,
+        output_binding,
+        output_words,
+        |first, words| {
+            on_chunk(first, words);
+            ControlFlow::Continue(())
+        },
+    )
+    .await
+}
+
+// Same as `dispatch_chunked`, but no more chunks are submitted once
+// `on_chunk` breaks
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn dispatch_chunked_until(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    compute_pipeline: &wgpu::ComputePipeline,
+    start: u128,
+    count: u128,
+    output_binding: u32,
+    output_words: impl Fn(usize) -> usize,
+    mut on_chunk: impl FnMut(u128, &[u32]) -> ControlFlow<()>,
+) -> Result<(), CollatzError> {
+    let chunk_size = max_numbers_per_dispatch(device).min(crate::RANGE as u64) as u128;
+
+    let mut offset = 0;
+    while offset < count {
+        let len = (count - offset).min(chunk_size);
+        let first = start + offset;
+        // counted up from first so a chunk ending at u128::MAX doesn't wrap
+        let numbers: Vec<u128> = (0..len).map(|i| first + i).collect();
+
+        let input = input_data(&numbers, InputLayout::ArrayOfStructs);
+        let expected = output_words(numbers.len());
+        let output_size = buffer_size(expected, 4)?;
+        let words = submit(
+            device,
+            queue,
+            compute_pipeline,
+            &input,
+            output_binding,
+            output_size,
+        )
+        .read(device)
+        .await?;
+        if words.len() != expected {
+            return Err(DecodeError::WrongLength {
+                expected: output_size as usize,
+                actual: words.len() * 4,
+            }
+            .into());
+        }
+
+        if on_chunk(first, &words).is_break() {
+            break;
+        }
+        offset += len;
+    }
+
+    Ok(())
+}
+
+// Same as `submit` but using `buffers`, which have to fit the dispatch and
+// not be in use by another one until this one has been read. One invocation
+// runs per `numbers_per_thread` numbers in `input_data` laid out as `layout`,
+// which has to match what `compute_pipeline` was built with. `lookup` is
+// bound as the lookup table for `main_lookup` pipelines. `in_flight` is how
+// many dispatches may be waiting to be read at once, see `mapping_channel`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn submit_with(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    compute_pipeline: &wgpu::ComputePipeline,
+    buffers: &KernelBuffers,
+    input_data: &[u8],
+    layout: InputLayout,
+    numbers_per_thread: u32,
+    output_binding: u32,
+    output_size: u64,
+    lookup: Option<&wgpu::Buffer>,
+    in_flight: usize,
+) -> PendingOutput {
+    let invocations = layout
+        .numbers_in(input_data.len())
+        .div_ceil(numbers_per_thread.max(1) as usize);
+    let input_size = input_data.len() as u64;
+
+    queue.write_buffer(&buffers.input, 0, input_data);
+
+    // bind only what this dispatch uses, the shader sizes its loop by
+    // arrayLength so a larger reused buffer must not show through
+    let bind_group_layout = compute_pipeline.get_bind_group_layout(0);
+    let mut entries = vec![
+        wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &buffers.input,
+                offset: 0,
+                size: wgpu::BufferSize::new(input_size),
+            }),
+        },
+        wgpu::BindGroupEntry {
+            binding: output_binding,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &buffers.output,
+                offset: 0,
+                size: wgpu::BufferSize::new(output_size),
+            }),
+        },
+    ];
+    if let Some(lookup) = lookup {
+        entries.push(wgpu::BindGroupEntry {
+            binding: LOOKUP_BINDING,
+            resource: lookup.as_entire_binding(),
+        });
+    }
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &entries,
+        label: Some("Bind Group"),
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Compute Encoder"),
+    });
+    // with readback checks on, a record the shader skips reads back as 0s
+    // rather than whatever an earlier dispatch left in a reused buffer
+    if crate::session::readback_checks() {
+        encoder.clear_buffer(&buffers.output, 0, Some(output_size));
+    }
+    {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(compute_pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        // Dispatch enough workgroups to cover all input numbers
+        let num_workgroups = (invocations as u32).div_ceil(WORKGROUP_SIZE);
+        cpass.dispatch_workgroups(num_workgroups, 1, 1);
+    }
+    // the first window goes in with the dispatch, the rest are copied as
+    // `read` gets to them
+    let window = output_size.min(buffers.staging.size());
+    encoder.copy_buffer_to_buffer(&buffers.output, 0, &buffers.staging, 0, window);
+    let submission = queue.submit(Some(encoder.finish()));
+    let receiver = map_staging(&buffers.staging, window, in_flight);
+
+    PendingOutput {
+        queue: queue.clone(),
+        output_buffer: buffers.output.clone(),
+        staging_buffer: buffers.staging.clone(),
+        size: output_size,
+        submission,
+        receiver,
+        in_flight,
+    }
+}
+
+// Start mapping the first `size` bytes of `staging`. In WASM, we need to use
+// a channel to properly await the buffer mapping.
+fn map_staging(
+    staging: &wgpu::Buffer,
+    size: u64,
+    in_flight: usize,
+) -> flume::Receiver<Result<(), wgpu::BufferAsyncError>> {
+    let (sender, receiver) = mapping_channel(in_flight);
+    staging
+        .slice(..size)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+    receiver
+}
+
+// Channel a `map_async` callback reports back on, with room for one
+// completion per chunk that can be in flight alongside it. A single-shot
+// dispatch passes 1, which is all it needs: the callback runs exactly once,
+// so one slot never blocks the sender. Chunks in flight at the same time
+// each get their own channel rather than sharing one, so they can be mapped
+// and read in any order without one chunk's result queueing behind
+// another's, and the capacity only makes sure a callback never waits on a
+// reader even if the channels are later shared between chunks.
+fn mapping_channel(in_flight: usize) -> MappingChannel {
+    flume::bounded(in_flight.max(1))
+}
+
+type MappingChannel = (
+    flume::Sender<Result<(), wgpu::BufferAsyncError>>,
+    flume::Receiver<Result<(), wgpu::BufferAsyncError>>,
+);
+
+impl PendingOutput {
+    // Wait for the output to be mapped and copy it out as u32 words, one
+    // staging buffer's worth at a time
+    pub(crate) async fn read(self, device: &wgpu::Device) -> Result<Vec<u32>, CollatzError> {
+        let mut words = Vec::with_capacity(self.size as usize / 4);
+        let mut submission = self.submission;
+        let mut receiver = self.receiver;
+        let mut offset = 0;
+        loop {
+            let window = (self.size - offset).min(self.staging_buffer.size());
+
+            wait_for_mapping(device, submission, &receiver).await?;
+
+            let buffer_slice = self.staging_buffer.slice(..window);
+            let data = buffer_slice.get_mapped_range();
+            let copied = bytemuck::try_cast_slice::<u8, u32>(&data)
+                .map(|window_words| words.extend_from_slice(window_words))
+                .map_err(DecodeError::NotWords);
+
+            drop(data);
+            self.staging_buffer.unmap();
+            copied?;
+
+            offset += window;
+            if offset == self.size {
+                return Ok(words);
+            }
+
+            let window = (self.size - offset).min(self.staging_buffer.size());
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+            encoder.copy_buffer_to_buffer(
+                &self.output_buffer,
+                offset,
+                &self.staging_buffer,
+                0,
+                window,
+            );
+            submission = self.queue.submit(Some(encoder.finish()));
+            receiver = map_staging(&self.staging_buffer, window, self.in_flight);
+        }
+    }
+}
+
+// Poll the device the way `set_poll_strategy` asked until `submission` is
+// done, then wait for its mapping to complete
+async fn wait_for_mapping(
+    device: &wgpu::Device,
+    submission: wgpu::SubmissionIndex,
+    receiver: &flume::Receiver<Result<(), wgpu::BufferAsyncError>>,
+) -> Result<(), CollatzError> {
+    match poll_strategy() {
+        PollStrategy::Wait => {
+            device
+                .poll(wgpu::PollType::Wait {
+                    submission_index: Some(submission),
+                    timeout: None,
+                })
+                .map_err(CollatzError::Poll)?;
+        }
+        // natively the callback only runs from inside a poll (in the
+        // browser, from the event loop while this sleeps), so once the
+        // channel has something in it the submission is done
+        PollStrategy::Sleep { interval_ms } => loop {
+            device
+                .poll(wgpu::PollType::Poll)
+                .map_err(CollatzError::Poll)?;
+            if !receiver.is_empty() {
+                break;
+            }
+            sleep_ms(interval_ms).await;
+        },
+    }
+
+    receiver
+        .recv_async()
+        .await
+        .map_err(|_| CollatzError::ChannelClosed)?
+        .map_err(CollatzError::Mapping)
+}
+
+/// A chunk of numbers that has been submitted to the GPU and is waiting to
+/// be read back
+pub(crate) struct PendingChunk {
+    numbers: Vec<u128>,
+    output: PendingOutput,
+    source: ResultSource,
+    full_record: bool,
+}
+
+#[cfg(test)]
+thread_local! {
+    // Reading back a chunk holding this number fails, for testing what
+    // scans do about a failed chunk
+    pub(crate) static FAIL_CHUNK_WITH: std::cell::Cell<Option<u128>> =
+        const { std::cell::Cell::new(None) };
+}
+
+// Upload numbers laid out as `layout`, which has to be the layout (and
+// `numbers_per_thread` the count) `compute_pipeline` was built for, dispatch
+// the shader over them and start mapping the results. `lookup` is the table
+// for `main_lookup` pipelines, `readback` has to have been resolved already.
+// `full_record` has to match the pipeline's too. `in_flight` is the most
+// chunks that will be waiting to be read at once.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn submit_chunk(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    compute_pipeline: &wgpu::ComputePipeline,
+    numbers: Vec<u128>,
+    layout: InputLayout,
+    numbers_per_thread: u32,
+    lookup: Option<&wgpu::Buffer>,
+    readback: ReadbackStrategy,
+    full_record: bool,
+    in_flight: usize,
+) -> PendingChunk {
+    // Output: each result is 32 bytes, or 68 for a full record (see
+    // RESULT_WORDS and FULL_RESULT_WORDS). Chunks are at most
+    // max_numbers_per_dispatch long, so this fits in one binding.
+    let output_size = numbers.len() as u64 * (result_words(full_record) * 4) as u64;
+    let input = input_data(&numbers, layout);
+    let buffers = KernelBuffers::with_readback(device, input.len() as u64, output_size, readback);
+    let output = submit_with(
+        device,
+        queue,
+        compute_pipeline,
+        &buffers,
+        &input,
+        layout,
+        numbers_per_thread,
+        1,
+        output_size,
+        lookup,
+        in_flight,
+    );
+
+    let source = match lookup {
+        Some(_) => ResultSource::Table,
+        None => ResultSource::Gpu,
+    };
+    PendingChunk {
+        numbers,
+        output,
+        source,
+        full_record,
+    }
+}
+
+impl PendingChunk {
+    // Lowest number in the chunk and how many it holds
+    pub(crate) fn range(&self) -> (u128, u32) {
+        let lowest = self.numbers.iter().copied().min().unwrap_or(0);
+        (lowest, self.numbers.len() as u32)
+    }
+
+    // Wait for the chunk's results to be mapped and decode them
+    pub(crate) async fn read(
+        self,
+        device: &wgpu::Device,
+    ) -> Result<Vec<CollatzResult>, CollatzError> {
+        #[cfg(test)]
+        if FAIL_CHUNK_WITH
+            .get()
+            .is_some_and(|n| self.numbers.contains(&n))
+        {
+            return Err(CollatzError::ChannelClosed);
+        }
+        let words = self.output.read(device).await?;
+        let results = decode_results(
+            &self.numbers,
+            bytemuck::cast_slice(&words),
+            self.full_record,
+        )?;
+        Ok(match self.source {
+            ResultSource::Gpu => results,
+            source => results
+                .into_iter()
+                .map(|result| result.with_source(source))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_hash_follows_the_wgsl() {
+        // FNV-1a of the shader file's bytes as they are on disk
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &byte in include_bytes!("add.wgsl") {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+        assert_eq!(KERNEL_HASH, hash);
+        assert_eq!(fnv1a(SHADER_SOURCE.as_bytes()), KERNEL_HASH);
+        let edited = SHADER_SOURCE.replacen("100000u", "100001u", 1);
+        assert_ne!(fnv1a(edited.as_bytes()), KERNEL_HASH);
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn compute_capability_probe() {
+        use wgpu::{DownlevelFlags, Limits};
+        assert!(supports_compute(&Limits::default(), DownlevelFlags::all()));
+        assert!(supports_compute(
+            &Limits::downlevel_defaults(),
+            DownlevelFlags::all()
+        ));
+        assert!(!supports_compute(
+            &Limits::default(),
+            DownlevelFlags::empty()
+        ));
+        let webgl2 = Limits::downlevel_webgl2_defaults();
+        assert!(!supports_compute(&webgl2, DownlevelFlags::all()));
+        let one_storage_buffer = Limits {
+            max_storage_buffers_per_shader_stage: 1,
+            ..Limits::default()
+        };
+        assert!(!supports_compute(
+            &one_storage_buffer,
+            DownlevelFlags::all()
+        ));
+
+        let instance = wgpu::Instance::default();
+        let options = wgpu::RequestAdapterOptions::default();
+        if let Ok(adapter) = pollster::block_on(instance.request_adapter(&options)) {
+            let flags = adapter.get_downlevel_capabilities().flags;
+            assert!(supports_compute(&adapter.limits(), flags));
+        }
+    }
+
+    #[test]
+    fn readback_strategy_for_adapter() {
+        let mut info = wgpu::AdapterInfo {
+            name: String::new(),
+            vendor: 0,
+            device: 0,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: wgpu::Backend::Vulkan,
+        };
+        assert_eq!(
+            ReadbackStrategy::for_adapter(&info),
+            ReadbackStrategy::Direct
+        );
+        assert_eq!(
+            ReadbackStrategy::Auto.resolve(Some(&info)),
+            ReadbackStrategy::Direct
+        );
+        info.device_type = wgpu::DeviceType::IntegratedGpu;
+        assert_eq!(
+            ReadbackStrategy::for_adapter(&info),
+            ReadbackStrategy::Windowed
+        );
+        info.device_type = wgpu::DeviceType::DiscreteGpu;
+        info.backend = wgpu::Backend::Gl;
+        assert_eq!(
+            ReadbackStrategy::Auto.resolve(Some(&info)),
+            ReadbackStrategy::Windowed
+        );
+        assert_eq!(
+            ReadbackStrategy::Direct.resolve(Some(&info)),
+            ReadbackStrategy::Direct
+        );
+        assert_eq!(
+            ReadbackStrategy::Auto.resolve(None),
+            ReadbackStrategy::Direct
+        );
+    }
+
+    #[test]
+    fn shader_entry_points_listed() {
+        let want = [
+            "main",
+            "main_lookup",
+            "encode",
+            "trace",
+            "quick_check",
+            "reaches",
+            "until_exceeds",
+            "top_peaks",
+            "mod_orbits",
+            "count_above",
+            "step_sum",
+            "first_failure",
+            "max_growth",
+            "champion",
+            "dropping_histogram",
+            "signed_orbits",
+        ];
+        assert_eq!(entry_points(SHADER_SOURCE), want);
+        let source = "@compute @workgroup_size(1)\nfn a() {}\n@compute\n@workgroup_size(1)\nfn b(@builtin(global_invocation_id) id: vec3<u32>) {}";
+        assert_eq!(entry_points(source), ["a", "b"]);
+    }
+
+    #[test]
+    fn oversized_buffers_error() {
+        assert_eq!(buffer_size(1000, 68).unwrap(), 68_000);
+        assert_eq!(buffer_size(0, 68).unwrap(), 0);
+        for (count, stride) in [
+            (usize::MAX, 68),
+            (usize::MAX / 2, 16),
+            (usize::MAX / 4 + 1, 4),
+        ] {
+            match buffer_size(count, stride) {
+                Err(CollatzError::RequestTooLarge {
+                    count: c,
+                    stride: s,
+                }) => assert_eq!((c, s), (count as u64, stride as u64)),
+                other => panic!("{count} {stride}: {other:?}"),
+            }
+        }
+    }
+
+    // Results for 1..=1000 from the `main` pipeline built from `source`, with
+    // readback checks on
+    fn checked_run(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &str,
+    ) -> Result<Vec<CollatzResult>, CollatzError> {
+        let pipeline = module_pipeline(device, source, "main", &[]);
+        crate::set_readback_checks(true);
+        let numbers = (1..=1000).collect();
+        let chunk = submit_chunk(
+            device,
+            queue,
+            &pipeline,
+            numbers,
+            InputLayout::ArrayOfStructs,
+            1,
+            None,
+            ReadbackStrategy::Direct,
+            false,
+            1,
+        );
+        let results = pollster::block_on(chunk.read(device));
+        crate::set_readback_checks(false);
+        results
+    }
+
+    #[test]
+    fn workgroup_size_mismatch_is_caught() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        let results = checked_run(&device, &queue, SHADER_SOURCE).unwrap();
+        assert_eq!(results[26].steps(), 111);
+        // a shader that ignores the host's size and runs 32 per workgroup
+        // gets 16 workgroups for 1000 numbers, so it stops after 512
+        let mismatched = SHADER_SOURCE
+            .replace(
+                "override workgroup_size: u32 = 64u",
+                "override group_size: u32 = 32u",
+            )
+            .replace("(workgroup_size)", "(group_size)");
+        match checked_run(&device, &queue, &mismatched) {
+            Err(CollatzError::Decode(DecodeError::Unwritten { index })) => assert_eq!(index, 512),
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn poll_errors_are_returned() {
+        let (Some((_busy, busy_queue)), Some((idle, _))) = (test_device(), test_device()) else {
+            return;
+        };
+        // an index from a device that has submitted more than `idle` has is
+        // one `idle` can't wait for
+        for _ in 0..3 {
+            busy_queue.submit(None);
+        }
+        let submission = busy_queue.submit(None);
+        let (_sender, receiver) = mapping_channel(1);
+        let waited = pollster::block_on(wait_for_mapping(&idle, submission, &receiver));
+        assert!(matches!(
+            waited,
+            Err(CollatzError::Poll(wgpu::PollError::WrongSubmissionIndex(
+                ..
+            )))
+        ));
+    }
+
+    #[test]
+    fn mapping_channel_scales_with_chunks_in_flight() {
+        assert_eq!(mapping_channel(0).0.capacity(), Some(1));
+        let (sender, receiver) = mapping_channel(3);
+        assert_eq!(sender.capacity(), Some(3));
+        // a full channel's worth of completions goes in without a reader
+        for _ in 0..3 {
+            assert!(sender.try_send(Ok(())).is_ok());
+        }
+        assert_eq!(receiver.drain().filter(Result::is_ok).count(), 3);
+    }
+
+    #[test]
+    fn in_flight_chunks_read_newest_first() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let range = |i: u128| i * 1000 + 1..=i * 1000 + 1000;
+        let chunks: Vec<_> = (0..4)
+            .map(|i| {
+                submit_chunk(
+                    &session.device,
+                    &session.queue,
+                    &session.pipeline,
+                    range(i).collect(),
+                    InputLayout::ArrayOfStructs,
+                    1,
+                    None,
+                    ReadbackStrategy::Direct,
+                    false,
+                    4,
+                )
+            })
+            .collect();
+        // each chunk's mapping has to arrive on its own channel
+        for (i, chunk) in chunks.into_iter().enumerate().rev() {
+            let got = pollster::block_on(chunk.read(&session.device)).unwrap();
+            let want: Vec<_> = range(i as u128).map(crate::cpu_collatz).collect();
+            assert!(crate::results_agree(&got, &want).is_empty(), "chunk {i}");
+        }
+    }
+
+    #[test]
+    fn readback_checks_catch_undersized_dispatch() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let read = |numbers_per_thread, readback| {
+            let chunk = submit_chunk(
+                &session.device,
+                &session.queue,
+                &session.pipeline,
+                (1..=1000).collect(),
+                InputLayout::ArrayOfStructs,
+                numbers_per_thread,
+                None,
+                readback,
+                false,
+                1,
+            );
+            pollster::block_on(chunk.read(&session.device))
+        };
+        crate::set_readback_checks(true);
+        // the session's pipeline runs one number per thread, dispatching it
+        // as if it ran four covers 4 workgroups, 256 numbers
+        let undersized = read(4, ReadbackStrategy::Direct);
+        let windowed = read(1, ReadbackStrategy::Windowed);
+        crate::set_readback_checks(false);
+        match undersized {
+            Err(CollatzError::Decode(DecodeError::Unwritten { index })) => assert_eq!(index, 256),
+            other => panic!("{other:?}"),
+        }
+        let want: Vec<_> = (1..=1000).map(crate::cpu_collatz).collect();
+        assert!(crate::results_agree(&windowed.unwrap(), &want).is_empty());
+    }
+}
@@ -1,11 +1,73 @@
+mod analysis;
+mod approx;
+mod archive;
+mod cpu;
 mod debug;
+mod encoding;
+mod error;
+mod gpu;
+mod modular;
+mod peaks;
+mod progress;
+mod quick;
+mod reach;
+mod result;
+mod sample;
+mod scan;
+mod session;
+mod signed;
+mod trajectory;
 
+use std::cell::Cell;
+use std::ops::ControlFlow;
+
+use session::session;
 use wasm_bindgen::prelude::*;
-use wgpu::util::DeviceExt;
+
+pub use analysis::{
+    add_leading_digits, delay_records, first_failure, first_with_steps, heatmap_rgba,
+    inconsistent_results, max_growth, most_steps, results_agree, scatter_points, step_sum,
+    steps_f64, window_variance, with_steps, work_units, ConvergenceSummary, Histogram,
+};
+pub use archive::{export_sweep, import_sweep, SweepArchive};
+pub use cpu::{cpu_collatz, cpu_steps, TrajectoryCache};
+pub use debug::{log_workgroup_occupancy, workgroup_occupancy, Occupancy};
+pub use encoding::ParityEncoding;
+pub use error::CollatzError;
+pub use gpu::{InputLayout, ReadbackStrategy, KERNEL_HASH};
+pub use modular::{ModOrbit, MAX_MODULUS};
+pub use peaks::{TopPeaks, MAX_PEAKS};
+pub use progress::ScanProgress;
+pub use result::{
+    pack_results, packed_record, CollatzResult, DecodeError, Outcome, OutputFormat, PackedRecord,
+    ResultSource, StepConvention, MAX_STEPS, PACKED_RECORD_SIZE,
+};
+pub use scan::{FailedRange, RunConfig};
+pub use session::{
+    buffer_allocations, recommended_chunk_size, set_buffer_reuse, set_poll_strategy,
+    set_readback_checks, warmup, PollStrategy,
+};
+pub use signed::SignedOrbit;
+pub use trajectory::Trajectory;
+
+// Seed `do_gpu_collatz_overflow_rate` uses when none is given
+const DEFAULT_SEED: u32 = 0x5EED;
 
 // 50,000 is 1mb
 const RANGE: u32 = 100_000;
 
+// Scans on a caller's device get a session that only lasts the call, so a
+// calibrated chunk size would be measured again on every call
+const ON_DEVICE_CONFIG: RunConfig = RunConfig {
+    chunk_size: RANGE,
+    ..RunConfig::DEFAULT
+};
+
+thread_local! {
+    // Answer from the last adapter probe, see `check_webgpu_support`
+    static WEBGPU_SUPPORT: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
 // Helper function to convert u128 to array of 4 u32s (little-endian)
 fn u128_to_u32_array(n: u128) -> [u32; 4] {
     [
@@ -34,8 +96,37 @@ fn u32_array_to_bytes(parts: &[u32; 4]) -> [u8; 16] {
     bytes
 }
 
+// The 16 bytes one number takes up in the shader's input buffer
+fn input_bytes(n: u128) -> [u8; 16] {
+    u32_array_to_bytes(&u128_to_u32_array(n))
+}
+
+// A plain JS object with these properties, in order
+fn js_object(pairs: &[(&str, JsValue)]) -> js_sys::Object {
+    let object = js_sys::Object::new();
+    for (key, value) in pairs {
+        // only fails on frozen objects
+        let _ = js_sys::Reflect::set(&object, &(*key).into(), value);
+    }
+    object
+}
+
+/// Whether this browser hands out a WebGPU adapter. Only the first call
+/// probes for one, later calls return the same answer, so a UI can poll it
+/// freely. `recheck_webgpu_support` probes again.
 #[wasm_bindgen]
 pub async fn check_webgpu_support() -> bool {
+    cached_webgpu_support(false, probe_webgpu_support).await
+}
+
+/// Probe for an adapter again, for after the user has changed a browser
+/// setting, and remember the new answer for `check_webgpu_support`
+#[wasm_bindgen]
+pub async fn recheck_webgpu_support() -> bool {
+    cached_webgpu_support(true, probe_webgpu_support).await
+}
+
+async fn probe_webgpu_support() -> bool {
     let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
         backends: wgpu::Backends::BROWSER_WEBGPU,
         ..Default::default()
@@ -48,219 +139,1843 @@ pub async fn check_webgpu_support() -> bool {
     adapter.is_ok()
 }
 
+// The cached answer, or `probe`'s if there isn't one yet or `force` is set.
+// Calls that overlap before the first answer is in each probe, and they all
+// store whatever the adapter said.
+async fn cached_webgpu_support<F: std::future::Future<Output = bool>>(
+    force: bool,
+    probe: impl FnOnce() -> F,
+) -> bool {
+    if let Some(supported) = WEBGPU_SUPPORT.get().filter(|_| !force) {
+        return supported;
+    }
+    let supported = probe().await;
+    WEBGPU_SUPPORT.set(Some(supported));
+    supported
+}
+
+/// Runs when the module is instantiated. Installs `console_error_panic_hook`
+/// unless built without the `panic-hook` feature.
 #[wasm_bindgen(start)]
 pub fn init() {
+    #[cfg(feature = "panic-hook")]
     console_error_panic_hook::set_once();
     console_log!("WASM module initialized!");
 }
 
+/// `{ version, kernelHash }`: the crate version and `KERNEL_HASH` as 16 hex
+/// digits, for bug reports and for spotting clients running a stale kernel
+#[wasm_bindgen]
+pub fn version_info() -> JsValue {
+    js_object(&[
+        ("version", env!("CARGO_PKG_VERSION").into()),
+        ("kernelHash", format!("{KERNEL_HASH:016x}").into()),
+    ])
+    .into()
+}
+
+/// `{ source, entryPoints }`: the WGSL the kernel is built from and the
+/// names of its compute entry points, to see exactly what ran
+#[wasm_bindgen]
+pub fn shader_info() -> JsValue {
+    let entry_points: js_sys::Array = gpu::entry_points(gpu::SHADER_SOURCE)
+        .into_iter()
+        .map(JsValue::from_str)
+        .collect();
+    js_object(&[
+        ("source", gpu::SHADER_SOURCE.into()),
+        ("entryPoints", entry_points.into()),
+    ])
+    .into()
+}
+
+// parse a decimal start n
+fn parse_n(start_n: &str) -> Result<u128, CollatzError> {
+    start_n
+        .trim()
+        .parse::<u128>()
+        .map_err(|_| CollatzError::InvalidNumber)
+}
+
+// a start n as 16 little-endian bytes, the same order as `u128::to_le_bytes`
+fn parse_n_bytes(start_bytes: &[u8]) -> Result<u128, CollatzError> {
+    let bytes: [u8; 16] = start_bytes
+        .try_into()
+        .map_err(|_| CollatzError::InvalidArgument("start_bytes must be exactly 16 bytes"))?;
+    Ok(u128::from_le_bytes(bytes))
+}
+
+// Check n..n + count is a non-empty range that fits in u128 and doesn't
+// include 0, before anything is dispatched for it
+fn check_range(n: u128, count: u64) -> Result<(), CollatzError> {
+    if n == 0 {
+        return Err(CollatzError::InvalidStart);
+    }
+    if count == 0 {
+        return Err(CollatzError::EmptyRange);
+    }
+    match n.checked_add(count as u128) {
+        Some(_) => Ok(()),
+        None => Err(CollatzError::RangeOverflow),
+    }
+}
+
+// The numbers n..n + count, erroring if the range runs past u128::MAX
+fn range_numbers(n: u128, count: u32) -> Result<Vec<u128>, CollatzError> {
+    check_range(n, count as u64)?;
+    Ok((n..n + count as u128).collect())
+}
+
+// start, start * factor, start * factor^2, ... up to and including max, or
+// until the next one would run past u128::MAX
+fn geometric_numbers(start: u128, max: u128, factor: u32) -> Result<Vec<u128>, CollatzError> {
+    if start == 0 {
+        return Err(CollatzError::InvalidStart);
+    }
+    if factor < 2 {
+        return Err(CollatzError::InvalidArgument("factor must be at least 2"));
+    }
+    if max < start {
+        return Err(CollatzError::EmptyRange);
+    }
+    Ok(
+        std::iter::successors(Some(start), |&n| n.checked_mul(factor as u128))
+            .take_while(|&n| n <= max)
+            .collect(),
+    )
+}
+
+// Running records over the results seen so far
+#[derive(Default)]
+struct Summary {
+    // number reached, n
+    highest_reached: (u128, u128),
+    overflows: u32,
+    // steps, n
+    most_steps: (u32, u128),
+    count: u32,
+}
+
+impl Summary {
+    fn add(&mut self, results: &[CollatzResult]) {
+        for result in results {
+            if matches!(result.outcome(), Outcome::Overflowed | Outcome::Saturated) {
+                self.overflows += 1;
+            }
+
+            if self.highest_reached.0 < result.max() {
+                self.highest_reached.0 = result.max();
+                self.highest_reached.1 = result.n();
+            }
+
+            if self.most_steps.0 < result.steps() {
+                self.most_steps.0 = result.steps();
+                self.most_steps.1 = result.n();
+            }
+        }
+        self.count += results.len() as u32;
+    }
+
+    fn to_words(&self) -> Vec<u32> {
+        let mut interesting_results: Vec<u32> = Vec::new();
+
+        // 0th word = overflows
+        interesting_results.push(self.overflows);
+        // 1,2,3,4 are max reached
+        interesting_results.extend_from_slice(&u128_to_u32_array(self.highest_reached.0));
+        // 5,6,7,8 are n for max reached
+        interesting_results.extend_from_slice(&u128_to_u32_array(self.highest_reached.1));
+        // 9 is steps
+        interesting_results.push(self.most_steps.0);
+        // 10, 11, 12, 13 is n for steps
+        interesting_results.extend_from_slice(&u128_to_u32_array(self.most_steps.1));
+        // 14 is range
+        interesting_results.push(self.count);
+
+        interesting_results
+    }
+}
+
 #[wasm_bindgen]
 pub async fn do_gpu_collatz(start_n: String) -> Result<Vec<u32>, JsValue> {
     console_log!("hello here");
 
-    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-        backends: wgpu::Backends::BROWSER_WEBGPU,
-        ..Default::default()
-    });
-    console_log!("made it here 0");
+    let test_numbers = range_numbers(parse_n(&start_n)?, RANGE)?;
+    let session = session().await?;
 
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            force_fallback_adapter: false,
-            compatible_surface: None,
+    console_log!("made it here 2");
+
+    let results = session.run(&test_numbers).await?;
+
+    let mut summary = Summary::default();
+    summary.add(&results);
+    let interesting_results = summary.to_words();
+
+    console_log!("{:?}", interesting_results);
+
+    Ok(interesting_results)
+}
+
+/// Every result for `count` numbers from `start_n`
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_results(
+    start_n: String,
+    count: u32,
+) -> Result<Vec<CollatzResult>, JsValue> {
+    let numbers = range_numbers(parse_n(&start_n)?, count)?;
+    Ok(session().await?.run(&numbers).await?)
+}
+
+/// `do_gpu_collatz_results` with the start given as 16 little-endian bytes,
+/// least significant first, instead of a decimal string.
+///
+/// ```js
+/// const bytes = new Uint8Array(16);
+/// bytes[0] = 27;
+/// await do_gpu_collatz_bytes(bytes, 1); // the result for 27
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_bytes(
+    start_bytes: &[u8],
+    count: u32,
+) -> Result<Vec<CollatzResult>, JsValue> {
+    let numbers = range_numbers(parse_n_bytes(start_bytes)?, count)?;
+    Ok(session().await?.run(&numbers).await?)
+}
+
+/// Results for `start`, `start * factor`, `start * factor^2`, ... up to and
+/// including `max` (decimal strings), a logarithmic sample of a range far
+/// too large to run in full. A factor of 2 samples at every power-of-two
+/// multiple of `start`.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_log_sample(
+    start: String,
+    max: String,
+    factor: u32,
+) -> Result<Vec<CollatzResult>, JsValue> {
+    let numbers = geometric_numbers(parse_n(&start)?, parse_n(&max)?, factor)?;
+    Ok(session().await?.run(&numbers).await?)
+}
+
+/// The result for each of `numbers` (decimal strings), in the same order.
+///
+/// Repeated numbers are only computed once and their result copied to every
+/// position they appear at, so only the distinct numbers have to fit in a
+/// single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_list(numbers: Vec<String>) -> Result<Vec<CollatzResult>, JsValue> {
+    let numbers = numbers
+        .iter()
+        .map(|n| parse_n(n))
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.contains(&0) {
+        return Err(CollatzError::InvalidStart.into());
+    }
+    Ok(session().await?.run_distinct(&numbers).await?)
+}
+
+/// The result for each of `numbers` (decimal strings) in a `Map` keyed by
+/// the decimal string of its start, for inputs that aren't a contiguous
+/// range so callers don't have to track positions. Keys are normalised, so
+/// " 007" comes back as "7", and repeats collapse into one entry. Keys are
+/// in the order each number first appears.
+///
+/// ```js
+/// const m = await do_gpu_collatz_map(["27", "97", "27"]);
+/// m.size;             // 2
+/// m.get("97").steps;  // 118
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_map(numbers: Vec<String>) -> Result<js_sys::Map, JsValue> {
+    let numbers = numbers
+        .iter()
+        .map(|n| parse_n(n))
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.contains(&0) {
+        return Err(CollatzError::InvalidStart.into());
+    }
+    let results = session().await?.run_distinct(&numbers).await?;
+
+    let map = js_sys::Map::new();
+    for result in results {
+        map.set(&result.start_string().into(), &result.into());
+    }
+    Ok(map)
+}
+
+/// The result for the single number `n`.
+///
+/// This is computed on the CPU rather than the GPU: one trajectory is
+/// sequential, so setting up a dispatch for it costs far more than just
+/// running it. The result is identical to the GPU's apart from `source`.
+#[wasm_bindgen]
+pub fn do_gpu_collatz_single(n: String) -> Result<CollatzResult, JsValue> {
+    Ok(cpu_collatz(parse_n(&n)?))
+}
+
+/// Every result for `count` numbers from `start`, run on a device and queue
+/// the caller already owns (e.g. an app that renders with wgpu too) instead
+/// of requesting a new one.
+///
+/// The pipeline is built on every call. Large counts are split into chunks
+/// of 100,000, since there is no session kept to calibrate a chunk size for.
+pub async fn gpu_collatz_on_device(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    start: u128,
+    count: u32,
+) -> Result<Vec<CollatzResult>, CollatzError> {
+    check_range(start, count as u64)?;
+
+    let session = session::CollatzSession::from_device(device.clone(), queue.clone());
+
+    let config = ON_DEVICE_CONFIG;
+    let mut results = Vec::with_capacity(scan::first_chunk_capacity(count as u64, &config));
+    scan::scan(&session, start, count as u64, &config, |chunk| {
+        results.extend_from_slice(chunk)
+    })
+    .await?;
+
+    Ok(results)
+}
+
+/// Same as `gpu_collatz_on_device`, but each chunk's results are written to
+/// `writer` in `format` as soon as they are read back instead of being
+/// collected, so memory use stays at a few chunks however large `count` is.
+///
+/// A failed chunk or write ends the stream with an error, and the writer
+/// then holds every result before that chunk.
+pub async fn gpu_collatz_to_writer<W: std::io::Write>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    writer: &mut W,
+    start: u128,
+    count: u64,
+    format: OutputFormat,
+) -> Result<(), CollatzError> {
+    check_range(start, count)?;
+
+    let session = session::CollatzSession::from_device(device.clone(), queue.clone());
+
+    writer
+        .write_all(format.header())
+        .map_err(CollatzError::Write)?;
+    let mut failure = None;
+    scan::scan_until(
+        &session,
+        start,
+        count,
+        &ON_DEVICE_CONFIG,
+        |chunk| match format.write_results(writer, chunk) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                failure = Some(CollatzError::Write(e));
+                ControlFlow::Break(())
+            }
+        },
+    )
+    .await?;
+
+    match failure {
+        Some(e) => Err(e),
+        None => writer.flush().map_err(CollatzError::Write),
+    }
+}
+
+/// Same computation as `do_gpu_collatz` but over `count` numbers, returning
+/// every result packed as described by `PACKED_RECORD_SIZE`.
+///
+/// In JS, read record `i` with a `DataView` at `i * 36`:
+/// `n = getBigUint64(0, true) | getBigUint64(8, true) << 64n`,
+/// `steps = getUint32(16, true)` and max the same way as n at offset 20.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_packed(
+    start_n: String,
+    count: u32,
+) -> Result<js_sys::ArrayBuffer, JsValue> {
+    let numbers = range_numbers(parse_n(&start_n)?, count)?;
+
+    let results = session().await?.run(&numbers).await?;
+
+    let packed = pack_results(&results);
+    Ok(js_sys::Uint8Array::from(packed.as_slice()).buffer())
+}
+
+/// Scan `count` numbers from `start_n` in chunks, returning the same summary
+/// words as `do_gpu_collatz` (word 14 is `count`).
+///
+/// Only `config.max_in_flight` chunks are on the GPU at once, which bounds
+/// memory use for ranges far larger than a single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_scan(
+    start_n: String,
+    count: u32,
+    config: Option<RunConfig>,
+) -> Result<Vec<u32>, JsValue> {
+    let start = parse_n(&start_n)?;
+    let config = config.unwrap_or_default();
+    config.check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut summary = Summary::default();
+    scan::scan(&session, start, count as u64, &config, |results| {
+        summary.add(results)
+    })
+    .await?;
+
+    Ok(summary.to_words())
+}
+
+/// Histogram of how far each trajectory climbs relative to its start,
+/// `max / n`, over `count` numbers from `start_n`.
+///
+/// Buckets are log-scale, `[1, 2)`, `[2, 4)`, ... with `buckets` of them and
+/// the last one catching everything above. Overflowed numbers are left out.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_ratios(
+    start_n: String,
+    count: u32,
+    buckets: u32,
+) -> Result<Histogram, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+    if buckets == 0 {
+        return Err(CollatzError::InvalidArgument("buckets must be at least 1").into());
+    }
+
+    let session = session().await?;
+
+    let mut histogram = analysis::ratio_histogram(buckets);
+    scan::scan(
+        &session,
+        start,
+        count as u64,
+        &RunConfig::default(),
+        |results| analysis::add_peak_ratios(&mut histogram, results),
+    )
+    .await?;
+
+    Ok(histogram)
+}
+
+/// Whether all `count` numbers from `start_n` reach 1 without overflowing
+/// u128 or hitting `MAX_STEPS`.
+///
+/// The check runs on the GPU and only one word per chunk is read back, and
+/// the scan stops at the first chunk with a failure in it. The failing
+/// number itself is given by `do_gpu_collatz_first_failure`.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_verify(start_n: String, count: u32) -> Result<bool, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let failure = quick::first_failure(&session, start, count as u64).await?;
+    Ok(failure.is_none())
+}
+
+/// The first of `count` numbers from `start_n` that doesn't reach 1, as a
+/// decimal string, or `undefined` if every one does. Runs the same check as
+/// `do_gpu_collatz_verify`.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_first_failure(
+    start_n: String,
+    count: u32,
+) -> Result<Option<String>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let failure = quick::first_failure(&session, start, count as u64).await?;
+    Ok(failure.map(|n| n.to_string()))
+}
+
+/// Sum of the step counts of `count` numbers from `start_n`, as a decimal
+/// string since it can go past what a JS number holds exactly.
+///
+/// Overflowed and capped numbers contribute the steps they got through.
+/// The sum is reduced on the GPU, so only one word per workgroup is read
+/// back.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_step_sum(start_n: String, count: u32) -> Result<String, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let total = quick::step_sum(&session, start, count as u64).await?;
+    Ok(total.to_string())
+}
+
+/// Run several ranges in one upload, the i-th range being `counts[i]`
+/// numbers from `starts[i]`. Returns an array with one array of results per
+/// range.
+///
+/// All the ranges together have to fit in a single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_ranges(
+    starts: Vec<String>,
+    counts: Vec<u32>,
+) -> Result<js_sys::Array, JsValue> {
+    if starts.len() != counts.len() {
+        return Err(
+            CollatzError::InvalidArgument("starts and counts must be the same length").into(),
+        );
+    }
+
+    let mut ranges = Vec::with_capacity(starts.len());
+    for (start_n, &count) in starts.iter().zip(&counts) {
+        let start = parse_n(start_n)?;
+        check_range(start, count as u64)?;
+        ranges.push((start, count));
+    }
+
+    let grouped = js_sys::Array::new();
+    for range in run_ranges(&*session().await?, &ranges).await? {
+        grouped.push(
+            &range
+                .into_iter()
+                .map(JsValue::from)
+                .collect::<js_sys::Array>(),
+        );
+    }
+
+    Ok(grouped)
+}
+
+// Results for each `(start, count)` range, run as one dispatch. The ranges
+// must already have been checked.
+async fn run_ranges(
+    session: &session::CollatzSession,
+    ranges: &[(u128, u32)],
+) -> Result<Vec<Vec<CollatzResult>>, CollatzError> {
+    // check the total before allocating anything for it
+    let total: u64 = ranges.iter().map(|&(_, count)| count as u64).sum();
+    let limit = gpu::max_numbers_per_dispatch(&session.device);
+    if total > limit {
+        return Err(CollatzError::TooManyNumbers {
+            count: total,
+            limit,
+        });
+    }
+
+    let numbers: Vec<u128> = ranges
+        .iter()
+        .flat_map(|&(start, count)| start..start + count as u128)
+        .collect();
+    let results = session.run(&numbers).await?;
+
+    let mut rest = results.as_slice();
+    Ok(ranges
+        .iter()
+        .map(|&(_, count)| {
+            let (range, tail) = rest.split_at(count as usize);
+            rest = tail;
+            range.to_vec()
         })
-        .await;
+        .collect())
+}
 
-    let adapter = match adapter {
-        Ok(a) => {
-            console_log!("Adapter found: {:?}", a.get_info().name);
-            a
-        }
-        Err(e) => {
-            console_log!(
-                "ERROR: No GPU adapter found. WebGPU may not be supported in this browser. {:?}",
-                e
-            );
-            return Err(JsValue::from_str(
-                "No GPU adapter found. Try Chrome WebGPU enabled. Safari Does not support WebGPU",
-            ));
-        }
+/// Count how `count` numbers from `start_n` ended, as
+/// `[converged, capped, overflowed, cycles, saturated]`.
+///
+/// Anything other than converged is either a bug or a genuinely
+/// interesting input.
+#[wasm_bindgen]
+pub async fn convergence_summary(start_n: String, count: u32) -> Result<Vec<u32>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut summary = ConvergenceSummary::default();
+    scan::scan(
+        &session,
+        start,
+        count as u64,
+        &RunConfig::default(),
+        |results| summary.add(results),
+    )
+    .await?;
+
+    Ok(vec![
+        summary.converged,
+        summary.capped,
+        summary.overflowed,
+        summary.cycles,
+        summary.saturated,
+    ])
+}
+
+/// How many of the peaks of `count` numbers from `start_n` start with each
+/// digit 1-9, for comparing against Benford's law. Index 0 is digit 1.
+#[wasm_bindgen]
+pub async fn leading_digit_distribution(start_n: String, count: u32) -> Result<Vec<u32>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut counts = [0; 9];
+    scan::scan(
+        &session,
+        start,
+        count as u64,
+        &RunConfig::default(),
+        |results| add_leading_digits(&mut counts, results),
+    )
+    .await?;
+
+    Ok(counts.to_vec())
+}
+
+/// The number out of `count` from `start_n` whose peak has the most bits
+/// over its start, ties going to the larger n. `undefined` if every number
+/// overflowed.
+///
+/// The search runs on the GPU, reading back one word per chunk, and only
+/// the winner is run again for its record at the end.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_max_growth(
+    start_n: String,
+    count: u32,
+) -> Result<Option<CollatzResult>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let Some(n) = quick::max_growth(&session, start, count as u64).await? else {
+        return Ok(None);
+    };
+    Ok(session.run(&[n]).await?.first().copied())
+}
+
+/// The first `max_bits` (1 to 128) bits of the parity vector of each of
+/// `count` numbers from `start_n`, see `ParityEncoding`.
+///
+/// All `count` numbers have to fit in a single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_encoding(
+    start_n: String,
+    count: u32,
+    max_bits: u32,
+) -> Result<Vec<ParityEncoding>, JsValue> {
+    if !(1..=128).contains(&max_bits) {
+        return Err(CollatzError::InvalidArgument("max_bits must be from 1 to 128").into());
+    }
+    let numbers = range_numbers(parse_n(&start_n)?, count)?;
+
+    let session = session().await?;
+
+    Ok(encoding::encode(&session, &numbers, max_bits).await?)
+}
+
+/// The numbers out of `count` from `start_n` that reach 1 in exactly `k`
+/// steps, ascending, as decimal strings
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_exact(
+    start_n: String,
+    count: u32,
+    k: u32,
+) -> Result<Vec<String>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut found = Vec::new();
+    scan::scan(
+        &session,
+        start,
+        count as u64,
+        &RunConfig::default(),
+        |results| found.extend(with_steps(results, k).map(|n| n.to_string())),
+    )
+    .await?;
+
+    Ok(found)
+}
+
+/// Every value in the trajectory of `n` computed on the GPU in full 128 bit
+/// precision, up to `max_len` values. See `Trajectory`.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_full_trajectory(
+    n: String,
+    max_len: u32,
+) -> Result<Trajectory, JsValue> {
+    let n = parse_n(&n)?;
+
+    let session = session().await?;
+
+    Ok(trajectory::trace(&session, n, max_len).await?)
+}
+
+/// Fraction of a random sample of `sample_size` numbers out of `count` from
+/// `start_n` that overflow u128, to check whether a range is in reach before
+/// scanning all of it.
+///
+/// Numbers are picked with replacement by a generator seeded with `seed`,
+/// so the same arguments always give the same estimate.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_overflow_rate(
+    start_n: String,
+    count: u32,
+    sample_size: u32,
+    seed: Option<u32>,
+) -> Result<f64, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+    if sample_size == 0 {
+        return Err(CollatzError::InvalidArgument("sample_size must be at least 1").into());
+    }
+
+    let session = session().await?;
+
+    let numbers = sample::sample_range(
+        start,
+        count,
+        sample_size,
+        seed.unwrap_or(DEFAULT_SEED) as u64,
+    );
+    let per_dispatch = gpu::max_numbers_per_dispatch(&session.device) as usize;
+    let mut summary = ConvergenceSummary::default();
+    for chunk in numbers.chunks(per_dispatch) {
+        summary.add(&session.run(chunk).await?);
+    }
+
+    Ok(summary.overflowed as f64 / sample_size as f64)
+}
+
+/// Every result for `count` numbers from `start_n`, giving up after about
+/// `timeout_ms` milliseconds.
+///
+/// Returns the results completed so far instead of an error when time runs
+/// out, so fewer than `count` results means it timed out. Time is checked
+/// between chunks, so it can overrun by up to one chunk.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_with_timeout(
+    start_n: String,
+    count: u32,
+    timeout_ms: f64,
+) -> Result<Vec<CollatzResult>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    Ok(session
+        .run_with_timeout(start, count as u64, timeout_ms)
+        .await?)
+}
+
+/// Step counts of `width * height` numbers from `start_n` as RGBA pixels,
+/// row by row, ready for a canvas:
+///
+/// ```js
+/// const rgba = await do_gpu_collatz_heatmap("1", 256, 256);
+/// ctx.putImageData(new ImageData(new Uint8ClampedArray(rgba.buffer), 256, 256), 0, 0);
+/// ```
+///
+/// Colors are scaled between the fewest and most steps in the grid, see
+/// `heatmap_rgba`.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_heatmap(
+    start_n: String,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, JsValue> {
+    let count = width
+        .checked_mul(height)
+        .ok_or(CollatzError::InvalidArgument(
+            "width * height must fit in a u32",
+        ))?;
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let config = RunConfig::default();
+    let mut steps = Vec::with_capacity(scan::first_chunk_capacity(count as u64, &config));
+    scan::scan(&session, start, count as u64, &config, |results| {
+        steps.extend(results.iter().map(|result| result.steps()))
+    })
+    .await?;
+
+    Ok(heatmap_rgba(&steps))
+}
+
+/// Variance of the step counts in each window of `window` consecutive
+/// numbers out of `count` from `start_n`, one per number, see
+/// `window_variance`. Windows near the end of the range are cut short.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_window_variance(
+    start_n: String,
+    count: u32,
+    window: u32,
+) -> Result<Vec<f64>, JsValue> {
+    if window == 0 {
+        return Err(CollatzError::InvalidArgument("window must be at least 1").into());
+    }
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+    let session = session().await?;
+
+    let config = RunConfig::default();
+    let mut steps = Vec::with_capacity(scan::first_chunk_capacity(count as u64, &config));
+    scan::scan(&session, start, count as u64, &config, |results| {
+        steps.extend(results.iter().map(|result| result.steps()))
+    })
+    .await?;
+
+    Ok(window_variance(&steps, window as usize))
+}
+
+/// The "hard" numbers out of `count` from `start_n`: the ones that neither
+/// drop below themselves nor reach 1 within `quick_steps` steps, as decimal
+/// strings. Numbers that overflow within the check count as hard.
+///
+/// Most numbers drop quickly (every even number does in one step), so this
+/// is a cheap filter for the ones worth a full run.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_hard_cases(
+    start_n: String,
+    count: u32,
+    quick_steps: u32,
+) -> Result<Vec<String>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+    if quick_steps == 0 {
+        return Err(CollatzError::InvalidArgument("quick_steps must be at least 1").into());
+    }
+
+    let session = session().await?;
+
+    let hard = quick::hard_cases(&session, start, count as u64, quick_steps).await?;
+    Ok(hard.iter().map(|n| n.to_string()).collect())
+}
+
+/// Results of a scan that kept going past failed chunks
+#[wasm_bindgen]
+pub struct RecoveredScan {
+    results: Vec<CollatzResult>,
+    failed: Vec<FailedRange>,
+}
+
+#[wasm_bindgen]
+impl RecoveredScan {
+    /// Every result that was read back, in scan order
+    #[wasm_bindgen(getter)]
+    pub fn results(&self) -> Vec<CollatzResult> {
+        self.results.clone()
+    }
+
+    /// The chunks that failed, to retry with another scan each
+    #[wasm_bindgen(getter)]
+    pub fn failed(&self) -> Vec<FailedRange> {
+        self.failed.clone()
+    }
+}
+
+/// Every result for `count` numbers from `start_n` like
+/// `do_gpu_collatz_scan`, except a chunk that fails to read back is skipped
+/// and reported in `failed` instead of failing the whole scan
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_scan_recovering(
+    start_n: String,
+    count: u32,
+    config: Option<RunConfig>,
+) -> Result<RecoveredScan, JsValue> {
+    let start = parse_n(&start_n)?;
+    let config = config.unwrap_or_default();
+    config.check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut results = Vec::with_capacity(scan::first_chunk_capacity(count as u64, &config));
+    let failed = scan::scan_recovering(&session, start, count as u64, &config, |chunk| {
+        results.extend_from_slice(chunk)
+    })
+    .await;
+
+    Ok(RecoveredScan { results, failed })
+}
+
+/// Results of a scan that stopped at its first failed chunk, keeping
+/// everything read back before it
+#[wasm_bindgen]
+pub struct PartialScan {
+    results: Vec<CollatzResult>,
+    error: Option<String>,
+}
+
+#[wasm_bindgen]
+impl PartialScan {
+    /// The results for the numbers before the failed chunk, in scan order
+    #[wasm_bindgen(getter)]
+    pub fn results(&self) -> Vec<CollatzResult> {
+        self.results.clone()
+    }
+
+    /// How many numbers from the start completed, the length of `results`.
+    /// Equal to `count` unless a chunk failed.
+    #[wasm_bindgen(getter = completedThrough)]
+    pub fn completed_through(&self) -> u32 {
+        // at most count, so it fits
+        self.results.len() as u32
+    }
+
+    /// Why the scan stopped early, `undefined` if it finished
+    #[wasm_bindgen(getter)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+/// Every result for `count` numbers from `start_n` like
+/// `do_gpu_collatz_scan`, except that when a chunk fails (the device is
+/// lost, a mapping fails) the scan stops there and returns what it already
+/// had instead of nothing. Resume with a scan from `start_n` plus
+/// `completedThrough`, or minus it for a descending one.
+///
+/// Chunks still in flight behind the failed one are dropped, use
+/// `do_gpu_collatz_scan_recovering` to carry on past failures instead.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_partial(
+    start_n: String,
+    count: u32,
+    config: Option<RunConfig>,
+) -> Result<PartialScan, JsValue> {
+    let start = parse_n(&start_n)?;
+    let config = config.unwrap_or_default();
+    config.check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let (results, error) = scan::scan_partial(&session, start, count as u64, &config).await;
+    Ok(PartialScan {
+        results,
+        error: error.map(|e| e.to_string()),
+    })
+}
+
+/// The number that takes the most steps in a range, with its trajectory if
+/// one was asked for
+#[wasm_bindgen]
+pub struct Champion {
+    result: CollatzResult,
+    trajectory: Option<Trajectory>,
+}
+
+#[wasm_bindgen]
+impl Champion {
+    #[wasm_bindgen(getter)]
+    pub fn result(&self) -> CollatzResult {
+        self.result
+    }
+
+    /// Every value from the champion's n down to 1, `undefined` unless a
+    /// `trajectory_len` was passed. Check `truncated` when it's shorter
+    /// than `result.steps + 1` values.
+    #[wasm_bindgen(getter)]
+    pub fn trajectory(&self) -> Option<Trajectory> {
+        self.trajectory.clone()
+    }
+}
+
+// Scan the range in order and stop at the first chunk holding a result with
+// at least `min_steps` steps, without reading back any chunk after it
+async fn find_first_with_steps(
+    session: &session::CollatzSession,
+    start: u128,
+    count: u32,
+    min_steps: u32,
+) -> Result<Option<CollatzResult>, CollatzError> {
+    let mut found = None;
+    scan::scan_until(
+        session,
+        start,
+        count as u64,
+        &RunConfig::default(),
+        |results| match first_with_steps(results, min_steps) {
+            Some(&result) => {
+                found = Some(result);
+                ControlFlow::Break(())
+            }
+            None => ControlFlow::Continue(()),
+        },
+    )
+    .await?;
+    Ok(found)
+}
+
+/// The smallest number out of `count` from `start_n` that takes at least
+/// `min_steps` steps to reach 1, `undefined` if none does. Chunks after the
+/// one it is found in are never read back, so this stops early where a
+/// full scan wouldn't.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_first_with_steps(
+    start_n: String,
+    count: u32,
+    min_steps: u32,
+) -> Result<Option<CollatzResult>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+    let session = session().await?;
+    Ok(find_first_with_steps(&session, start, count, min_steps).await?)
+}
+
+// Find the champion of the range on the GPU, then trace it with room for up to
+// `trajectory_len` values
+async fn find_champion(
+    session: &session::CollatzSession,
+    start: u128,
+    count: u32,
+    trajectory_len: Option<u32>,
+) -> Result<Option<Champion>, CollatzError> {
+    // only the winner.s record is read back, not every number.s
+    let Some((n, _)) = quick::champion_in(session, start, count as u128).await? else {
+        return Ok(None);
     };
-    console_log!("made it here 1");
-
-    let (device, queue) = match adapter
-        .request_device(&wgpu::DeviceDescriptor::default())
-        .await
-    {
-        Ok(a) => a,
-        Err(e) => {
-            console_log!("{e}");
-            return Err(JsValue::from_str(&format!("{e}")));
+    let Some(&result) = session.run(&[n]).await?.first() else {
+        return Ok(None);
+    };
+    let trajectory = match trajectory_len {
+        // the whole trajectory is steps + 1 values, no point allocating more
+        Some(max_len) => {
+            let max_len = max_len.min(result.steps().saturating_add(1));
+            Some(trajectory::trace(session, result.n(), max_len).await?)
         }
+        None => None,
     };
+    Ok(Some(Champion { result, trajectory }))
+}
 
-    console_log!("made it here 2");
+/// The number out of `count` from `start_n` that takes the most steps to
+/// reach 1, ties going to the smaller n. `undefined` if none converged.
+///
+/// Pass `trajectory_len` to get the champion's trajectory in the same call,
+/// at most that many values with `truncated` set if it goes on further.
+///
+/// ```js
+/// const c = await do_gpu_collatz_champion("1", 30, 1000);
+/// c.result.start;         // "27"
+/// c.trajectory.values[0]; // "27", down to "1" 111 steps later
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_champion(
+    start_n: String,
+    count: u32,
+    trajectory_len: Option<u32>,
+) -> Result<Option<Champion>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
 
-    // parse start n
-    let n = if let Ok(n) = start_n.parse::<u128>() {
-        n
-    } else {
-        return Err(JsValue::from_str("Could not parse n"));
-    };
+    let session = session().await?;
 
-    let test_numbers: Vec<u128> = (n..n + RANGE as u128).collect();
+    Ok(find_champion(&session, start, count, trajectory_len).await?)
+}
 
-    // Convert to GPU format (4 × u32 per number)
-    let input_data: Vec<u8> = test_numbers
-        .iter()
-        .flat_map(|&n| u32_array_to_bytes(&u128_to_u32_array(n)))
-        .collect();
+/// The first number after `n` that takes more steps than `n` to reach 1,
+/// scanning upward in chunks. `undefined` if none turned up within about
+/// `timeout_ms` milliseconds, time is checked between chunks.
+///
+/// Only numbers that converge count, and `n` itself has to converge.
+#[wasm_bindgen]
+pub async fn next_record_after(
+    n: String,
+    timeout_ms: f64,
+) -> Result<Option<CollatzResult>, JsValue> {
+    let n = parse_n(&n)?;
+    let target = cpu_collatz(n);
+    if target.outcome() != Outcome::Converged {
+        return Err(CollatzError::InvalidArgument(
+            "n has to converge to have a step count to beat",
+        )
+        .into());
+    }
+    let start = n.checked_add(1).ok_or(CollatzError::RangeOverflow)?;
+    let count = (u128::MAX - start).min(u64::MAX as u128) as u64;
 
-    let input_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Input Buffer"),
-        contents: &input_data,
-        usage: wgpu::BufferUsages::STORAGE,
-    });
+    let session = session().await?;
 
-    // Output: Each result has steps (u32=4 bytes) + max (4×u32=16 bytes) = 20 bytes, but align to 32 bytes
-    let output_size = test_numbers.len() * 32; // Struct padding for alignment
+    Ok(session
+        .find_with_timeout(start, count, timeout_ms, |result| {
+            result.outcome() == Outcome::Converged && result.steps() > target.steps()
+        })
+        .await?)
+}
 
-    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Output Buffer"),
-        size: output_size as u64,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    });
+/// For each of `count` numbers from `start_n`, the step at which its
+/// trajectory first passes through `target`, or -1 if it reaches 1 (or
+/// overflows) without doing so. A number is at step 0 of its own trajectory.
+///
+/// ```js
+/// const steps = await do_gpu_collatz_reaches("3", 3, "16");
+/// steps; // Int32Array [3, -1, 1], 3 -> 10 -> 5 -> 16 and 5 -> 16
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_reaches(
+    start_n: String,
+    count: u32,
+    target: String,
+) -> Result<Vec<i32>, JsValue> {
+    let start = parse_n(&start_n)?;
+    let target = parse_n(&target)?;
+    check_range(start, count as u64)?;
+    if target == 0 {
+        return Err(CollatzError::InvalidArgument("target must be at least 1").into());
+    }
 
-    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Staging Buffer"),
-        size: output_size as u64,
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
+    let session = session().await?;
 
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some("Collatz Shader"),
-        source: wgpu::ShaderSource::Wgsl(include_str!("add.wgsl").into()),
-    });
+    Ok(reach::reach_steps(&session, start, count as u64, target).await?)
+}
 
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: Some("Compute Pipeline"),
-        layout: None,
-        module: &shader,
-        entry_point: Some("main"),
-        compilation_options: wgpu::PipelineCompilationOptions::default(),
-        cache: None
-    });
+/// For each of `count` numbers from `start_n`, the step at which its
+/// trajectory first goes above `ceiling`, or -1 if it reaches 1 without
+/// doing so. A start above the ceiling is at step 0, and a 3n + 1 that
+/// overflows u128 counts as going above it. Trajectories stop as soon as
+/// they're past the ceiling, so a low one is much cheaper than full runs.
+///
+/// ```js
+/// const steps = await do_gpu_collatz_until_exceeds("3", 3, "10");
+/// steps; // Int32Array [3, -1, 1], 3 -> 10 -> 5 -> 16 and 5 -> 16
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_until_exceeds(
+    start_n: String,
+    count: u32,
+    ceiling: String,
+) -> Result<Vec<i32>, JsValue> {
+    let start = parse_n(&start_n)?;
+    let ceiling = parse_n(&ceiling)?;
+    check_range(start, count as u64)?;
 
-    let bind_group_layout = compute_pipeline.get_bind_group_layout(0);
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: input_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: output_buffer.as_entire_binding(),
-            },
-        ],
-        label: Some("Bind Group"),
-    });
+    let session = session().await?;
 
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("Compute Encoder"),
-    });
-    {
-        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Compute Pass"),
-            timestamp_writes: None,
-        });
-        cpass.set_pipeline(&compute_pipeline);
-        cpass.set_bind_group(0, &bind_group, &[]);
-        // Dispatch enough workgroups to cover all input numbers
-        let workgroup_size = 64;
-        let num_workgroups = (test_numbers.len() as u32 + workgroup_size - 1) / workgroup_size;
-        cpass.dispatch_workgroups(num_workgroups, 1, 1);
-    }
-    encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging_buffer, 0, output_size as u64);
-    queue.submit(Some(encoder.finish()));
-
-    let buffer_slice = staging_buffer.slice(..);
-
-    // In WASM, we need to use a channel to properly await the buffer mapping
-    let (sender, receiver) = flume::bounded(1);
-    buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-        let _ = sender.send(result);
-    });
+    Ok(reach::exceed_steps(&session, start, count as u64, ceiling).await?)
+}
 
-    // Poll the device until the buffer is mapped
-    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+/// The `peak_count` (1 to `MAX_PEAKS`) highest peaks of each of `count`
+/// numbers from `start_n`, see `TopPeaks`.
+///
+/// All `count` numbers have to fit in a single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_top_peaks(
+    start_n: String,
+    count: u32,
+    peak_count: u32,
+) -> Result<Vec<TopPeaks>, JsValue> {
+    if !(1..=MAX_PEAKS).contains(&peak_count) {
+        return Err(CollatzError::InvalidArgument(peaks::PEAK_COUNT_ERROR).into());
+    }
+    let numbers = range_numbers(parse_n(&start_n)?, count)?;
 
-    // Wait for the mapping to complete
-    receiver
-        .recv_async()
-        .await
-        .map_err(|e| JsValue::from_str(&format!("Channel error: {}", e)))?
-        .map_err(|e| JsValue::from_str(&format!("Buffer mapping failed: {:?}", e)))?;
+    let session = session().await?;
 
-    let data = buffer_slice.get_mapped_range();
-    let results: &[u32] = bytemuck::cast_slice(&data);
+    Ok(peaks::top_peaks(&session, &numbers, peak_count).await?)
+}
 
-    // number reached, n
-    let mut highest_reached: (u128, u128) = (0, 0);
-    let mut overflows: u32 = 0;
-    // steps, n
-    let mut most_steps: (u32, u128) = (0, 0);
-
-    for (i, &n) in test_numbers.iter().enumerate() {
-        let offset = i * 8; // 32 bytes per result / 4 bytes per u32 = 8 u32s
-        let steps = results[offset];
-        let max_parts = [
-            results[offset + 1],
-            results[offset + 2],
-            results[offset + 3],
-            results[offset + 4],
-        ];
-        let max_value = u32_array_to_u128(&max_parts);
+/// Every number that reaches `value` within `depth` steps, as ascending
+/// decimal strings. Built on the CPU by walking the tree backwards from
+/// `value`: n always comes from 2n, and from (n - 1) / 3 when that's an odd
+/// integer. `value` itself is left out, even when it's on a cycle.
+///
+/// Errors rather than returning more than 2^20 numbers, which from 1 takes
+/// a depth of 55.
+///
+/// ```js
+/// do_collatz_predecessors("16", 2); // ["5", "10", "32", "64"]
+/// ```
+#[wasm_bindgen]
+pub fn do_collatz_predecessors(value: String, depth: u32) -> Result<Vec<String>, JsValue> {
+    let ancestors = cpu::predecessors(parse_n(&value)?, depth)?;
+    Ok(ancestors.iter().map(|n| n.to_string()).collect())
+}
+
+/// Parity vector of `n` under the classic map: one bit per step, 1 where
+/// the value the step starts from is odd, from n itself up to the step that
+/// reaches 1. Computed on the CPU like `do_gpu_collatz_single`.
+///
+/// `{ bits, length, overflowed }`: `bits` is a Uint32Array with bit i of
+/// the vector at bit `i % 32` of word `i / 32`, `length` the number of
+/// bits. If 3n + 1 overflows, `overflowed` is true and the vector stops
+/// before that step. A `length` of `MAX_STEPS` means it was cut off there.
+///
+/// ```js
+/// const p = do_gpu_collatz_parity("3");
+/// p.length;  // 7, 3 -> 10 -> 5 -> 16 -> 8 -> 4 -> 2 -> 1
+/// p.bits[0]; // 5, 0b0000101: 3 and 5 are odd
+/// ```
+#[wasm_bindgen]
+pub fn do_gpu_collatz_parity(n: String) -> Result<JsValue, JsValue> {
+    let n = parse_n(&n)?;
+    if n == 0 {
+        return Err(CollatzError::InvalidStart.into());
+    }
+    let (bits, length, overflowed) = cpu::parity_vector(n);
+
+    Ok(js_object(&[
+        ("bits", js_sys::Uint32Array::from(bits.as_slice()).into()),
+        ("length", length.into()),
+        ("overflowed", overflowed.into()),
+    ])
+    .into())
+}
+
+/// Report card for one number, computed on the CPU so it doesn't wait on
+/// the GPU. Every field matches what the batch kernel gives for n.
+///
+/// `{ start, steps, peak, evenSteps, oddSteps, glide, stoppingTime,
+/// overflowed }` with start and peak as decimal strings. `glide` is the
+/// steps until the first value below n and `stoppingTime` the same count
+/// under the shortcut map `(3n + 1) / 2`, both `undefined` if the trajectory
+/// overflowed first. peak is "0" for an overflowed trajectory, like max.
+#[wasm_bindgen]
+pub fn do_gpu_collatz_report(n: String) -> Result<JsValue, JsValue> {
+    let n = parse_n(&n)?;
+    let result = cpu_collatz(n);
+    let dropped = cpu::drop_below(n);
+
+    Ok(js_object(&[
+        ("start", result.start_string().into()),
+        ("steps", result.steps().into()),
+        ("peak", result.max_string().into()),
+        ("evenSteps", result.steps_shortcut().into()),
+        (
+            "oddSteps",
+            result
+                .steps_shortcut()
+                .map(|even| result.steps() - even)
+                .into(),
+        ),
+        ("glide", dropped.map(|(steps, _)| steps).into()),
+        ("stoppingTime", dropped.map(|(_, halvings)| halvings).into()),
+        (
+            "overflowed",
+            (result.outcome() == Outcome::Overflowed).into(),
+        ),
+    ])
+    .into())
+}
+
+/// The step counts of `count` numbers from `start_n` as a `Float64Array`,
+/// for charting libraries that want floats. `steps[i]` is for
+/// `start_n + i`. Only the steps are kept, peaks and outcomes are dropped,
+/// use `do_gpu_collatz_scan` for those.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_steps_f64(
+    start_n: String,
+    count: u32,
+) -> Result<js_sys::Float64Array, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let config = RunConfig::default();
+    let mut steps = Vec::with_capacity(scan::first_chunk_capacity(count as u64, &config));
+    scan::scan(&session, start, count as u64, &config, |results| {
+        steps.extend(steps_f64(results))
+    })
+    .await?;
+
+    Ok(js_sys::Float64Array::from(steps.as_slice()))
+}
 
-        if max_value == 0 {
-            overflows += 1;
+/// `{ xs, ys }` for `count` numbers from `start_n`, ready to hand to a
+/// plotting library: `xs` is a `Float64Array` of n and `ys` a `Uint32Array`
+/// of steps, with `ys[i]` belonging to `xs[i]`.
+///
+/// n above 2^53 can't be held exactly in an f64 and is rounded to the
+/// nearest one, so neighbouring numbers that large can share an x.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_scatter(start_n: String, count: u32) -> Result<JsValue, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let config = RunConfig::default();
+    let capacity = scan::first_chunk_capacity(count as u64, &config);
+    let (mut xs, mut ys) = (Vec::with_capacity(capacity), Vec::with_capacity(capacity));
+    scan::scan(&session, start, count as u64, &config, |results| {
+        let (chunk_xs, chunk_ys) = scatter_points(results);
+        xs.extend(chunk_xs);
+        ys.extend(chunk_ys);
+    })
+    .await?;
+
+    Ok(js_object(&[
+        ("xs", js_sys::Float64Array::from(xs.as_slice()).into()),
+        ("ys", js_sys::Uint32Array::from(ys.as_slice()).into()),
+    ])
+    .into())
+}
+
+/// Tail and cycle length of each of `count` numbers from `start_n` under the
+/// Collatz map mod `m` (1 to `MAX_MODULUS`), see `ModOrbit`.
+///
+/// All `count` numbers have to fit in a single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_mod(
+    start_n: String,
+    count: u32,
+    m: u32,
+) -> Result<Vec<ModOrbit>, JsValue> {
+    if !(1..=MAX_MODULUS).contains(&m) {
+        return Err(CollatzError::InvalidArgument("m must be from 1 to 65536").into());
+    }
+    let numbers = range_numbers(parse_n(&start_n)?, count)?;
+
+    let session = session().await?;
+
+    Ok(modular::mod_orbits(&session, &numbers, m).await?)
+}
+
+/// The numbers out of `count` from `start_n` that set a new delay record,
+/// taking more steps to reach 1 than every number before them, as decimal
+/// strings.
+///
+/// Numbers below `start_n` aren't run, so pass the record they set as
+/// `prior_steps` to extend a known table. Leave it out when starting from 1.
+///
+/// ```js
+/// await do_gpu_collatz_delay_records("1", 30); // ["1", "2", "3", "6", "7", "9", "18", "25", "27"]
+/// await do_gpu_collatz_delay_records("28", 50, 111); // ["54", "73"]
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_delay_records(
+    start_n: String,
+    count: u32,
+    prior_steps: Option<u32>,
+) -> Result<Vec<String>, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut best = prior_steps;
+    let mut records = Vec::new();
+    scan::scan(
+        &session,
+        start,
+        count as u64,
+        &RunConfig::default(),
+        |results| records.extend(delay_records(&mut best, results)),
+    )
+    .await?;
+
+    Ok(records.iter().map(|n| n.to_string()).collect())
+}
+
+/// `count` numbers from `start_n` scanned with `config`, exported together
+/// with the config as one file's worth of bytes, see `export_sweep`
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_export(
+    start_n: String,
+    count: u32,
+    config: Option<RunConfig>,
+) -> Result<Vec<u8>, JsValue> {
+    let start = parse_n(&start_n)?;
+    let config = config.unwrap_or_default();
+    config.check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    let mut results = Vec::with_capacity(scan::first_chunk_capacity(count as u64, &config));
+    scan::scan(&session, start, count as u64, &config, |chunk| {
+        results.extend_from_slice(chunk)
+    })
+    .await?;
+
+    Ok(export_sweep(&config, &results))
+}
+
+/// Read back the bytes from `do_gpu_collatz_export`
+#[wasm_bindgen(js_name = importSweep)]
+pub fn import_sweep_js(bytes: &[u8]) -> Result<SweepArchive, JsValue> {
+    Ok(import_sweep(bytes)?)
+}
+
+/// How many of `count` numbers from `start_n` take more than `k` steps to
+/// reach 1, without reading back any per-number results. Numbers that
+/// overflow only count if they had already gone past `k` steps.
+#[wasm_bindgen]
+pub async fn count_above(start_n: String, count: u32, k: u32) -> Result<u32, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+
+    let session = session().await?;
+
+    // at most count, so it fits
+    Ok(quick::count_above(&session, start, count as u64, k).await? as u32)
+}
+
+/// The number below `bound` that takes the most steps to reach 1, ties
+/// going to the smaller n, or `undefined` if none of them converged.
+///
+/// Sweeps 1 up to `bound` in chunks, each one reduced on the GPU so only
+/// one record-holder per 32768 numbers is read back instead of every
+/// result. The winner is then run again for its record.
+///
+/// ```js
+/// const r = await do_gpu_collatz_champion_below("100");
+/// r.start; // "97"
+/// r.steps; // 118
+/// ```
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_champion_below(
+    bound: String,
+) -> Result<Option<CollatzResult>, JsValue> {
+    let bound = parse_n(&bound)?;
+    if bound < 2 {
+        return Err(CollatzError::InvalidArgument("bound must be at least 2").into());
+    }
+
+    let session = session().await?;
+
+    Ok(champion_result(&session, bound).await?)
+}
+
+// `quick::champion_below`, with the winner run again for its record
+async fn champion_result(
+    session: &session::CollatzSession,
+    bound: u128,
+) -> Result<Option<CollatzResult>, CollatzError> {
+    match quick::champion_below(session, bound).await? {
+        Some((n, _)) => Ok(session.run(&[n]).await?.first().copied()),
+        None => Ok(None),
+    }
+}
+
+/// Estimated step counts for `count` numbers from `start_n`, which can be
+/// far past u128 (up to about 1.8e308). Experimental and approximate: this
+/// is for ranking candidates before checking them exactly, never for
+/// verifying anything.
+///
+/// WGSL has no f64, so a separate shader follows each number's exact low 64
+/// bits for as long as they decide the parity and then adds the average
+/// number of steps for a value of the size it has reached. Numbers whose
+/// trajectory stays below 2^63 come out exact. A start too large for an f64
+/// is an error rather than infinite estimates.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_approx(start_n: String, count: u32) -> Result<Vec<f32>, JsValue> {
+    let start = approx::ApproxStart::parse(&start_n)?;
+    if count == 0 {
+        return Err(CollatzError::EmptyRange.into());
+    }
+
+    let session = session().await?;
+    Ok(approx::approx_steps(&session, &start, count as u64).await?)
+}
+
+/// Which known cycle on Z each of `count` signed numbers from `start` ends
+/// in, and after how many steps, see `SignedOrbit`. `start` is a decimal
+/// i128 and may be negative.
+///
+/// All `count` numbers have to fit in a single dispatch.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_signed(start: String, count: u32) -> Result<Vec<SignedOrbit>, JsValue> {
+    let first = start
+        .trim()
+        .parse::<i128>()
+        .map_err(|_| CollatzError::InvalidNumber)?;
+    if count == 0 {
+        return Err(CollatzError::EmptyRange.into());
+    }
+    if first.checked_add(count as i128).is_none() {
+        return Err(CollatzError::InvalidArgument("Range runs past the largest i128").into());
+    }
+    let numbers: Vec<i128> = (first..first + count as i128).collect();
+
+    let session = session().await?;
+
+    Ok(signed::signed_orbits(&session, &numbers).await?)
+}
+
+/// Distribution of dropping times, the steps until the value first falls
+/// below the start, over `count` numbers from `start_n`. Each number's time
+/// is found and binned on the GPU.
+///
+/// `{ counts, noDrop, overflowed }`: `counts` is a Uint32Array of
+/// `max_bin + 1` bins where `counts[t]` is how many numbers drop after
+/// exactly t steps and the last bin also holds every time above `max_bin`.
+/// `noDrop` counts numbers that never drop (only 1, or any that hit
+/// `MAX_STEPS`) and `overflowed` those that overflowed before dropping.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_dropping_histogram(
+    start_n: String,
+    count: u32,
+    max_bin: u32,
+) -> Result<JsValue, JsValue> {
+    let start = parse_n(&start_n)?;
+    check_range(start, count as u64)?;
+    if max_bin > MAX_STEPS {
+        return Err(CollatzError::InvalidArgument("max_bin must be at most MAX_STEPS").into());
+    }
+
+    let session = session().await?;
+
+    // every bin is at most count, so they all fit
+    let bins: Vec<u32> = quick::dropping_histogram(&session, start, count as u64, max_bin)
+        .await?
+        .into_iter()
+        .map(|count| count as u32)
+        .collect();
+    let (counts, special) = bins.split_at(max_bin as usize + 1);
+
+    Ok(js_object(&[
+        ("counts", js_sys::Uint32Array::from(counts).into()),
+        ("noDrop", special[0].into()),
+        ("overflowed", special[1].into()),
+    ])
+    .into())
+}
+
+/// Results for `sample_count` numbers picked uniformly, with replacement,
+/// from the `range_size` numbers starting at `range_start`, for ranges far
+/// too large to sweep. Both are decimal strings so the range can span
+/// almost all of u128. Results come back in the order the numbers were
+/// picked.
+///
+/// The picks only depend on the arguments, so the same `seed` (a BigInt in
+/// JS) always samples the same numbers.
+#[wasm_bindgen]
+pub async fn do_gpu_collatz_sample(
+    range_start: String,
+    range_size: String,
+    sample_count: u32,
+    seed: u64,
+) -> Result<Vec<CollatzResult>, JsValue> {
+    let start = parse_n(&range_start)?;
+    let size = parse_n(&range_size)?;
+    if start == 0 {
+        return Err(CollatzError::InvalidStart.into());
+    }
+    if size == 0 || sample_count == 0 {
+        return Err(CollatzError::EmptyRange.into());
+    }
+    if start.checked_add(size).is_none() {
+        return Err(CollatzError::RangeOverflow.into());
+    }
+
+    let session = session().await?;
+
+    let numbers = sample::sample_big_range(start, size, sample_count, seed);
+    let per_dispatch = gpu::max_numbers_per_dispatch(&session.device) as usize;
+    let mut results = Vec::with_capacity(numbers.len());
+    for chunk in numbers.chunks(per_dispatch) {
+        results.extend(session.run(chunk).await?);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::test_session;
+
+    // The exact bytes the input buffer holds for the decimal number `s`
+    fn decimal_to_input_bytes(s: &str) -> Vec<u8> {
+        input_bytes(s.trim().parse().unwrap()).to_vec()
+    }
+
+    #[test]
+    fn input_bytes_layout() {
+        assert_eq!(decimal_to_input_bytes("0"), [0; 16]);
+        let mut one = [0; 16];
+        one[0] = 1;
+        assert_eq!(decimal_to_input_bytes(" 1 "), one);
+        // limb k holds bits 32k..32k + 32, each limb little-endian
+        let n: u128 = 0x0000000d_0000000c_0000000b_0000000a;
+        let bytes = decimal_to_input_bytes(&n.to_string());
+        assert_eq!(bytes, [10, 0, 0, 0, 11, 0, 0, 0, 12, 0, 0, 0, 13, 0, 0, 0]);
+        assert_eq!(decimal_to_input_bytes(&u128::MAX.to_string()), [0xff; 16]);
+    }
+
+    #[test]
+    fn summary_skips_powers_of_two() {
+        // every power of two up to 2^39 takes at most 39 steps, 97 takes 118
+        let mut numbers: Vec<u128> = (0..20).map(|k| 1 << k).collect();
+        numbers.push(97);
+        numbers.extend((20..40).map(|k| 1u128 << k));
+        let check = |results: &[CollatzResult]| {
+            let mut summary = Summary::default();
+            summary.add(results);
+            assert_eq!(summary.most_steps, (118, 97));
+            let words = summary.to_words();
+            assert_eq!(words[9], 118);
+            assert_eq!(u32_array_to_u128(words[10..14].try_into().unwrap()), 97);
+        };
+        check(&numbers.iter().map(|&n| cpu_collatz(n)).collect::<Vec<_>>());
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        check(&pollster::block_on(session.run(&numbers)).unwrap());
+    }
+
+    #[test]
+    fn ranges_match_separate_runs() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let ranges = [
+            (1, 100),
+            (1 << 100, 7),
+            (27, 1),
+            (u128::MAX - 10, 10),
+            (500, 300),
+        ];
+        let got = pollster::block_on(run_ranges(&session, &ranges)).unwrap();
+        assert_eq!(got.len(), ranges.len());
+        for (results, &(start, count)) in got.iter().zip(&ranges) {
+            let numbers: Vec<u128> = (start..start + count as u128).collect();
+            assert_eq!(results, &pollster::block_on(session.run(&numbers)).unwrap());
         }
+    }
 
-        if highest_reached.0 < max_value {
-            highest_reached.0 = max_value;
-            highest_reached.1 = n;
+    #[test]
+    fn external_device_matches_session() {
+        let Some((device, queue)) = gpu::test_device() else {
+            return;
+        };
+        let got = pollster::block_on(gpu_collatz_on_device(&device, &queue, 1000, 5000)).unwrap();
+        let session = session::CollatzSession::from_device(device.clone(), queue.clone());
+        let want = pollster::block_on(session.run(&(1000..6000).collect::<Vec<_>>())).unwrap();
+        assert_eq!(got, want);
+        let empty = pollster::block_on(gpu_collatz_on_device(&device, &queue, 1, 0));
+        assert!(matches!(empty, Err(CollatzError::EmptyRange)));
+    }
+
+    #[test]
+    fn zero_start_rejected() {
+        assert!(matches!(
+            range_numbers(0, 10),
+            Err(CollatzError::InvalidStart)
+        ));
+        assert!(matches!(check_range(0, 0), Err(CollatzError::InvalidStart)));
+        assert!(range_numbers(1, 10).is_ok());
+        let descending = RunConfig {
+            descending: true,
+            ..RunConfig::DEFAULT
+        };
+        assert!(matches!(
+            descending.check_range(0, 1),
+            Err(CollatzError::InvalidStart)
+        ));
+        assert!(descending.check_range(10, 10).is_ok());
+    }
+
+    #[test]
+    fn map_keys_cover_inputs() {
+        let inputs = ["27", " 007", "97", "27", "1000000007"];
+        let numbers: Vec<u128> = inputs.iter().map(|n| parse_n(n).unwrap()).collect();
+        assert_eq!(cpu_collatz(numbers[1]).start_string(), "7");
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        let results = pollster::block_on(session.run_distinct(&numbers)).unwrap();
+        let keyed: std::collections::HashMap<_, _> = results
+            .iter()
+            .map(|result| (result.start_string(), result.steps()))
+            .collect();
+        assert_eq!(keyed.len(), 4);
+        for n in numbers {
+            assert_eq!(keyed[&n.to_string()], cpu_collatz(n).steps());
         }
+        assert_eq!(keyed["97"], 118);
+    }
+
+    #[test]
+    fn writer_stream_matches_in_memory() {
+        let Some((device, queue)) = gpu::test_device() else {
+            return;
+        };
+        let write = |mut writer: &mut dyn std::io::Write, start, count, format| {
+            pollster::block_on(gpu_collatz_to_writer(
+                &device,
+                &queue,
+                &mut writer,
+                start,
+                count,
+                format,
+            ))
+        };
+        let mut packed = Vec::new();
+        write(&mut packed, 1, 150_000, OutputFormat::Packed).unwrap();
+        let all = pollster::block_on(gpu_collatz_on_device(&device, &queue, 1, 150_000)).unwrap();
+        assert_eq!(packed, pack_results(&all));
+
+        let mut csv = Vec::new();
+        write(&mut csv, 25, 3, OutputFormat::Csv).unwrap();
+        let want =
+            "n,steps,max,outcome\n25,23,88,converged\n26,10,40,converged\n27,111,9232,converged\n";
+        assert_eq!(String::from_utf8(csv).unwrap(), want);
 
-        if most_steps.0 < steps {
-            most_steps.0 = steps;
-            most_steps.1 = n;
+        // a writer that fills up after 1000 bytes
+        struct Full(usize);
+        impl std::io::Write for Full {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                if self.0 + bytes.len() > 1000 {
+                    return Err(std::io::Error::other("full"));
+                }
+                self.0 += bytes.len();
+                Ok(bytes.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
         }
+        let full = write(&mut Full(0), 1, 150_000, OutputFormat::Csv);
+        assert!(matches!(full, Err(CollatzError::Write(_))));
     }
 
+    #[test]
+    fn champion_includes_trajectory() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let champion = |start, count, trajectory_len| {
+            pollster::block_on(find_champion(&session, start, count, trajectory_len))
+                .unwrap()
+                .unwrap()
+        };
+        let full = champion(1, 30, Some(1000));
+        assert_eq!(full.result.n(), 27);
+        let trajectory = full.trajectory.unwrap();
+        assert_eq!(trajectory.values().first(), Some(&27));
+        assert_eq!(trajectory.values().last(), Some(&1));
+        assert_eq!(trajectory.values().len() as u32, full.result.steps() + 1);
+        assert!(!trajectory.truncated());
 
-    let mut interesting_results: Vec<u32> = Vec::new();
+        let cut = champion(1, 30, Some(10)).trajectory.unwrap();
+        assert!(cut.truncated() && cut.values().len() == 10 && cut.values()[0] == 27);
+        assert!(champion(1, 30, None).trajectory.is_none());
+        // 12 and 13 both take 9 steps, ties go to the smaller n
+        assert_eq!(champion(12, 2, None).result.n(), 12);
 
-    // 0th word = overflows
-    interesting_results.push(overflows);
-    // 1,2,3,4 are max reached
-    interesting_results.extend_from_slice(&u128_to_u32_array(highest_reached.0));
-    // 5,6,7,8 are n for max reached
-    interesting_results.extend_from_slice(&u128_to_u32_array(highest_reached.1));
-    // 9 is steps
-    interesting_results.push(most_steps.0);
-    // 10, 11, 12, 13 is n for steps
-    interesting_results.extend_from_slice(&u128_to_u32_array(most_steps.1));
-    // 14 is range
-    interesting_results.push(RANGE);
+        // the GPU's pick over several segments away from 1 matches the host's
+        let start = (1 << 40) + 12_345;
+        let results: Vec<_> = (start..start + 70_000).map(cpu_collatz).collect();
+        let want = most_steps(None, &results).unwrap();
+        let got = champion(start, 70_000, None).result;
+        assert!(results_agree(&[got], &[want]).is_empty());
+    }
 
-    drop(data);
-    staging_buffer.unmap();
+    #[test]
+    fn champion_result_is_the_full_result() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let result = pollster::block_on(champion_result(&session, 100))
+            .unwrap()
+            .unwrap();
+        assert_eq!((result.n(), result.steps(), result.max()), (97, 118, 9232));
+        assert!(results_agree(&[result], &[cpu_collatz(97)]).is_empty());
+    }
 
-    console_log!("{:?}", interesting_results);
+    #[test]
+    fn bytes_start_round_trips() {
+        let starts = [
+            1,
+            27,
+            1 << 64,
+            u128::MAX - 5,
+            0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10,
+        ];
+        for n in starts {
+            assert_eq!(
+                parse_n_bytes(&n.to_le_bytes()).unwrap(),
+                parse_n(&n.to_string()).unwrap()
+            );
+        }
+        for len in [15, 17] {
+            assert!(matches!(
+                parse_n_bytes(&vec![1; len]),
+                Err(CollatzError::InvalidArgument(_))
+            ));
+        }
+    }
 
-    Ok(interesting_results)
+    #[test]
+    fn log_sample_is_geometric() {
+        assert_eq!(
+            geometric_numbers(3, 100, 2).unwrap(),
+            [3, 6, 12, 24, 48, 96]
+        );
+        assert_eq!(geometric_numbers(5, 125, 5).unwrap(), [5, 25, 125]);
+        assert_eq!(geometric_numbers(7, 7, 10).unwrap(), [7]);
+        let powers = geometric_numbers(1, u128::MAX, 2).unwrap();
+        assert_eq!((powers.len(), powers.last()), (128, Some(&(1 << 127))));
+        assert!(matches!(
+            geometric_numbers(0, 10, 2),
+            Err(CollatzError::InvalidStart)
+        ));
+        assert!(matches!(
+            geometric_numbers(1, 10, 1),
+            Err(CollatzError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            geometric_numbers(10, 9, 2),
+            Err(CollatzError::EmptyRange)
+        ));
+    }
+
+    #[test]
+    fn webgpu_support_is_probed_once() {
+        let probes = std::cell::Cell::new(0);
+        let probe = |answer| {
+            let probes = &probes;
+            move || {
+                probes.set(probes.get() + 1);
+                async move { answer }
+            }
+        };
+        let supported =
+            |force, answer| pollster::block_on(cached_webgpu_support(force, probe(answer)));
+        assert!(supported(false, true));
+        assert!(supported(false, false));
+        assert!(supported(false, false));
+        assert_eq!(probes.get(), 1);
+        // forcing a recheck replaces the cached answer
+        assert!(!supported(true, false));
+        assert!(!supported(false, true));
+        assert_eq!(probes.get(), 2);
+    }
+
+    #[test]
+    fn first_with_steps_matches_brute_force() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let cases = [
+            (1, 5000, 100),
+            (1, 5000, 200),
+            (1, 5000, 238),
+            (1, 5000, 239),
+            (1, 5000, 0),
+            (500, 4500, 150),
+            (1, 150_000, 350),
+        ];
+        for (start, count, min_steps) in cases {
+            let want =
+                (start..start + count as u128).find(|&n| cpu_collatz(n).steps() >= min_steps);
+            let got = pollster::block_on(find_first_with_steps(&session, start, count, min_steps));
+            let got = got.unwrap();
+            assert_eq!(
+                got.map(|result| result.n()),
+                want,
+                "start {start} {min_steps}"
+            );
+            assert!(got.is_none_or(|result| result.steps() >= min_steps));
+        }
+    }
 }
@@ -0,0 +1,151 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{
+    buffer_size, create_mod_orbits_pipeline, input_data, max_records_per_dispatch, submit,
+    InputLayout,
+};
+use crate::result::DecodeError;
+use crate::session::CollatzSession;
+
+/// Largest modulus `do_gpu_collatz_mod` accepts (`modulus` in add.wgsl)
+pub const MAX_MODULUS: u32 = 1 << 16;
+
+// Each `mod_orbits` record is tail (1 word) + cycle_length (1 word)
+const ORBIT_WORDS: usize = 2;
+
+/// Where the orbit of n mod m ends up under the Collatz map on residues:
+/// `x / 2` for even x and `(3x + 1) mod m` for odd x.
+///
+/// With finitely many residues every orbit ends in a cycle, so instead of
+/// steps to 1 this is how many steps it takes to get onto the cycle and how
+/// long the cycle is.
+///
+/// ```js
+/// const o = (await do_gpu_collatz_mod("5", 1, 7))[0];
+/// o.tail;        // 1, 5 -> 16 mod 7 = 2 is already on the cycle
+/// o.cycleLength; // 3, 2 -> 1 -> 4 -> 2
+/// ```
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModOrbit {
+    n: u128,
+    tail: u32,
+    cycle_length: u32,
+}
+
+#[wasm_bindgen]
+impl ModOrbit {
+    #[wasm_bindgen(getter = start)]
+    pub fn start_string(&self) -> String {
+        self.n.to_string()
+    }
+
+    /// Steps before the first residue that is on the cycle, 0 if n mod m
+    /// is on it already
+    #[wasm_bindgen(getter)]
+    pub fn tail(&self) -> u32 {
+        self.tail
+    }
+
+    #[wasm_bindgen(getter = cycleLength)]
+    pub fn cycle_length(&self) -> u32 {
+        self.cycle_length
+    }
+}
+
+impl ModOrbit {
+    pub fn n(&self) -> u128 {
+        self.n
+    }
+}
+
+// Run the `mod_orbits` entry point over `numbers` mod `modulus`, which must
+// be from 1 to MAX_MODULUS
+pub(crate) async fn mod_orbits(
+    session: &CollatzSession,
+    numbers: &[u128],
+    modulus: u32,
+) -> Result<Vec<ModOrbit>, CollatzError> {
+    let device = &session.device;
+    let limit = max_records_per_dispatch(device, ORBIT_WORDS as u64 * 4);
+    if numbers.len() as u64 > limit {
+        return Err(CollatzError::TooManyNumbers {
+            count: numbers.len() as u64,
+            limit,
+        });
+    }
+
+    // reduce on the host so the shader only ever sees residues
+    let residues: Vec<u128> = numbers.iter().map(|&n| n % modulus as u128).collect();
+
+    let pipeline = create_mod_orbits_pipeline(device, modulus);
+    let output_size = buffer_size(numbers.len(), ORBIT_WORDS * 4)?;
+    let input = input_data(&residues, InputLayout::ArrayOfStructs);
+    let words = submit(device, &session.queue, &pipeline, &input, 7, output_size)
+        .read(device)
+        .await?;
+    if words.len() != numbers.len() * ORBIT_WORDS {
+        return Err(DecodeError::WrongLength {
+            expected: output_size as usize,
+            actual: words.len() * 4,
+        }
+        .into());
+    }
+
+    Ok(numbers
+        .iter()
+        .zip(words.chunks_exact(ORBIT_WORDS))
+        .map(|(&n, words)| ModOrbit {
+            n,
+            tail: words[0],
+            cycle_length: words[1],
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // Tail and cycle length of x0's orbit mod m, walked on the host
+    fn host_orbit(x0: u32, m: u32) -> (u32, u32) {
+        let step = |x: u32| {
+            if x.is_multiple_of(2) {
+                x / 2
+            } else {
+                ((3 * x as u64 + 1) % m as u64) as u32
+            }
+        };
+        let mut seen = HashMap::new();
+        let (mut x, mut i) = (x0, 0);
+        while !seen.contains_key(&x) {
+            seen.insert(x, i);
+            x = step(x);
+            i += 1;
+        }
+        (seen[&x], i - seen[&x])
+    }
+
+    #[test]
+    fn mod_orbits_match_host() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let numbers: Vec<u128> = (0..300).chain([(1 << 100) + 3, u128::MAX]).collect();
+        for m in [1, 2, 3, 7, 10, 64, 97, 1000, MAX_MODULUS] {
+            let orbits = pollster::block_on(mod_orbits(&session, &numbers, m)).unwrap();
+            for orbit in &orbits {
+                let want = host_orbit((orbit.n() % m as u128) as u32, m);
+                assert_eq!(
+                    (orbit.tail(), orbit.cycle_length()),
+                    want,
+                    "n {} m {m}",
+                    orbit.n()
+                );
+            }
+        }
+    }
+}
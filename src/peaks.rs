@@ -0,0 +1,176 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{
+    buffer_size, create_top_peaks_pipeline, input_data, max_records_per_dispatch, submit,
+    InputLayout,
+};
+use crate::result::DecodeError;
+use crate::session::CollatzSession;
+
+// `MAX_PEAKS` as a literal, so messages can be built from it with `concat!`
+macro_rules! max_peaks {
+    () => {
+        8
+    };
+}
+
+/// Largest number of peaks `do_gpu_collatz_top_peaks` can keep per number,
+/// the size of the sorted buffer in the shader (`MAX_PEAKS` in add.wgsl)
+pub const MAX_PEAKS: u32 = max_peaks!();
+
+// Error for a `peak_count` outside 1..=MAX_PEAKS
+pub(crate) const PEAK_COUNT_ERROR: &str = concat!("peak_count must be from 1 to ", max_peaks!());
+
+/// The highest peaks of one trajectory, largest first.
+///
+/// A peak is a value larger than the values either side of it, and n itself
+/// counts if it is larger than the value after it. So every `3n + 1` is a
+/// peak, and the first one is the trajectory's max.
+///
+/// ```js
+/// const p = (await do_gpu_collatz_top_peaks("7", 1, 3))[0];
+/// p.peaks;      // ["52", "40", "34"], 7 -> 22 -> 11 -> 34 -> 17 -> 52 -> 26 -> 13 -> 40 ...
+/// p.overflowed; // false
+/// ```
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopPeaks {
+    n: u128,
+    peaks: Vec<u128>,
+    overflowed: bool,
+}
+
+#[wasm_bindgen]
+impl TopPeaks {
+    #[wasm_bindgen(getter = start)]
+    pub fn start_string(&self) -> String {
+        self.n.to_string()
+    }
+
+    /// Up to the requested number of peaks, fewer if the trajectory didn't
+    /// have that many
+    #[wasm_bindgen(getter = peaks)]
+    pub fn peak_strings(&self) -> Vec<String> {
+        self.peaks.iter().map(|peak| peak.to_string()).collect()
+    }
+
+    /// 3n + 1 overflowed 128 bits, only the peaks before that are included
+    #[wasm_bindgen(getter)]
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl TopPeaks {
+    pub fn n(&self) -> u128 {
+        self.n
+    }
+
+    pub fn peaks(&self) -> &[u128] {
+        &self.peaks
+    }
+
+    // Decode one `top_peaks` output record, unused slots are zero
+    fn from_gpu_words(n: u128, words: &[u32]) -> Self {
+        TopPeaks {
+            n,
+            peaks: words[1..]
+                .chunks_exact(4)
+                .map(|parts| crate::u32_array_to_u128(&[parts[0], parts[1], parts[2], parts[3]]))
+                .filter(|&peak| peak != 0)
+                .collect(),
+            overflowed: words[0] != 0,
+        }
+    }
+}
+
+// Run the `top_peaks` entry point over `numbers`, keeping `count` (1 to
+// MAX_PEAKS) peaks for each
+pub(crate) async fn top_peaks(
+    session: &CollatzSession,
+    numbers: &[u128],
+    count: u32,
+) -> Result<Vec<TopPeaks>, CollatzError> {
+    let device = &session.device;
+    let record_words = 1 + 4 * count as usize;
+    let limit = max_records_per_dispatch(device, record_words as u64 * 4);
+    if numbers.len() as u64 > limit {
+        return Err(CollatzError::TooManyNumbers {
+            count: numbers.len() as u64,
+            limit,
+        });
+    }
+
+    let pipeline = create_top_peaks_pipeline(device, count);
+    let output_size = buffer_size(numbers.len(), record_words * 4)?;
+    let input = input_data(numbers, InputLayout::ArrayOfStructs);
+    let words = submit(device, &session.queue, &pipeline, &input, 6, output_size)
+        .read(device)
+        .await?;
+    if words.len() != numbers.len() * record_words {
+        return Err(DecodeError::WrongLength {
+            expected: output_size as usize,
+            actual: words.len() * 4,
+        }
+        .into());
+    }
+
+    Ok(numbers
+        .iter()
+        .zip(words.chunks_exact(record_words))
+        .map(|(&n, words)| TopPeaks::from_gpu_words(n, words))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The `count` largest distinct local peaks on n's trajectory, n itself
+    // included if it falls straight away, and whether it overflowed
+    fn host_peaks(n: u128, count: usize) -> (Vec<u128>, bool) {
+        let path = crate::cpu::walk(n);
+        let mut peaks = Vec::new();
+        for (i, pair) in path.windows(2).enumerate() {
+            let rose = i == 0 || path[i - 1] < pair[0];
+            if rose && pair[1] < pair[0] && !peaks.contains(&pair[0]) {
+                peaks.push(pair[0]);
+            }
+        }
+        peaks.sort_unstable_by(|a, b| b.cmp(a));
+        peaks.truncate(count);
+        (peaks, path.last() != Some(&1))
+    }
+
+    #[test]
+    fn peak_count_error_names_the_limit() {
+        let limit = format!("from 1 to {MAX_PEAKS}");
+        assert!(PEAK_COUNT_ERROR.ends_with(&limit));
+    }
+
+    #[test]
+    fn top_peaks_match_host() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let numbers: Vec<u128> = (1..3000)
+            .chain([(1 << 100) + 7, (1 << 127) + 1, u128::MAX])
+            .collect();
+        for count in [1, 3, MAX_PEAKS] {
+            let got = pollster::block_on(top_peaks(&session, &numbers, count)).unwrap();
+            for peaks in &got {
+                let want = host_peaks(peaks.n(), count as usize);
+                assert_eq!(
+                    (peaks.peaks().to_vec(), peaks.overflowed()),
+                    want,
+                    "n {} {count}",
+                    peaks.n()
+                );
+            }
+            assert_eq!(got[26].peaks()[0], 9232);
+        }
+        let seven = pollster::block_on(top_peaks(&session, &[7], 3)).unwrap();
+        assert_eq!(seven[0].peaks(), [52, 40, 34]);
+    }
+}
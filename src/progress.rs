@@ -0,0 +1,244 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::result::{CollatzResult, Outcome};
+
+// Start of every exported state, then the format version
+const STATE_MAGIC: &[u8; 4] = b"CLZS";
+const STATE_VERSION: u8 = 1;
+
+/// Which numbers a client has covered so far and the record among them, for
+/// saving progress and resuming later, on this device or another one.
+///
+/// ```js
+/// const progress = new ScanProgress();
+/// progress.addResults(await do_gpu_collatz_results("1", 1000000));
+/// const saved = progress.exportState(); // Uint8Array, store it anywhere
+/// const resumed = ScanProgress.importState(saved);
+/// resumed.covered;     // "1000000"
+/// resumed.recordStart; // "837799", 524 steps
+/// ```
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanProgress {
+    // inclusive (first, last) ranges, sorted, disjoint and not touching
+    intervals: Vec<(u128, u128)>,
+    // converged number with the most steps, the smallest on ties
+    record: Option<(u128, u32)>,
+}
+
+#[wasm_bindgen]
+impl ScanProgress {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ScanProgress {
+        ScanProgress::default()
+    }
+
+    /// How many numbers have been covered, as a decimal string
+    #[wasm_bindgen(getter = covered)]
+    pub fn covered_string(&self) -> String {
+        self.covered().to_string()
+    }
+
+    /// Number with the most steps so far, `undefined` before any converged
+    #[wasm_bindgen(getter = recordStart)]
+    pub fn record_start(&self) -> Option<String> {
+        self.record.map(|(n, _)| n.to_string())
+    }
+
+    #[wasm_bindgen(getter = recordSteps)]
+    pub fn record_steps(&self) -> Option<u32> {
+        self.record.map(|(_, steps)| steps)
+    }
+
+    /// Mark the results' numbers as covered, see `add`
+    #[wasm_bindgen(js_name = addResults)]
+    pub fn add_results(&mut self, results: Vec<CollatzResult>) {
+        self.add(&results);
+    }
+
+    /// Whether n has been covered
+    #[wasm_bindgen(js_name = contains)]
+    pub fn contains_string(&self, n: String) -> bool {
+        crate::parse_n(&n).is_ok_and(|n| self.contains(n))
+    }
+
+    /// The state as bytes in a versioned format `importState` reads back:
+    /// "CLZS", version (1 byte), has record (1 byte), record n (16 bytes) and
+    /// steps (4 bytes), interval count (4 bytes), then first and last (16
+    /// bytes each) of every covered interval. Everything little-endian.
+    #[wasm_bindgen(js_name = exportState)]
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(30 + self.intervals.len() * 32);
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+        let (n, steps) = self.record.unwrap_or((0, 0));
+        bytes.push(self.record.is_some() as u8);
+        bytes.extend_from_slice(&n.to_le_bytes());
+        bytes.extend_from_slice(&steps.to_le_bytes());
+        bytes.extend_from_slice(&(self.intervals.len() as u32).to_le_bytes());
+        for &(first, last) in &self.intervals {
+            bytes.extend_from_slice(&first.to_le_bytes());
+            bytes.extend_from_slice(&last.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Read back what `exportState` wrote
+    #[wasm_bindgen(js_name = importState)]
+    pub fn import_state_js(bytes: &[u8]) -> Result<ScanProgress, JsValue> {
+        Ok(ScanProgress::import_state(bytes)?)
+    }
+}
+
+impl ScanProgress {
+    pub fn import_state(bytes: &[u8]) -> Result<ScanProgress, CollatzError> {
+        const BAD_STATE: CollatzError = CollatzError::InvalidArgument("not a saved scan state");
+
+        let mut reader = ByteReader(bytes);
+        if reader.take::<4>()? != *STATE_MAGIC {
+            return Err(BAD_STATE);
+        }
+        if reader.take::<1>()?[0] != STATE_VERSION {
+            return Err(CollatzError::InvalidArgument(
+                "scan state was saved in an unknown format version",
+            ));
+        }
+        let has_record = reader.take::<1>()?[0] != 0;
+        let n = u128::from_le_bytes(reader.take()?);
+        let steps = u32::from_le_bytes(reader.take()?);
+        let count = u32::from_le_bytes(reader.take()?);
+
+        let mut progress = ScanProgress {
+            intervals: Vec::new(),
+            record: has_record.then_some((n, steps)),
+        };
+        for _ in 0..count {
+            let first = u128::from_le_bytes(reader.take()?);
+            let last = u128::from_le_bytes(reader.take()?);
+            if first > last {
+                return Err(BAD_STATE);
+            }
+            progress.intervals.push((first, last));
+        }
+        if !reader.0.is_empty() {
+            return Err(BAD_STATE);
+        }
+
+        // tolerate hand-edited files with overlapping or unsorted intervals
+        progress.normalize();
+        Ok(progress)
+    }
+
+    /// Mark every result's number as covered and keep the record up to date
+    pub fn add(&mut self, results: &[CollatzResult]) {
+        let mut numbers: Vec<u128> = results.iter().map(|result| result.n()).collect();
+        numbers.sort_unstable();
+        for n in numbers {
+            match self.intervals.last_mut() {
+                Some((_, last)) if last.checked_add(1) == Some(n) || *last == n => *last = n,
+                _ => self.intervals.push((n, n)),
+            }
+        }
+        self.normalize();
+
+        for result in results {
+            if result.outcome() != Outcome::Converged {
+                continue;
+            }
+            let candidate = (result.n(), result.steps());
+            self.record = match self.record {
+                Some(best)
+                    if best.1 > candidate.1 || (best.1 == candidate.1 && best.0 < candidate.0) =>
+                {
+                    Some(best)
+                }
+                _ => Some(candidate),
+            };
+        }
+    }
+
+    pub fn covered(&self) -> u128 {
+        self.intervals
+            .iter()
+            .map(|&(first, last)| last - first + 1)
+            .fold(0, u128::saturating_add)
+    }
+
+    pub fn contains(&self, n: u128) -> bool {
+        let i = self.intervals.partition_point(|&(_, last)| last < n);
+        self.intervals.get(i).is_some_and(|&(first, _)| first <= n)
+    }
+
+    /// Covered intervals as inclusive `(first, last)` pairs, ascending
+    pub fn intervals(&self) -> &[(u128, u128)] {
+        &self.intervals
+    }
+
+    // Sort the intervals and merge any that overlap or touch
+    fn normalize(&mut self) {
+        self.intervals.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(self.intervals.len());
+        for &(first, last) in &self.intervals {
+            match merged.last_mut() {
+                Some((_, end)) if first <= end.saturating_add(1) => *end = (*end).max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        self.intervals = merged;
+    }
+}
+
+// Reads fixed-size fields off the front of exported bytes
+pub(crate) struct ByteReader<'a>(pub(crate) &'a [u8]);
+
+impl ByteReader<'_> {
+    pub(crate) fn take<const N: usize>(&mut self) -> Result<[u8; N], CollatzError> {
+        let (field, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or(CollatzError::InvalidArgument("saved data is cut short"))?;
+        self.0 = rest;
+        Ok(*field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_collatz;
+
+    fn results(numbers: impl Iterator<Item = u128>) -> Vec<CollatzResult> {
+        numbers.map(cpu_collatz).collect()
+    }
+
+    #[test]
+    fn progress_round_trips() {
+        let mut progress = ScanProgress::new();
+        progress.add(&results(1..5000));
+        progress.add(&results(9000..9100));
+        progress.add(&results((4990..5010).chain([u128::MAX, u128::MAX - 1])));
+        let intervals = [(1, 5009), (9000, 9099), (u128::MAX - 1, u128::MAX)];
+        assert_eq!(progress.intervals(), intervals);
+        assert_eq!(progress.covered(), 5009 + 100 + 2);
+        assert_eq!(progress.record_start().as_deref(), Some("3711"));
+        assert_eq!(progress.record_steps(), Some(237));
+        assert!(progress.contains(9050) && progress.contains(u128::MAX));
+        assert!(!progress.contains(9100) && !progress.contains(0));
+
+        let bytes = progress.export_state();
+        assert_eq!(ScanProgress::import_state(&bytes).unwrap(), progress);
+        let empty = ScanProgress::new();
+        assert_eq!(
+            ScanProgress::import_state(&empty.export_state()).unwrap(),
+            empty
+        );
+        assert!(ScanProgress::import_state(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 2;
+        assert!(ScanProgress::import_state(&bad_version).is_err());
+        let mut bad_magic = bytes;
+        bad_magic[0] = 0;
+        assert!(ScanProgress::import_state(&bad_magic).is_err());
+    }
+}
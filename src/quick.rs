@@ -0,0 +1,437 @@
+use std::ops::ControlFlow;
+
+use crate::error::CollatzError;
+use crate::gpu::{
+    create_champion_pipeline, create_count_above_pipeline, create_dropping_histogram_pipeline,
+    create_first_failure_pipeline, create_max_growth_pipeline, create_quick_check_pipeline,
+    create_step_sum_pipeline, dispatch_chunked, dispatch_chunked_until, WORKGROUP_SIZE,
+};
+use crate::session::CollatzSession;
+
+// The numbers out of `count` from `start` that don't drop below themselves
+// within `quick_steps` steps, ascending. The GPU writes one flag word per
+// number and the host keeps the flagged ones. The range must already have
+// been checked.
+pub(crate) async fn hard_cases(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    quick_steps: u32,
+) -> Result<Vec<u128>, CollatzError> {
+    let pipeline = create_quick_check_pipeline(&session.device, quick_steps);
+
+    let mut hard = Vec::new();
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count.into(),
+        4,
+        |len| len,
+        |first, flags| {
+            hard.extend(
+                flags
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &flag)| flag != 0)
+                    .map(|(i, _)| first + i as u128),
+            )
+        },
+    )
+    .await?;
+
+    Ok(hard)
+}
+
+// How many of the `count` numbers from `start` take more than `threshold`
+// steps. The GPU sums each workgroup and the host adds up the sums, so only
+// one word per workgroup is read back. The range must already have been
+// checked.
+pub(crate) async fn count_above(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    threshold: u32,
+) -> Result<u64, CollatzError> {
+    let pipeline = create_count_above_pipeline(&session.device, threshold);
+
+    let mut total = 0;
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count.into(),
+        9,
+        |len| len.div_ceil(WORKGROUP_SIZE as usize),
+        |_, counts| total += counts.iter().map(|&count| count as u64).sum::<u64>(),
+    )
+    .await?;
+
+    Ok(total)
+}
+
+// Sum of the step counts of the `count` numbers from `start`. Like
+// `count_above`, the GPU adds up each workgroup and only those sums are read
+// back. The range must already have been checked.
+pub(crate) async fn step_sum(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+) -> Result<u128, CollatzError> {
+    let pipeline = create_step_sum_pipeline(&session.device);
+
+    let mut total = 0;
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count.into(),
+        9,
+        |len| len.div_ceil(WORKGROUP_SIZE as usize),
+        |_, sums| total += sums.iter().map(|&sum| sum as u128).sum::<u128>(),
+    )
+    .await?;
+
+    Ok(total)
+}
+
+// The first of the `count` numbers from `start` that doesn't reach 1, or
+// `None` if they all do. Each chunk is reduced on the GPU to one word and
+// the scan stops at the first chunk with a failure in it. The range must
+// already have been checked.
+pub(crate) async fn first_failure(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+) -> Result<Option<u128>, CollatzError> {
+    let pipeline = create_first_failure_pipeline(&session.device);
+
+    let mut failure = None;
+    dispatch_chunked_until(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count.into(),
+        13,
+        |_| 1,
+        |first, key| match key[0] {
+            0 => ControlFlow::Continue(()),
+            key => {
+                failure = Some(first + (u32::MAX - key) as u128);
+                ControlFlow::Break(())
+            }
+        },
+    )
+    .await?;
+
+    Ok(failure)
+}
+
+// The number out of `count` from `start` whose peak has the most bits over
+// its start, ties going to the larger n, or `None` if every number
+// overflowed. Each chunk is reduced on the GPU to one key and only that is
+// read back. The range must already have been checked.
+pub(crate) async fn max_growth(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+) -> Result<Option<u128>, CollatzError> {
+    let pipeline = create_max_growth_pipeline(&session.device);
+    let index_mask = (1 << 17) - 1;
+
+    let mut best: Option<(u32, u128)> = None;
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count.into(),
+        14,
+        |_| 1,
+        |first, key| {
+            // chunks come in ascending order, so a later one takes over a
+            // tie and it goes to the larger n
+            let growth = key[0] >> 17;
+            if growth != 0 && best.is_none_or(|(best, _)| growth >= best) {
+                best = Some((growth, first + (key[0] & index_mask) as u128));
+            }
+        },
+    )
+    .await?;
+
+    Ok(best.map(|(_, n)| n))
+}
+
+// Dropping times of the `count` numbers from `start` binned on the GPU:
+// `max_bin + 1` bins of single times with the last holding everything from
+// `max_bin` up, then one for numbers that never dropped and one for those
+// that overflowed first. The range must already have been checked.
+pub(crate) async fn dropping_histogram(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    max_bin: u32,
+) -> Result<Vec<u64>, CollatzError> {
+    let pipeline = create_dropping_histogram_pipeline(&session.device);
+    let bins = max_bin as usize + 3;
+
+    let mut totals = vec![0; bins];
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count.into(),
+        11,
+        |_| bins,
+        |_, counts| {
+            for (total, &count) in totals.iter_mut().zip(counts) {
+                *total += count as u64;
+            }
+        },
+    )
+    .await?;
+
+    Ok(totals)
+}
+
+// Numbers per `champion` key, CHAMPION_SEGMENT_BITS in add.wgsl
+const CHAMPION_SEGMENT_BITS: u32 = 15;
+
+// The number below `bound` that takes the most steps to reach 1 and its
+// step count, ties going to the smaller n. `None` if nothing below `bound`
+// converged, `bound` has to be at least 2.
+pub(crate) async fn champion_below(
+    session: &CollatzSession,
+    bound: u128,
+) -> Result<Option<(u128, u32)>, CollatzError> {
+    champion_in(session, 1, bound - 1).await
+}
+
+// Same as `champion_below` for `count` numbers from `start`, which has to
+// have been checked already. Each chunk is reduced on the GPU to one key per
+// 2^15 numbers and only those are read back.
+pub(crate) async fn champion_in(
+    session: &CollatzSession,
+    start: u128,
+    count: u128,
+) -> Result<Option<(u128, u32)>, CollatzError> {
+    let pipeline = create_champion_pipeline(&session.device);
+    let segment_mask = (1 << CHAMPION_SEGMENT_BITS) - 1;
+
+    let mut best: Option<(u128, u32)> = None;
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        start,
+        count,
+        12,
+        |len| len.div_ceil(1 << CHAMPION_SEGMENT_BITS),
+        |first, keys| {
+            // segments come in ascending order, so only a strictly longer
+            // trajectory takes over and ties stay with the smaller n
+            for (segment, &key) in keys.iter().enumerate() {
+                if key == 0 {
+                    continue;
+                }
+                let steps = (key >> CHAMPION_SEGMENT_BITS) - 1;
+                let position = segment_mask - (key & segment_mask);
+                let n = first + ((segment as u128) << CHAMPION_SEGMENT_BITS) + position as u128;
+                if best.is_none_or(|(_, best)| steps > best) {
+                    best = Some((n, steps));
+                }
+            }
+        },
+    )
+    .await?;
+
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::test_session;
+
+    #[test]
+    fn first_failure_matches_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        assert_eq!(
+            pollster::block_on(first_failure(&session, 1, 250_000)).unwrap(),
+            None
+        );
+        for start in [u128::MAX - 999, (1 << 127) - 500, 1 << 126] {
+            let numbers: Vec<u128> = (0..1000).map(|i| start + i).collect();
+            let results = pollster::block_on(session.run(&numbers)).unwrap();
+            let want = crate::first_failure(&results).map(|result| result.n());
+            assert!(want.is_some());
+            assert_eq!(
+                pollster::block_on(first_failure(&session, start, 1000)).unwrap(),
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn step_sum_matches_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let want: u128 = (1..=150_000)
+            .map(|n| crate::cpu_collatz(n).steps() as u128)
+            .sum();
+        assert_eq!(
+            pollster::block_on(step_sum(&session, 1, 150_000)).unwrap(),
+            want
+        );
+        // overflowed trajectories count the steps they got through
+        let start = u128::MAX - 299;
+        let results =
+            pollster::block_on(session.run(&(start..=u128::MAX).collect::<Vec<_>>())).unwrap();
+        let want = crate::step_sum(&results);
+        assert_eq!(
+            pollster::block_on(step_sum(&session, start, 300)).unwrap(),
+            want
+        );
+    }
+
+    #[test]
+    fn max_growth_matches_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        for (start, count) in [
+            (1, 150_000),
+            (u128::MAX - 2999, 3000),
+            ((1 << 100) + 1, 5000),
+        ] {
+            let numbers: Vec<u128> = (0..count).map(|i| start + i as u128).collect();
+            let results = pollster::block_on(session.run(&numbers)).unwrap();
+            let want = crate::max_growth(None, &results).map(|result| result.n());
+            assert_eq!(
+                pollster::block_on(max_growth(&session, start, count)).unwrap(),
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn hard_cases_match_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        // whether n stays at or above itself for its first `steps` steps
+        let slow = |n: u128, steps: usize| {
+            crate::cpu::walk(n)
+                .iter()
+                .take(steps + 1)
+                .all(|&v| v >= n && v != 1)
+        };
+        for quick_steps in [1, 3, 10, 50] {
+            let got = pollster::block_on(hard_cases(&session, 1, 100_000, quick_steps)).unwrap();
+            let want: Vec<u128> = (1..=100_000)
+                .filter(|&n| slow(n, quick_steps as usize))
+                .collect();
+            assert_eq!(got, want, "{quick_steps} steps");
+        }
+        let got = pollster::block_on(hard_cases(&session, 1, 30, 10)).unwrap();
+        assert!(got.contains(&27) && !got.contains(&3) && !got.contains(&4));
+    }
+
+    #[test]
+    fn count_above_matches_brute_force() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let ranges = [
+            (1, 10_000),
+            (1, 1),
+            (12345, 70_001),
+            ((1 << 127) + 1, 1000),
+            (1 << 80, 777),
+        ];
+        for (start, count) in ranges {
+            for k in [0, 50, 111, 200, 1000] {
+                let got = pollster::block_on(count_above(&session, start, count, k)).unwrap();
+                let want = (start..start + count as u128)
+                    .filter(|&n| crate::cpu_collatz(n).steps() > k)
+                    .count() as u64;
+                assert_eq!(got, want, "start {start} count {count} k {k}");
+            }
+        }
+    }
+
+    #[test]
+    fn dropping_histogram_matches_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let cases = [
+            (1, 20_000, 40),
+            (1 << 60, 3000, 200),
+            (u128::MAX - 5000, 5000, 10),
+            (1, 5, 0),
+        ];
+        for (start, count, max_bin) in cases {
+            let got = pollster::block_on(dropping_histogram(&session, start, count, max_bin));
+            let max_bin = max_bin as usize;
+            let mut want = vec![0; max_bin + 3];
+            for n in start..start + count as u128 {
+                let bin = match crate::cpu::drop_below(n) {
+                    _ if n == 1 => max_bin + 1,
+                    Some((steps, _)) => (steps as usize).min(max_bin),
+                    None => max_bin + 2,
+                };
+                want[bin] += 1;
+            }
+            assert_eq!(got.unwrap(), want, "start {start}");
+        }
+    }
+
+    #[test]
+    fn champion_below_matches_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let mut best = (1, 0);
+        let mut bounds = [2, 3, 14, 32_769, 32_770, 100_001].into_iter().peekable();
+        for n in 1.. {
+            if bounds.next_if_eq(&n).is_some() {
+                let got = pollster::block_on(champion_below(&session, n)).unwrap();
+                assert_eq!(got, Some(best), "bound {n}");
+            }
+            if bounds.peek().is_none() {
+                break;
+            }
+            let steps = crate::cpu_collatz(n).steps();
+            if steps > best.1 {
+                best = (n, steps);
+            }
+        }
+    }
+
+    #[test]
+    fn champion_below_skips_powers_of_two() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        // 1 to 32 holds six powers of two, taking at most 5 steps, and 27
+        // with 111
+        assert_eq!(
+            pollster::block_on(champion_below(&session, 33)).unwrap(),
+            Some((27, 111))
+        );
+        // with only powers of two the longest is still the largest of them
+        assert_eq!(
+            pollster::block_on(champion_below(&session, 3)).unwrap(),
+            Some((2, 1))
+        );
+    }
+}
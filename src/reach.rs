@@ -0,0 +1,117 @@
+use crate::error::CollatzError;
+use crate::gpu::{create_reaches_pipeline, create_until_exceeds_pipeline, dispatch_chunked};
+use crate::session::CollatzSession;
+
+// For each of the `count` numbers from `start`, the step at which its
+// trajectory first passes through `target`, or -1 if it never does before
+// reaching 1. The range must already have been checked.
+pub(crate) async fn reach_steps(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    target: u128,
+) -> Result<Vec<i32>, CollatzError> {
+    let pipeline = create_reaches_pipeline(&session.device, target);
+    step_words(session, &pipeline, start, count).await
+}
+
+// For each of the `count` numbers from `start`, the step at which its
+// trajectory first goes above `ceiling`, or -1 if it reaches 1 first. The
+// range must already have been checked.
+pub(crate) async fn exceed_steps(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    ceiling: u128,
+) -> Result<Vec<i32>, CollatzError> {
+    let pipeline = create_until_exceeds_pipeline(&session.device, ceiling);
+    step_words(session, &pipeline, start, count).await
+}
+
+// Run `pipeline`, writing one step (or -1) per number to `reach_step`, over
+// `count` numbers from `start`
+async fn step_words(
+    session: &CollatzSession,
+    pipeline: &wgpu::ComputePipeline,
+    start: u128,
+    count: u64,
+) -> Result<Vec<i32>, CollatzError> {
+    let mut steps = Vec::new();
+    dispatch_chunked(
+        &session.device,
+        &session.queue,
+        pipeline,
+        start,
+        count.into(),
+        5,
+        |len| len,
+        |_, words| steps.extend(words.iter().map(|&word| word as i32)),
+    )
+    .await?;
+
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::walk;
+    use crate::session::test_session;
+
+    #[test]
+    fn reach_steps_match_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let reach = |start, count, target| {
+            pollster::block_on(reach_steps(&session, start, count, target)).unwrap()
+        };
+        // 3 -> 10 -> 5 -> 16, 4 never passes 16, 5 -> 16
+        assert_eq!(reach(3, 3, 16), [3, -1, 1]);
+        assert_eq!(reach(1, 2, 1), [0, 1]);
+        for (n, step) in (1..).zip(reach(1, 3000, 40)) {
+            let want = walk(n)
+                .iter()
+                .position(|&v| v == 40)
+                .map_or(-1, |i| i as i32);
+            assert_eq!(step, want, "n {n}");
+        }
+        let big = (1 << 100) + 1;
+        assert_eq!(reach(big, 1, 3 * big + 1), [1]);
+        assert_eq!(reach(1 << 101, 1, 3 * big + 1), [-1]);
+    }
+
+    #[test]
+    fn exceed_steps_match_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        // an overflowing 3n + 1 is past any ceiling
+        let host = |n: u128, ceiling: u128| {
+            let path = walk(n);
+            match path.iter().position(|&v| v > ceiling) {
+                Some(step) => step as i32,
+                None if path.last() != Some(&1) => path.len() as i32,
+                None => -1,
+            }
+        };
+        let cases = [
+            (1, 2000, 100),
+            (1, 2000, 9232),
+            (1, 500, 0),
+            (1, 300, 1 << 70),
+            (u128::MAX - 20, 20, u128::MAX - 1),
+            (u128::MAX / 3 - 5, 10, u128::MAX),
+        ];
+        for (start, count, ceiling) in cases {
+            let got = pollster::block_on(exceed_steps(&session, start, count, ceiling)).unwrap();
+            let want: Vec<_> = (start..start + count as u128)
+                .map(|n| host(n, ceiling))
+                .collect();
+            assert_eq!(got, want, "start {start} ceiling {ceiling}");
+        }
+        // 3 -> 10 -> 5 -> 16, 4 never passes 10, 5 -> 16
+        let got = pollster::block_on(exceed_steps(&session, 3, 3, 10)).unwrap();
+        assert_eq!(got, [3, -1, 1]);
+    }
+}
@@ -0,0 +1,941 @@
+use wasm_bindgen::prelude::*;
+
+// Safety limit the shader stops iterating at (see `collatz` in add.wgsl)
+pub const MAX_STEPS: u32 = 100_000;
+
+/// What counts as a step once a trajectory reaches 1
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StepConvention {
+    /// Stop the first time the value is 1, steps is the number of
+    /// iterations it took to get there. 1 takes 0 steps and 2 takes 1.
+    #[default]
+    UntilOne = 0,
+    /// Also go once round the trivial cycle 1 -> 4 -> 2 -> 1, adding 3 steps
+    /// (and a peak of at least 4) to everything that reaches 1. 1 takes 3
+    /// steps and 2 takes 4, matching tables that run until the first 1 after
+    /// the start.
+    ThroughTrivialCycle = 1,
+}
+
+// Top bit of the steps word is set by the shader when a cycle was detected
+const CYCLE_FLAG: u32 = 1 << 31;
+
+// Next bit down is set when saturate_overflow clamped 3n + 1
+const SATURATED_FLAG: u32 = 1 << 30;
+
+// Each output record is steps (1 word) + max (4 words), then a word the
+// shader always sets to 1 and 2 of padding (see `store_result` in add.wgsl)
+pub(crate) const RESULT_WORDS: usize = 8;
+
+// With `RunConfig::full_record` each output record is steps (1 word) + max
+// (4 words) + height_step (1 word) + height (4 words) + steps_shortcut
+// (1 word) + power_step (1 word) + runs (1 word) + sum (4 words)
+pub(crate) const FULL_RESULT_WORDS: usize = 17;
+
+// Words per output record, full or not
+pub(crate) const fn result_words(full_record: bool) -> usize {
+    if full_record {
+        FULL_RESULT_WORDS
+    } else {
+        RESULT_WORDS
+    }
+}
+
+// power_step word of a trajectory that never reached a power of two
+const NO_POWER_OF_TWO: u32 = u32::MAX;
+
+/// How a single trajectory ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// Reached 1
+    Converged,
+    /// 3n + 1 overflowed 128 bits, steps is how far it got
+    Overflowed,
+    /// 3n + 1 overflowed 128 bits and was clamped to `u128::MAX` (only with
+    /// `RunConfig::saturate_overflow`). Steps include the clamped one and
+    /// max is `u128::MAX`, which is only a lower bound on the real peak, so
+    /// neither is exact.
+    Saturated,
+    /// Hit `MAX_STEPS` without reaching 1
+    Capped,
+    /// Tortoise and hare met before reaching 1
+    Cycle,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Converged => "converged",
+            Outcome::Overflowed => "overflowed",
+            Outcome::Saturated => "saturated",
+            Outcome::Capped => "capped",
+            Outcome::Cycle => "cycle",
+        }
+    }
+}
+
+/// Which code path produced a result.
+///
+/// This is tracked for debugging and validation. It isn't a JS property, but
+/// `export_sweep` keeps it with each archived result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResultSource {
+    /// Computed by the compute shader
+    Gpu,
+    /// Computed on the host by `cpu_collatz`
+    Cpu,
+    /// Computed by the `main_lookup` kernel, which finishes each trajectory
+    /// from the small-number table
+    Table,
+    /// Copied from the result of an earlier occurrence of the same number
+    Derived,
+}
+
+impl ResultSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultSource::Gpu => "gpu",
+            ResultSource::Cpu => "cpu",
+            ResultSource::Table => "table",
+            ResultSource::Derived => "derived",
+        }
+    }
+}
+
+/// The decoded result for one starting number.
+///
+/// In JS the fields are read-only properties, with the 128 bit values as
+/// decimal strings so no precision is lost:
+///
+/// ```js
+/// const r = results[0];
+/// BigInt(r.start); // starting number
+/// r.steps;         // number
+/// r.stepsClassic;  // same as steps under the classic map
+/// r.stepsShortcut; // steps counting (3n + 1) / 2 as one
+/// r.totalHalvings; // steps that halved
+/// r.stepsFromFirstOdd; // steps after the leading halvings
+/// BigInt(r.max);   // "0" if the trajectory overflowed
+/// r.outcome;       // "converged", "overflowed", "saturated", "capped" or "cycle"
+/// r.heightStep;    // step where the value first has max's bit length
+/// r.stepsToPowerOfTwo; // step where it first hits a power of two
+/// r.completenessX1000; // 1000 * steps per bit of the start
+/// r.maxEvenRun;    // longest run of halvings
+/// r.maxOddRun;     // longest run of odd steps under the shortcut map
+/// r.monotoneAfterPeak; // whether it only falls after its peak
+/// BigInt(r.trajectorySum); // every value visited added up
+/// JSON.stringify(r);
+/// // {"start":"27","steps":111,"stepsClassic":111,"stepsShortcut":70,"totalHalvings":70,"stepsFromFirstOdd":111,"max":"9232","heightStep":77,"height":"9232","stepsToPowerOfTwo":107,"completenessX1000":22200,"maxEvenRun":5,"maxOddRun":6,"monotoneAfterPeak":false,"trajectorySum":"101440","outcome":"converged"}
+/// ```
+///
+/// The GPU only works out steps and max unless the run asked for
+/// `RunConfig::full_record`. Without it `stepsShortcut`, `totalHalvings`,
+/// `heightStep`, `height`, `stepsToPowerOfTwo`, `maxEvenRun`, `maxOddRun`
+/// and `trajectorySum` are `undefined`. Results from the CPU always have
+/// every field.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollatzResult {
+    n: u128,
+    steps: u32,
+    max: u128,
+    outcome: Outcome,
+    extras: Option<Extras>,
+    source: ResultSource,
+}
+
+// The fields only a full record has, see `RunConfig::full_record`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Extras {
+    height_step: u32,
+    height: u128,
+    steps_shortcut: u32,
+    steps_to_power_of_two: Option<u32>,
+    max_even_run: u32,
+    max_odd_run: u32,
+    trajectory_sum: u128,
+}
+
+#[wasm_bindgen]
+impl CollatzResult {
+    #[wasm_bindgen(getter = start)]
+    pub fn start_string(&self) -> String {
+        self.n.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Same as `steps`, for comparing against `stepsShortcut`
+    #[wasm_bindgen(getter = stepsClassic)]
+    pub fn steps_classic(&self) -> u32 {
+        self.steps
+    }
+
+    /// Steps under the shortcut map, where each 3n + 1 and the halving
+    /// after it count as one `(3n + 1) / 2` step. This is the number of
+    /// halvings, so `steps - stepsShortcut` is the number of 3n + 1 steps.
+    #[wasm_bindgen(getter = stepsShortcut)]
+    pub fn steps_shortcut(&self) -> Option<u32> {
+        self.extras.map(|extras| extras.steps_shortcut)
+    }
+
+    /// Total 2-adic valuation removed over the trajectory, the sum of every
+    /// trailing zero shifted off on the way to 1. Each classic step halves
+    /// by exactly one bit, so this is the number of even steps and the same
+    /// as `stepsShortcut`, under the name number theory uses.
+    #[wasm_bindgen(getter = totalHalvings)]
+    pub fn total_halvings(&self) -> Option<u32> {
+        self.steps_shortcut()
+    }
+
+    /// Steps counted from the first odd value instead of from n, leaving out
+    /// the halvings at the start. For odd k, `k * 2^j` gives the same count
+    /// as k itself, so even and odd starts line up.
+    #[wasm_bindgen(getter = stepsFromFirstOdd)]
+    pub fn steps_from_first_odd(&self) -> u32 {
+        self.steps.saturating_sub(self.n.trailing_zeros())
+    }
+
+    /// Step where the value is first a power of two, after which it only
+    /// halves down to 1. 0 for a power of two itself, `undefined` if the
+    /// trajectory never reached one (it overflowed, was capped or cycled)
+    /// or this isn't a full record.
+    #[wasm_bindgen(getter = stepsToPowerOfTwo)]
+    pub fn steps_to_power_of_two(&self) -> Option<u32> {
+        self.extras.and_then(|extras| extras.steps_to_power_of_two)
+    }
+
+    /// Steps per bit of the start, times 1000 and rounded down:
+    /// `steps * 1000 / bitLength(n)`. Bit length stands in for log2 so it
+    /// stays in integers, and makes starts of different sizes comparable.
+    /// 0 for n = 1, `undefined` unless the trajectory converged.
+    #[wasm_bindgen(getter = completenessX1000)]
+    pub fn completeness_x1000(&self) -> Option<u32> {
+        if self.outcome != Outcome::Converged {
+            return None;
+        }
+        // steps is at most MAX_STEPS, so this can't overflow
+        let bit_length = (u128::BITS - self.n.leading_zeros()).max(1);
+        Some(self.steps * 1000 / bit_length)
+    }
+
+    /// Longest run of consecutive halvings, the most trailing zeros shifted
+    /// off one value on the way down
+    #[wasm_bindgen(getter = maxEvenRun)]
+    pub fn max_even_run(&self) -> Option<u32> {
+        self.extras.map(|extras| extras.max_even_run)
+    }
+
+    /// Longest run of consecutive odd steps under the shortcut map
+    /// `(3n + 1) / 2`: odd steps each followed by exactly one halving. Under
+    /// the classic map odd steps are never consecutive, since 3n + 1 is
+    /// always even, so counted that way this would never be more than 1.
+    #[wasm_bindgen(getter = maxOddRun)]
+    pub fn max_odd_run(&self) -> Option<u32> {
+        self.extras.map(|extras| extras.max_odd_run)
+    }
+
+    /// The trajectory only goes down after its peak, with no smaller bumps
+    /// on the way to 1. Any odd value after the peak would rise again, so
+    /// this is the same as the peak being a power of two. False unless the
+    /// trajectory converged. The trip round the trivial cycle isn't counted.
+    #[wasm_bindgen(getter = monotoneAfterPeak)]
+    pub fn monotone_after_peak(&self) -> bool {
+        self.outcome == Outcome::Converged && self.max.is_power_of_two()
+    }
+
+    #[wasm_bindgen(getter = max)]
+    pub fn max_string(&self) -> String {
+        self.max.to_string()
+    }
+
+    #[wasm_bindgen(getter = trajectorySum)]
+    pub fn trajectory_sum_string(&self) -> Option<String> {
+        self.trajectory_sum().map(|sum| sum.to_string())
+    }
+
+    #[wasm_bindgen(getter = heightStep)]
+    pub fn height_step(&self) -> Option<u32> {
+        self.extras.map(|extras| extras.height_step)
+    }
+
+    #[wasm_bindgen(getter = height)]
+    pub fn height_string(&self) -> Option<String> {
+        self.height().map(|height| height.to_string())
+    }
+
+    #[wasm_bindgen(getter = outcome)]
+    pub fn outcome_name(&self) -> String {
+        self.outcome.as_str().to_string()
+    }
+
+    #[wasm_bindgen(js_name = toJSON)]
+    pub fn to_json(&self) -> JsValue {
+        crate::js_object(&[
+            ("start", self.start_string().into()),
+            ("steps", self.steps.into()),
+            ("stepsClassic", self.steps_classic().into()),
+            ("stepsShortcut", self.steps_shortcut().into()),
+            ("totalHalvings", self.total_halvings().into()),
+            ("stepsFromFirstOdd", self.steps_from_first_odd().into()),
+            ("max", self.max_string().into()),
+            ("heightStep", self.height_step().into()),
+            ("height", self.height_string().into()),
+            ("stepsToPowerOfTwo", self.steps_to_power_of_two().into()),
+            ("completenessX1000", self.completeness_x1000().into()),
+            ("maxEvenRun", self.max_even_run().into()),
+            ("maxOddRun", self.max_odd_run().into()),
+            ("monotoneAfterPeak", self.monotone_after_peak().into()),
+            ("trajectorySum", self.trajectory_sum_string().into()),
+            ("outcome", self.outcome.as_str().into()),
+        ])
+        .into()
+    }
+}
+
+impl CollatzResult {
+    pub fn n(&self) -> u128 {
+        self.n
+    }
+
+    /// Highest value reached, 0 if the trajectory overflowed
+    pub fn max(&self) -> u128 {
+        self.max
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    /// Every value visited from n down to the first 1 added up, n and 1
+    /// included, plus 4 + 2 + 1 under `ThroughTrivialCycle`. Overflowed
+    /// trajectories stop at the last value before the overflow. It sticks at
+    /// `u128::MAX` once it doesn't fit, so any smaller sum is exact.
+    /// `None` unless this is a full record.
+    pub fn trajectory_sum(&self) -> Option<u128> {
+        self.extras.map(|extras| extras.trajectory_sum)
+    }
+
+    /// First value whose bit length is the furthest above the start's,
+    /// the shader's division-free stand-in for the largest `value / n`.
+    ///
+    /// Because it compares bit lengths, this is the first time the
+    /// trajectory reaches the bit length of its peak, which can be several
+    /// steps before `max` itself. `None` unless this is a full record.
+    pub fn height(&self) -> Option<u128> {
+        self.extras.map(|extras| extras.height)
+    }
+
+    /// Whether the optional fields were worked out, see
+    /// `RunConfig::full_record`
+    pub fn is_full_record(&self) -> bool {
+        self.extras.is_some()
+    }
+
+    /// Bits the peak has over the start, 0 if the trajectory overflowed or
+    /// saturated
+    pub fn bit_growth(&self) -> u32 {
+        if matches!(self.outcome, Outcome::Overflowed | Outcome::Saturated) {
+            return 0;
+        }
+        self.max.leading_zeros().abs_diff(self.n.leading_zeros())
+    }
+
+    pub fn source(&self) -> ResultSource {
+        self.source
+    }
+
+    // The same result, labelled as coming from `source`
+    pub(crate) fn with_source(self, source: ResultSource) -> Self {
+        CollatzResult { source, ..self }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_cpu(
+        n: u128,
+        steps: u32,
+        max: u128,
+        outcome: Outcome,
+        height_step: u32,
+        height: u128,
+        steps_shortcut: u32,
+        steps_to_power_of_two: Option<u32>,
+        (max_even_run, max_odd_run): (u32, u32),
+        trajectory_sum: u128,
+    ) -> Self {
+        CollatzResult {
+            n,
+            steps,
+            max,
+            outcome,
+            extras: Some(Extras {
+                height_step,
+                height,
+                steps_shortcut,
+                steps_to_power_of_two,
+                max_even_run,
+                max_odd_run,
+                trajectory_sum,
+            }),
+            source: ResultSource::Cpu,
+        }
+    }
+
+    // Decode one shader output record, RESULT_WORDS u32s or FULL_RESULT_WORDS
+    // for a full record
+    pub(crate) fn from_gpu_words(n: u128, words: &[u32]) -> Self {
+        let raw_steps = words[0];
+        let max = crate::u32_array_to_u128(&[words[1], words[2], words[3], words[4]]);
+        let steps = raw_steps & !(CYCLE_FLAG | SATURATED_FLAG);
+
+        let outcome = if raw_steps & CYCLE_FLAG != 0 {
+            Outcome::Cycle
+        } else if raw_steps & SATURATED_FLAG != 0 {
+            Outcome::Saturated
+        } else if max == 0 {
+            Outcome::Overflowed
+        } else if steps >= MAX_STEPS {
+            Outcome::Capped
+        } else {
+            Outcome::Converged
+        };
+
+        let extras = (words.len() == FULL_RESULT_WORDS).then(|| Extras {
+            height_step: words[5],
+            height: crate::u32_array_to_u128(&[words[6], words[7], words[8], words[9]]),
+            steps_shortcut: words[10],
+            steps_to_power_of_two: Some(words[11]).filter(|&step| step != NO_POWER_OF_TWO),
+            max_even_run: words[12] & 0xffff,
+            max_odd_run: words[12] >> 16,
+            trajectory_sum: crate::u32_array_to_u128(&[words[13], words[14], words[15], words[16]]),
+        });
+
+        CollatzResult {
+            n,
+            steps,
+            max,
+            outcome,
+            extras,
+            source: ResultSource::Gpu,
+        }
+    }
+}
+
+/// Size in bytes of one record written by `pack_results`.
+///
+/// Records are tightly packed with no padding, all fields little-endian:
+///
+/// | offset | size | field                                  |
+/// |--------|------|----------------------------------------|
+/// | 0      | 16   | n                                      |
+/// | 16     | 4    | steps                                  |
+/// | 20     | 16   | max (0 if the trajectory overflowed)   |
+///
+/// Record `i` always starts at `i * PACKED_RECORD_SIZE`, so a file of them
+/// can be memory-mapped and read at random with `packed_record`.
+pub const PACKED_RECORD_SIZE: usize = 36;
+
+/// Pack results into the `PACKED_RECORD_SIZE` byte record layout
+pub fn pack_results(results: &[CollatzResult]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(results.len() * PACKED_RECORD_SIZE);
+    for result in results {
+        bytes.extend_from_slice(&result.n.to_le_bytes());
+        bytes.extend_from_slice(&result.steps.to_le_bytes());
+        bytes.extend_from_slice(&result.max.to_le_bytes());
+    }
+    bytes
+}
+
+/// How `gpu_collatz_to_writer` formats results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    /// `PACKED_RECORD_SIZE` byte records, the same bytes as `pack_results`
+    #[default]
+    Packed,
+    /// A `n,steps,max,outcome` header line, then one line per result
+    Csv,
+}
+
+impl OutputFormat {
+    // Anything that goes before the first result
+    pub(crate) fn header(&self) -> &'static [u8] {
+        match self {
+            OutputFormat::Packed => b"",
+            OutputFormat::Csv => b"n,steps,max,outcome\n",
+        }
+    }
+
+    // Write `results` in this format, without the header, in one
+    // `write_all` so an unbuffered writer isn't hit once per result
+    pub(crate) fn write_results<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        results: &[CollatzResult],
+    ) -> std::io::Result<()> {
+        let bytes = match self {
+            OutputFormat::Packed => pack_results(results),
+            OutputFormat::Csv => {
+                let mut lines = String::new();
+                for result in results {
+                    let outcome = result.outcome.as_str();
+                    lines += &format!("{},{},{},{outcome}\n", result.n, result.steps, result.max);
+                }
+                lines.into_bytes()
+            }
+        };
+        writer.write_all(&bytes)
+    }
+}
+
+/// One record read back out of `pack_results` output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackedRecord {
+    pub n: u128,
+    /// Steps word as the shader wrote it, top bit set for a detected cycle
+    pub steps: u32,
+    pub max: u128,
+}
+
+/// Record `index` of packed bytes, `None` past the last whole record.
+///
+/// Only the record's own bytes are read, so `bytes` can be a memory-mapped
+/// file of any size written out from `pack_results`.
+pub fn packed_record(bytes: &[u8], index: usize) -> Option<PackedRecord> {
+    let offset = index.checked_mul(PACKED_RECORD_SIZE)?;
+    let record = bytes.get(offset..offset.checked_add(PACKED_RECORD_SIZE)?)?;
+    Some(PackedRecord {
+        n: u128::from_le_bytes(record[0..16].try_into().unwrap()),
+        steps: u32::from_le_bytes(record[16..20].try_into().unwrap()),
+        max: u128::from_le_bytes(record[20..36].try_into().unwrap()),
+    })
+}
+
+// Size of one record in an exported sweep (see `archive.rs`): n, steps, max,
+// outcome (1 byte), height_step, height, steps_shortcut, source (1 byte),
+// steps_to_power_of_two, max_even_run, max_odd_run, trajectory_sum, whether
+// it's a full record (1 byte)
+pub(crate) const ARCHIVE_RECORD_SIZE: usize = 16 + 4 + 16 + 1 + 4 + 16 + 4 + 1 + 4 + 4 + 4 + 16 + 1;
+
+impl CollatzResult {
+    // Every field of the result, little-endian in declaration order, with 0s
+    // for the fields a short record doesn't have
+    pub(crate) fn archive_bytes(&self) -> [u8; ARCHIVE_RECORD_SIZE] {
+        let outcome = match self.outcome {
+            Outcome::Converged => 0,
+            Outcome::Overflowed => 1,
+            Outcome::Capped => 2,
+            Outcome::Cycle => 3,
+            Outcome::Saturated => 4,
+        };
+        let source = match self.source {
+            ResultSource::Gpu => 0,
+            ResultSource::Cpu => 1,
+            ResultSource::Table => 2,
+            ResultSource::Derived => 3,
+        };
+
+        let mut bytes = [0; ARCHIVE_RECORD_SIZE];
+        let extras = self.extras.unwrap_or(Extras {
+            height_step: 0,
+            height: 0,
+            steps_shortcut: 0,
+            steps_to_power_of_two: None,
+            max_even_run: 0,
+            max_odd_run: 0,
+            trajectory_sum: 0,
+        });
+        let power_step = extras.steps_to_power_of_two.unwrap_or(NO_POWER_OF_TWO);
+        let fields: [&[u8]; 13] = [
+            &self.n.to_le_bytes(),
+            &self.steps.to_le_bytes(),
+            &self.max.to_le_bytes(),
+            &[outcome],
+            &extras.height_step.to_le_bytes(),
+            &extras.height.to_le_bytes(),
+            &extras.steps_shortcut.to_le_bytes(),
+            &[source],
+            &power_step.to_le_bytes(),
+            &extras.max_even_run.to_le_bytes(),
+            &extras.max_odd_run.to_le_bytes(),
+            &extras.trajectory_sum.to_le_bytes(),
+            &[self.extras.is_some() as u8],
+        ];
+        let mut offset = 0;
+        for field in fields {
+            bytes[offset..offset + field.len()].copy_from_slice(field);
+            offset += field.len();
+        }
+        bytes
+    }
+
+    // Read back `archive_bytes`, `None` for an unknown outcome, source or
+    // full record flag
+    pub(crate) fn from_archive_bytes(bytes: &[u8; ARCHIVE_RECORD_SIZE]) -> Option<Self> {
+        let u128_at = |at: usize| u128::from_le_bytes(bytes[at..at + 16].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+
+        let outcome = match bytes[36] {
+            0 => Outcome::Converged,
+            1 => Outcome::Overflowed,
+            2 => Outcome::Capped,
+            3 => Outcome::Cycle,
+            4 => Outcome::Saturated,
+            _ => return None,
+        };
+        let source = match bytes[61] {
+            0 => ResultSource::Gpu,
+            1 => ResultSource::Cpu,
+            2 => ResultSource::Table,
+            3 => ResultSource::Derived,
+            _ => return None,
+        };
+        let extras = match bytes[90] {
+            0 => None,
+            1 => Some(Extras {
+                height_step: u32_at(37),
+                height: u128_at(41),
+                steps_shortcut: u32_at(57),
+                steps_to_power_of_two: Some(u32_at(62)).filter(|&step| step != NO_POWER_OF_TWO),
+                max_even_run: u32_at(66),
+                max_odd_run: u32_at(70),
+                trajectory_sum: u128_at(74),
+            }),
+            _ => return None,
+        };
+
+        Some(CollatzResult {
+            n: u128_at(0),
+            steps: u32_at(16),
+            max: u128_at(20),
+            outcome,
+            extras,
+            source,
+        })
+    }
+}
+
+/// Why a mapped output buffer couldn't be decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes can't be viewed as u32s (bad alignment or length)
+    NotWords(bytemuck::PodCastError),
+    /// The buffer doesn't hold exactly one record per input number
+    WrongLength { expected: usize, actual: usize },
+    /// Record `index` is all zeros, so the shader never wrote it. No real
+    /// record is: a short one has a word that is always 1, a full one's
+    /// height is at least the start, and a start of 0 runs out of steps.
+    Unwritten { index: usize },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::NotWords(e) => write!(f, "Output buffer is not u32 words: {e}"),
+            DecodeError::WrongLength { expected, actual } => write!(
+                f,
+                "Output buffer is {actual} bytes, expected {expected} for the input numbers"
+            ),
+            DecodeError::Unwritten { index } => {
+                write!(f, "Output record {index} was never written by the shader")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Decode the mapped output buffer, one record per input number, full records
+// if `full_record`. Never panics on malformed output, it's reported as an
+// error instead.
+pub(crate) fn decode_results(
+    numbers: &[u128],
+    data: &[u8],
+    full_record: bool,
+) -> Result<Vec<CollatzResult>, DecodeError> {
+    let record_words = result_words(full_record);
+    let expected = numbers.len() * record_words * 4;
+    if data.len() != expected {
+        return Err(DecodeError::WrongLength {
+            expected,
+            actual: data.len(),
+        });
+    }
+    let results: &[u32] = bytemuck::try_cast_slice(data).map_err(DecodeError::NotWords)?;
+    if let Some(index) = results
+        .chunks_exact(record_words)
+        .position(|words| words.iter().all(|&word| word == 0))
+    {
+        return Err(DecodeError::Unwritten { index });
+    }
+
+    Ok(numbers
+        .iter()
+        .zip(results.chunks_exact(record_words))
+        .map(|(&n, words)| CollatzResult::from_gpu_words(n, words))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The short record the shader writes for 27: 111 steps, peak 9232
+    fn record_27() -> [u32; RESULT_WORDS] {
+        let mut words = [0; RESULT_WORDS];
+        words[0] = 111;
+        words[1] = 9232;
+        words[5] = 1;
+        words
+    }
+
+    #[test]
+    fn each_path_sets_its_source() {
+        let decoded = decode_results(&[27], bytemuck::cast_slice(&record_27()), false).unwrap();
+        assert_eq!(decoded[0].source(), ResultSource::Gpu);
+        assert_eq!(crate::cpu_collatz(27).source(), ResultSource::Cpu);
+
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let config = crate::RunConfig {
+            lookup_table: true,
+            ..crate::RunConfig::DEFAULT
+        };
+        let mut table = Vec::new();
+        pollster::block_on(crate::scan::scan(&session, 1, 100, &config, |results| {
+            table.extend_from_slice(results)
+        }))
+        .unwrap();
+        assert!(table
+            .iter()
+            .all(|result| result.source() == ResultSource::Table));
+        let repeated = pollster::block_on(session.run_distinct(&[27, 97, 27])).unwrap();
+        let sources: Vec<_> = repeated.iter().map(|result| result.source()).collect();
+        assert_eq!(
+            sources,
+            [ResultSource::Gpu, ResultSource::Gpu, ResultSource::Derived]
+        );
+    }
+
+    // The values the `CollatzResult` docs show JS reading for 27
+    #[test]
+    fn js_example_values() {
+        let r = crate::cpu_collatz(27);
+        assert_eq!(
+            (r.start_string(), r.steps(), r.steps_classic()),
+            ("27".into(), 111, 111)
+        );
+        assert_eq!(
+            (
+                r.steps_shortcut(),
+                r.total_halvings(),
+                r.steps_from_first_odd()
+            ),
+            (Some(70), Some(70), 111)
+        );
+        assert_eq!(
+            (r.max_string(), r.height_step(), r.height_string()),
+            ("9232".into(), Some(77), Some("9232".into()))
+        );
+        assert_eq!(
+            (r.steps_to_power_of_two(), r.completeness_x1000()),
+            (Some(107), Some(22_200))
+        );
+        assert_eq!(
+            (r.max_even_run(), r.max_odd_run(), r.monotone_after_peak()),
+            (Some(5), Some(6), false)
+        );
+        assert_eq!(
+            (r.trajectory_sum_string(), r.outcome_name()),
+            (Some("101440".into()), "converged".into())
+        );
+    }
+
+    #[test]
+    fn packed_bytes_decode_to_results() {
+        let numbers = [1, 27, 97, 1 << 100, u128::MAX];
+        let results: Vec<_> = numbers.iter().map(|&n| crate::cpu_collatz(n)).collect();
+        let bytes = pack_results(&results);
+        assert_eq!(bytes.len(), numbers.len() * PACKED_RECORD_SIZE);
+        for (record, result) in bytes.chunks_exact(PACKED_RECORD_SIZE).zip(&results) {
+            let n = u128::from_le_bytes(record[0..16].try_into().unwrap());
+            let steps = u32::from_le_bytes(record[16..20].try_into().unwrap());
+            let max = u128::from_le_bytes(record[20..36].try_into().unwrap());
+            assert_eq!((n, steps, max), (result.n(), result.steps(), result.max()));
+        }
+        assert_eq!(bytes[PACKED_RECORD_SIZE + 16], 111);
+        // u128::MAX overflows on its first step
+        assert_eq!(&bytes[4 * PACKED_RECORD_SIZE + 20..], &[0; 16]);
+    }
+
+    // Random buffers of random length and alignment, some of them the right
+    // length for their numbers. Seeded so a failure can be replayed.
+    #[test]
+    fn decode_fuzz() {
+        let mut rng = crate::sample::SplitMix64::new(0x1234_5678_9abc_def0);
+        let mut storage = vec![0u32; 4 * FULL_RESULT_WORDS + 1];
+        for _ in 0..20_000 {
+            let numbers: Vec<u128> = (0..rng.next_u64() % 4).map(|_| rng.next_u128()).collect();
+            let full_record = rng.next_u64().is_multiple_of(2);
+            let words = result_words(full_record);
+            let len = match rng.next_u64() % 3 {
+                0 => numbers.len() * words * 4,
+                _ => (rng.next_u64() % (4 * words as u64 * 4)) as usize,
+            };
+            let offset = (rng.next_u64() % 4) as usize;
+            for word in &mut storage {
+                // mostly zero words, so some records are unwritten
+                *word = if rng.next_u64().is_multiple_of(4) {
+                    0
+                } else {
+                    rng.next_u64() as u32
+                };
+            }
+            let bytes = &bytemuck::cast_slice::<u32, u8>(&storage)[offset..offset + len];
+            match decode_results(&numbers, bytes, full_record) {
+                Ok(results) => {
+                    assert_eq!(offset, 0);
+                    assert!(results.iter().map(|r| r.n()).eq(numbers.iter().copied()));
+                    assert!(results.iter().all(|r| r.is_full_record() == full_record));
+                }
+                Err(DecodeError::NotWords(_)) => assert_ne!(offset, 0),
+                Err(DecodeError::WrongLength { expected, actual }) => {
+                    assert_eq!((expected, actual), (numbers.len() * words * 4, len))
+                }
+                Err(DecodeError::Unwritten { index }) => assert!(index < numbers.len()),
+            }
+        }
+    }
+
+    // Steps of n under the shortcut map, and how many of them were odd
+    fn shortcut_steps(mut n: u128) -> (u32, u32) {
+        let (mut steps, mut odd) = (0, 0);
+        while n != 1 {
+            if n.is_multiple_of(2) {
+                n /= 2;
+            } else {
+                n = (3 * n + 1) >> 1;
+                odd += 1;
+            }
+            steps += 1;
+        }
+        (steps, odd)
+    }
+
+    #[test]
+    fn shortcut_steps_relationship() {
+        let check = |results: &[CollatzResult]| {
+            for r in results.iter().filter(|r| r.outcome() == Outcome::Converged) {
+                let (shortcut, odd) = shortcut_steps(r.n());
+                assert_eq!(r.steps_shortcut(), Some(shortcut), "n {}", r.n());
+                assert_eq!(Some(r.steps_classic()), r.steps_shortcut().map(|s| s + odd));
+            }
+            assert_eq!(
+                (results[26].steps(), results[26].steps_shortcut()),
+                (111, Some(70))
+            );
+        };
+        let numbers: Vec<u128> = (1..5000).chain([(1 << 100) + 7, (1 << 127) + 1]).collect();
+        let cpu: Vec<_> = numbers.iter().map(|&n| crate::cpu_collatz(n)).collect();
+        check(&cpu);
+
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let gpu = crate::session::run_full(&session, &numbers);
+        check(&gpu);
+        assert!(gpu
+            .iter()
+            .zip(&cpu)
+            .all(|(g, c)| g.steps_shortcut() == c.steps_shortcut()));
+    }
+
+    #[test]
+    fn packed_records_by_index() {
+        let results: Vec<_> = (1..2000)
+            .chain([(1 << 127) + 1])
+            .map(crate::cpu_collatz)
+            .collect();
+        let bytes = pack_results(&results);
+        for i in [0, 26, 1998, 1999] {
+            let record = packed_record(&bytes, i).unwrap();
+            let r = &results[i];
+            assert_eq!(
+                (record.n, record.steps, record.max),
+                (r.n(), r.steps(), r.max())
+            );
+        }
+        assert_eq!(packed_record(&bytes, 26).unwrap().steps, 111);
+        assert_eq!(packed_record(&bytes, 2000), None);
+        assert_eq!(packed_record(&bytes[..bytes.len() - 1], 1999), None);
+        assert_eq!(packed_record(&bytes, usize::MAX), None);
+    }
+
+    #[test]
+    fn completeness_matches_host() {
+        let numbers = [
+            1,
+            2,
+            3,
+            7,
+            27,
+            97,
+            871,
+            1 << 40,
+            0xffff_ffff_ffff,
+            u128::MAX,
+        ];
+        let results: Vec<_> = numbers.into_iter().map(crate::cpu_collatz).collect();
+        for result in &results {
+            let want = (result.outcome() == Outcome::Converged).then(|| {
+                let bits = 128 - result.n().leading_zeros();
+                (result.steps() as f64 * 1000.0 / bits as f64).floor() as u32
+            });
+            assert_eq!(result.completeness_x1000(), want, "n {}", result.n());
+        }
+        assert_eq!(results[0].completeness_x1000(), Some(0));
+        // 27 has 5 bits and takes 111 steps
+        assert_eq!(results[4].completeness_x1000(), Some(22_200));
+        assert_eq!(results[9].completeness_x1000(), None);
+    }
+
+    #[test]
+    fn monotone_after_peak_matches_host() {
+        let results: Vec<_> = (1..20_000)
+            .chain([u128::MAX])
+            .map(crate::cpu_collatz)
+            .collect();
+        for result in &results {
+            let path = crate::cpu::walk(result.n());
+            let peak = path
+                .iter()
+                .rposition(|v| v == path.iter().max().unwrap())
+                .unwrap();
+            let falls = path[peak..].windows(2).all(|pair| pair[1] < pair[0]);
+            let want = path.last() == Some(&1) && falls;
+            assert_eq!(result.monotone_after_peak(), want, "n {}", result.n());
+        }
+        // 27 peaks at 9232 and then bumps up again
+        assert!(!results[26].monotone_after_peak());
+        // 5 -> 16 -> 8 -> 4 -> 2 -> 1
+        assert!(results[4].monotone_after_peak());
+        assert!(!results.last().unwrap().monotone_after_peak());
+    }
+
+    #[test]
+    fn steps_from_first_odd_strips_leading_halvings() {
+        for k in (1..2000).step_by(2) {
+            let odd = crate::cpu_collatz(k);
+            assert_eq!(odd.steps_from_first_odd(), odd.steps());
+            for j in 1..6 {
+                let even = crate::cpu_collatz(k << j);
+                assert_eq!(even.steps_from_first_odd(), odd.steps(), "n {}", k << j);
+                assert_eq!(even.steps(), odd.steps() + j);
+            }
+        }
+    }
+}
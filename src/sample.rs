@@ -0,0 +1,89 @@
+// SplitMix64, small and good enough for picking sample points. Seeded so the
+// same arguments always sample the same numbers.
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn next_u128(&mut self) -> u128 {
+        (self.next_u64() as u128) << 64 | self.next_u64() as u128
+    }
+}
+
+// `size` numbers picked uniformly, with replacement, from start..start + count
+pub(crate) fn sample_range(start: u128, count: u32, size: u32, seed: u64) -> Vec<u128> {
+    let mut rng = SplitMix64::new(seed);
+    (0..size)
+        .map(|_| start + (rng.next_u64() % count as u64) as u128)
+        .collect()
+}
+
+// `samples` numbers picked uniformly, with replacement, from start..start +
+// size, where size can be anything up to u128::MAX. Draws below
+// 2^128 mod size are thrown away so every number is equally likely even
+// when size doesn't divide 2^128. size must not be 0.
+pub(crate) fn sample_big_range(start: u128, size: u128, samples: u32, seed: u64) -> Vec<u128> {
+    let mut rng = SplitMix64::new(seed);
+    let threshold = size.wrapping_neg() % size;
+    (0..samples)
+        .map(|_| loop {
+            let draw = rng.next_u128();
+            if draw >= threshold {
+                break start + draw % size;
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu_collatz, ConvergenceSummary};
+
+    // Share of a seeded sample of a million numbers from `start` that
+    // overflow, the way `do_gpu_collatz_overflow_rate` estimates it
+    fn overflow_rate(start: u128) -> f64 {
+        let numbers = sample_range(start, 1_000_000, 2000, 7);
+        assert_eq!(numbers, sample_range(start, 1_000_000, 2000, 7));
+        assert!(numbers.iter().all(|&n| n >= start && n < start + 1_000_000));
+        let mut summary = ConvergenceSummary::default();
+        summary.add(&numbers.iter().map(|&n| cpu_collatz(n)).collect::<Vec<_>>());
+        summary.overflowed as f64 / 2000.0
+    }
+
+    #[test]
+    fn overflow_rate_by_region() {
+        assert_eq!(overflow_rate(1), 0.0);
+        assert!(overflow_rate(1 << 127) > 0.5);
+        assert_ne!(sample_range(1, 1000, 50, 1), sample_range(1, 1000, 50, 2));
+    }
+
+    #[test]
+    fn big_range_sampling_reproducible() {
+        let (start, size) = (1 << 100, (1 << 126) + 12345);
+        let samples = sample_big_range(start, size, 3000, 42);
+        assert_eq!(samples, sample_big_range(start, size, 3000, 42));
+        assert_ne!(samples, sample_big_range(start, size, 3000, 43));
+        assert!(samples.iter().all(|&n| n >= start && n < start + size));
+        let above_half = samples.iter().filter(|&&n| n - start >= size / 2).count();
+        assert!((1300..1700).contains(&above_half), "{above_half}");
+
+        let small = sample_big_range(5, 3, 3000, 1);
+        for value in 5..8 {
+            let count = small.iter().filter(|&&n| n == value).count();
+            assert!((850..1150).contains(&count), "{value}: {count}");
+        }
+        let everything = sample_big_range(1, u128::MAX - 1, 10, 7);
+        assert!(everything.iter().all(|&n| n >= 1));
+    }
+}
@@ -0,0 +1,999 @@
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{
+    create_pipeline_for, max_numbers_per_dispatch, submit_chunk, InputLayout, PendingChunk,
+    ReadbackStrategy,
+};
+use crate::result::{result_words, CollatzResult, StepConvention};
+use crate::session::{sleep_ms, CollatzSession};
+
+// GPU memory per number in a chunk: 16 input bytes, then the output and
+// staging bytes, 32 each or 68 each for a full record
+const fn bytes_per_number(full_record: bool) -> u64 {
+    16 + 2 * 4 * result_words(full_record) as u64
+}
+
+// Enough for the page to get a frame in between chunks in the browser
+const DEFAULT_THROTTLE_MS: u32 = if cfg!(target_arch = "wasm32") { 4 } else { 0 };
+
+/// How a scan over a large range is split into GPU dispatches
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunConfig {
+    /// Numbers computed per dispatch, clamped to what the device can cover
+    /// in one dispatch. Each number costs 80 bytes of GPU memory
+    /// (16 input, 32 output, 32 staging), 152 with `full_record`. 0, the default, uses the chunk
+    /// size calibrated for the session (see `recommended_chunk_size`), which
+    /// costs a few timed dispatches the first time a session needs it.
+    pub chunk_size: u32,
+    /// Most chunks submitted but not yet read back at once. Later chunks are
+    /// only submitted once the oldest one has been read, so GPU memory stays
+    /// under `chunk_size * max_in_flight` times the bytes per number.
+    pub max_in_flight: u32,
+    /// Go down from the start instead of up: start, start - 1, ...,
+    /// start - count + 1
+    pub descending: bool,
+    /// Whether steps stop at the first 1 or go round 1 -> 4 -> 2 -> 1 once
+    pub step_convention: StepConvention,
+    /// How numbers are laid out in the input buffer. All give the same
+    /// results. Struct of arrays is there for comparing memory access
+    /// patterns on different GPUs, packed u32 cuts the upload to a quarter
+    /// for ranges below 2^32.
+    pub input_layout: InputLayout,
+    /// Finish trajectories off from a table once they drop below 2^16
+    /// instead of iterating down to 1. Same results, fewer iterations for
+    /// the many numbers that fall below 2^16 long before reaching 1. The
+    /// table (2 MiB) is uploaded once per session.
+    pub lookup_table: bool,
+    /// Milliseconds to wait after reading back each chunk before carrying
+    /// on, so a long scan doesn't starve the compositor on a shared GPU.
+    /// Defaults to a few milliseconds on wasm and 0 natively.
+    pub throttle_ms: u32,
+    /// How each chunk is read back. `Windowed` keeps a chunk's staging
+    /// buffer to 1 MiB instead of its 32 bytes per number (68 with
+    /// `full_record`), for backends that map large buffers slowly. Both give
+    /// the same results.
+    pub readback_strategy: ReadbackStrategy,
+    /// Consecutive numbers each GPU thread runs, 0 and 1 both meaning one.
+    /// Fewer, longer threads per dispatch, which can pay off on GPUs where
+    /// launching threads costs more than the short trajectories in them.
+    /// Same results and output layout whatever it is set to, except on
+    /// llvmpipe, which cuts a thread's loop short after 65535 iterations in
+    /// total, so keep it small there.
+    pub numbers_per_thread: u32,
+    /// Clamp a 3n + 1 that overflows 128 bits to `u128::MAX` and end the
+    /// trajectory there as `Outcome::Saturated`, instead of reporting it as
+    /// `Overflowed` with a max of 0. Saturated results are approximate: the
+    /// max is a lower bound and the steps stop at the clamp.
+    pub saturate_overflow: bool,
+    /// Also work out and read back the height record, shortcut steps, step
+    /// to the first power of two, longest runs and trajectory sum. Off by
+    /// default: records are then 32 bytes instead of 68, the shader skips
+    /// the bookkeeping, and those fields of each result are `undefined`.
+    pub full_record: bool,
+}
+
+#[wasm_bindgen]
+impl RunConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RunConfig {
+        RunConfig::default()
+    }
+
+    /// Use the largest chunk size that keeps all `max_in_flight` chunks
+    /// within `megabytes` of GPU memory
+    #[wasm_bindgen(js_name = withMaxMemory)]
+    pub fn with_max_memory(mut self, megabytes: u32) -> RunConfig {
+        let budget = megabytes as u64 * 1024 * 1024;
+        let per_chunk =
+            budget / (self.max_in_flight.max(1) as u64 * bytes_per_number(self.full_record));
+        self.chunk_size = per_chunk.clamp(1, u32::MAX as u64) as u32;
+        self
+    }
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        RunConfig::DEFAULT
+    }
+}
+
+impl RunConfig {
+    // `Default::default()`, usable in consts
+    pub(crate) const DEFAULT: RunConfig = RunConfig {
+        chunk_size: 0,
+        max_in_flight: 3,
+        descending: false,
+        step_convention: StepConvention::UntilOne,
+        input_layout: InputLayout::ArrayOfStructs,
+        lookup_table: false,
+        throttle_ms: DEFAULT_THROTTLE_MS,
+        readback_strategy: ReadbackStrategy::Auto,
+        numbers_per_thread: 1,
+        saturate_overflow: false,
+        full_record: false,
+    };
+
+    // Check `count` numbers from `start` in this config's direction stay
+    // within 1..=u128::MAX
+    pub(crate) fn check_range(&self, start: u128, count: u64) -> Result<(), CollatzError> {
+        if !self.descending {
+            return crate::check_range(start, count);
+        }
+        if start == 0 {
+            return Err(CollatzError::InvalidStart);
+        }
+        if count == 0 {
+            return Err(CollatzError::EmptyRange);
+        }
+        if count as u128 > start {
+            return Err(CollatzError::RangeUnderflow);
+        }
+        Ok(())
+    }
+}
+
+// Run `count` numbers from `start` in chunks, handing each chunk's results to
+// `on_chunk` in order. The range must already have been checked with
+// `RunConfig::check_range`.
+pub(crate) async fn scan<F>(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    config: &RunConfig,
+    mut on_chunk: F,
+) -> Result<(), CollatzError>
+where
+    F: FnMut(&[CollatzResult]),
+{
+    scan_until(session, start, count, config, |results| {
+        on_chunk(results);
+        ControlFlow::Continue(())
+    })
+    .await
+}
+
+// Same as `scan`, but stops without reading any more chunks as soon as
+// `on_chunk` breaks
+pub(crate) async fn scan_until<F>(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    config: &RunConfig,
+    mut on_chunk: F,
+) -> Result<(), CollatzError>
+where
+    F: FnMut(&[CollatzResult]) -> ControlFlow<()>,
+{
+    let mut failure = None;
+    scan_chunks(session, start, count, config, |_, results| match results {
+        Ok(results) => on_chunk(&results),
+        Err(e) => {
+            failure = Some(e);
+            ControlFlow::Break(())
+        }
+    })
+    .await;
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Room to reserve for the results of a scan: the first chunk only, the rest
+// grows as chunks come back rather than reserving the whole range (which
+// `as usize` would also truncate on wasm32)
+pub(crate) fn first_chunk_capacity(count: u64, config: &RunConfig) -> usize {
+    let first_chunk = match config.chunk_size {
+        0 => crate::RANGE,
+        chunk_size => chunk_size,
+    };
+    count.min(u64::from(first_chunk)) as usize
+}
+
+// Every result of `scan` up to the first chunk that fails, and that chunk's
+// error. Chunks are read back in order, so the results always cover a
+// prefix of the range.
+pub(crate) async fn scan_partial(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    config: &RunConfig,
+) -> (Vec<CollatzResult>, Option<CollatzError>) {
+    let mut results = Vec::with_capacity(first_chunk_capacity(count, config));
+    let error = scan(session, start, count, config, |chunk| {
+        results.extend_from_slice(chunk)
+    })
+    .await
+    .err();
+    (results, error)
+}
+
+/// A chunk of a scan that couldn't be read back: `count` numbers from
+/// `start`, ascending whichever way the scan went
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedRange {
+    start: u128,
+    count: u32,
+    error: String,
+}
+
+#[wasm_bindgen]
+impl FailedRange {
+    #[wasm_bindgen(getter = start)]
+    pub fn start_string(&self) -> String {
+        self.start.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Why the chunk failed
+    #[wasm_bindgen(getter)]
+    pub fn error(&self) -> String {
+        self.error.clone()
+    }
+}
+
+impl FailedRange {
+    pub fn start(&self) -> u128 {
+        self.start
+    }
+}
+
+// Same as `scan`, but a chunk that fails is recorded and skipped instead of
+// ending the scan. Returns the failed chunks in scan order.
+pub(crate) async fn scan_recovering<F>(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    config: &RunConfig,
+    mut on_chunk: F,
+) -> Vec<FailedRange>
+where
+    F: FnMut(&[CollatzResult]),
+{
+    let mut failed = Vec::new();
+    scan_chunks(session, start, count, config, |(start, count), results| {
+        match results {
+            Ok(results) => on_chunk(&results),
+            Err(e) => failed.push(FailedRange {
+                start,
+                count,
+                error: e.to_string(),
+            }),
+        }
+        ControlFlow::Continue(())
+    })
+    .await;
+    failed
+}
+
+// Submit and read back every chunk, handing each one's range (lowest number,
+// count) and results or read error to `on_chunk` in order, until it breaks
+async fn scan_chunks<F>(
+    session: &CollatzSession,
+    start: u128,
+    count: u64,
+    config: &RunConfig,
+    mut on_chunk: F,
+) where
+    F: FnMut((u128, u32), Result<Vec<CollatzResult>, CollatzError>) -> ControlFlow<()>,
+{
+    let device = &session.device;
+    let chunk_size = match config.chunk_size {
+        // a failed calibration leaves the chunk size as it was before there
+        // was one, the error will show up again on the real chunks
+        0 => session.recommend_chunk_size().await.unwrap_or(crate::RANGE),
+        chunk_size => chunk_size,
+    };
+    let chunk_size = (chunk_size as u64).clamp(1, max_numbers_per_dispatch(device));
+    let max_in_flight = config.max_in_flight.max(1) as usize;
+
+    // ranges are contiguous, so the end furthest from 0 is the largest
+    let largest = if config.descending {
+        start
+    } else {
+        start + (count as u128).saturating_sub(1)
+    };
+    let layout = match config.input_layout {
+        InputLayout::PackedU32 if largest > u32::MAX as u128 => InputLayout::ArrayOfStructs,
+        layout => layout,
+    };
+
+    // the session's pipeline stops at 1, reads array of structs input, has
+    // no lookup table, runs one number per thread, reports overflows and
+    // writes short records, other options need their own
+    let per_thread = config.numbers_per_thread.max(1);
+    let custom_pipeline;
+    let pipeline = match (
+        config.step_convention,
+        layout,
+        config.lookup_table,
+        per_thread,
+        config.saturate_overflow,
+        config.full_record,
+    ) {
+        (StepConvention::UntilOne, InputLayout::ArrayOfStructs, false, 1, false, false) => {
+            &session.pipeline
+        }
+        (convention, layout, lookup, per_thread, saturate, full_record) => {
+            custom_pipeline = create_pipeline_for(
+                device,
+                convention,
+                layout,
+                lookup,
+                per_thread,
+                saturate,
+                full_record,
+            );
+            &custom_pipeline
+        }
+    };
+    let lookup = config.lookup_table.then(|| session.lookup_table());
+    let readback = config
+        .readback_strategy
+        .resolve(session.adapter_info.as_ref());
+
+    let mut in_flight: VecDeque<PendingChunk> = VecDeque::with_capacity(max_in_flight);
+    let mut offset = 0;
+
+    while offset < count {
+        // wait for the oldest chunk to come back before going over the limit
+        if in_flight.len() == max_in_flight {
+            if let Some(chunk) = in_flight.pop_front() {
+                let range = chunk.range();
+                if on_chunk(range, chunk.read(device).await).is_break() {
+                    return;
+                }
+                throttle(config).await;
+            }
+        }
+
+        let len = (count - offset).min(chunk_size);
+        let numbers = if config.descending {
+            let first = start - offset as u128;
+            (first - len as u128 + 1..=first).rev().collect()
+        } else {
+            let first = start + offset as u128;
+            (first..first + len as u128).collect()
+        };
+        in_flight.push_back(submit_chunk(
+            device,
+            &session.queue,
+            pipeline,
+            numbers,
+            layout,
+            per_thread,
+            lookup,
+            readback,
+            config.full_record,
+            max_in_flight,
+        ));
+        offset += len;
+    }
+
+    while let Some(chunk) = in_flight.pop_front() {
+        let range = chunk.range();
+        if on_chunk(range, chunk.read(device).await).is_break() {
+            return;
+        }
+        if !in_flight.is_empty() {
+            throttle(config).await;
+        }
+    }
+}
+
+// Wait out the config's `throttle_ms` between two chunks
+async fn throttle(config: &RunConfig) {
+    if config.throttle_ms > 0 {
+        sleep_ms(config.throttle_ms).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::FAIL_CHUNK_WITH;
+    use crate::session::test_session;
+
+    fn scan_all(
+        session: &CollatzSession,
+        start: u128,
+        count: u64,
+        config: &RunConfig,
+    ) -> Vec<CollatzResult> {
+        let mut all = Vec::new();
+        pollster::block_on(scan(session, start, count, config, |results| {
+            all.extend_from_slice(results)
+        }))
+        .unwrap();
+        all
+    }
+
+    #[test]
+    fn chunked_scan_matches_single_run() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let numbers: Vec<u128> = (1000..3500).collect();
+        let single = pollster::block_on(session.run(&numbers)).unwrap();
+        for (chunk_size, max_in_flight) in [(1, 1), (7, 3), (100, 2), (5000, 3), (333, 10)] {
+            let config = RunConfig {
+                chunk_size,
+                max_in_flight,
+                ..RunConfig::DEFAULT
+            };
+            let got = scan_all(&session, 1000, 2500, &config);
+            assert_eq!(got, single, "chunk {chunk_size} in flight {max_in_flight}");
+        }
+    }
+
+    #[test]
+    fn memory_budget_chunks_match_single_run() {
+        let config = RunConfig::new().with_max_memory(1);
+        assert_eq!(
+            config.chunk_size as u64,
+            (1 << 20) / (config.max_in_flight as u64 * bytes_per_number(false))
+        );
+        assert_eq!(RunConfig::new().with_max_memory(0).chunk_size, 1);
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        let numbers: Vec<u128> = (1..=5000).collect();
+        let single = pollster::block_on(session.run(&numbers)).unwrap();
+        assert!((config.chunk_size as usize) < numbers.len());
+        assert_eq!(scan_all(&session, 1, 5000, &config), single);
+    }
+
+    #[test]
+    fn descending_scan_matches_ascending() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let up = pollster::block_on(session.run(&(1..=777).collect::<Vec<_>>())).unwrap();
+        let config = RunConfig {
+            chunk_size: 100,
+            max_in_flight: 2,
+            descending: true,
+            ..RunConfig::DEFAULT
+        };
+        config.check_range(777, 777).unwrap();
+        let mut down = scan_all(&session, 777, 777, &config);
+        assert_eq!(down[0].n(), 777);
+        down.reverse();
+        assert_eq!(down, up);
+    }
+
+    #[test]
+    fn step_conventions_match_host() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        for step_convention in [
+            StepConvention::UntilOne,
+            StepConvention::ThroughTrivialCycle,
+        ] {
+            let config = RunConfig {
+                step_convention,
+                ..RunConfig::DEFAULT
+            };
+            let results = scan_all(&session, 1, 1000, &config);
+            for result in &results {
+                let want = crate::cpu_steps(result.n(), step_convention);
+                assert_eq!(Some(result.steps()), want, "n {}", result.n());
+            }
+            if step_convention == StepConvention::ThroughTrivialCycle {
+                // going round 1 -> 4 -> 2 -> 1 peaks at 4
+                assert_eq!(
+                    (results[0].max(), results[1].max(), results[2].max()),
+                    (4, 4, 16)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn recovering_scan_skips_failed_chunk() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        FAIL_CHUNK_WITH.set(Some(2500));
+        let config = RunConfig {
+            chunk_size: 1000,
+            ..RunConfig::DEFAULT
+        };
+        let mut results = Vec::new();
+        let failed = pollster::block_on(scan_recovering(&session, 1, 5000, &config, |chunk| {
+            results.extend_from_slice(chunk)
+        }));
+        let plain = pollster::block_on(scan(&session, 1, 5000, &config, |_| {}));
+        let descending = RunConfig {
+            descending: true,
+            ..config
+        };
+        let failed_down =
+            pollster::block_on(scan_recovering(&session, 5000, 5000, &descending, |_| {}));
+        FAIL_CHUNK_WITH.set(None);
+
+        assert_eq!(failed.len(), 1);
+        assert_eq!((failed[0].start(), failed[0].count()), (2001, 1000));
+        let want: Vec<u128> = (1..=2000).chain(3001..=5000).collect();
+        assert!(results.iter().map(|result| result.n()).eq(want));
+        assert!(plain.is_err());
+        let failed_down: Vec<_> = failed_down.iter().map(|f| (f.start(), f.count())).collect();
+        assert_eq!(failed_down, [(2001, 1000)]);
+    }
+
+    #[test]
+    fn throttled_scan_completes() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        assert_eq!(RunConfig::DEFAULT.throttle_ms, DEFAULT_THROTTLE_MS);
+        let config = RunConfig {
+            chunk_size: 500,
+            throttle_ms: 20,
+            ..RunConfig::DEFAULT
+        };
+        let started = std::time::Instant::now();
+        let got = scan_all(&session, 1, 3000, &config);
+        assert!(started.elapsed() >= std::time::Duration::from_millis(100));
+        let want: Vec<_> = (1..=3000).map(crate::cpu_collatz).collect();
+        assert!(crate::results_agree(&got, &want).is_empty());
+    }
+
+    #[test]
+    fn windowed_readback_matches_direct() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        // One chunk spanning a few staging windows, the last one partial
+        let read = |readback_strategy| {
+            let config = RunConfig {
+                chunk_size: 40_000,
+                readback_strategy,
+                ..RunConfig::DEFAULT
+            };
+            scan_all(&session, 1, 40_000, &config)
+        };
+        let direct = read(ReadbackStrategy::Direct);
+        assert_eq!(direct.len(), 40_000);
+        assert_eq!(read(ReadbackStrategy::Windowed), direct);
+        assert_eq!(read(ReadbackStrategy::Auto), direct);
+    }
+
+    #[test]
+    fn lookup_kernel_matches_plain() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let ranges = [
+            (1, 20_000),
+            ((1 << 100) + 1, 2000),
+            (u128::MAX - 2000, 2000),
+            (1 << 64, 2000),
+        ];
+        for (start, count) in ranges {
+            for step_convention in [
+                StepConvention::UntilOne,
+                StepConvention::ThroughTrivialCycle,
+            ] {
+                for input_layout in [InputLayout::ArrayOfStructs, InputLayout::StructOfArrays] {
+                    let plain = RunConfig {
+                        step_convention,
+                        input_layout,
+                        full_record: true,
+                        ..RunConfig::DEFAULT
+                    };
+                    let lookup = RunConfig {
+                        lookup_table: true,
+                        ..plain
+                    };
+                    let want = scan_all(&session, start, count, &plain);
+                    let got = scan_all(&session, start, count, &lookup);
+                    assert_eq!(want.len(), count as usize);
+                    let context = format!("start {start} {step_convention:?} {input_layout:?}");
+                    assert!(crate::results_agree(&got, &want).is_empty(), "{context}");
+                    let shortcut = |results: &[CollatzResult]| -> Vec<Option<u32>> {
+                        results
+                            .iter()
+                            .map(|result| result.steps_shortcut())
+                            .collect()
+                    };
+                    assert_eq!(shortcut(&got), shortcut(&want), "{context}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn power_of_two_steps_match_host() {
+        assert_eq!(crate::cpu_collatz(27).steps_to_power_of_two(), Some(107));
+        assert_eq!(crate::cpu_collatz(64).steps_to_power_of_two(), Some(0));
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        for lookup_table in [false, true] {
+            let config = RunConfig {
+                lookup_table,
+                full_record: true,
+                ..RunConfig::DEFAULT
+            };
+            for start in [1, 1 << 40, u128::MAX / 3 - 5000] {
+                for result in scan_all(&session, start, 5000, &config) {
+                    let want = (result.outcome() == crate::Outcome::Converged)
+                        .then(|| crate::cpu::walk(result.n()))
+                        .and_then(|path| path.iter().position(|v| v.is_power_of_two()))
+                        .map(|steps| steps as u32);
+                    let n = result.n();
+                    assert_eq!(result.steps_to_power_of_two(), want, "n {n} {lookup_table}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partial_scan_keeps_prefix() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let config = RunConfig {
+            chunk_size: 1000,
+            max_in_flight: 3,
+            ..RunConfig::DEFAULT
+        };
+        FAIL_CHUNK_WITH.set(Some(2500));
+        let (results, error) = pollster::block_on(scan_partial(&session, 1, 5000, &config));
+        FAIL_CHUNK_WITH.set(None);
+        assert!(error.is_some());
+        let want: Vec<_> = (1..=2000).map(crate::cpu_collatz).collect();
+        assert!(results.iter().map(|result| result.n()).eq(1..=2000));
+        assert!(crate::results_agree(&results, &want).is_empty());
+
+        let (full, error) = pollster::block_on(scan_partial(&session, 1, 5000, &config));
+        assert!(error.is_none() && full.len() == 5000);
+    }
+
+    #[test]
+    fn packed_u32_layout_matches_normal() {
+        // 5 starts pack into 20 bytes, padded with 0s to a whole 16 byte slot
+        assert_eq!(
+            crate::gpu::input_data(&[1, 2, 3, 4, 5], InputLayout::PackedU32).len(),
+            32
+        );
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        let top = u32::MAX as u128;
+        let ranges = [
+            (1, 20_001, false),
+            (top - 9000, 9001, false),
+            (30_000, 29_999, true),
+            (top - 5, 11, false),
+        ];
+        for (start, count, descending) in ranges {
+            for lookup_table in [false, true] {
+                let read = |input_layout| {
+                    let config = RunConfig {
+                        chunk_size: 4099,
+                        input_layout,
+                        descending,
+                        lookup_table,
+                        ..RunConfig::DEFAULT
+                    };
+                    scan_all(&session, start, count, &config)
+                };
+                let normal = read(InputLayout::ArrayOfStructs);
+                assert_eq!(normal.len() as u64, count);
+                let context = format!("start {start} descending {descending} {lookup_table}");
+                assert_eq!(read(InputLayout::PackedU32), normal, "{context}");
+            }
+        }
+    }
+
+    #[test]
+    fn total_halvings_counts_even_steps() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        for step_convention in [
+            StepConvention::UntilOne,
+            StepConvention::ThroughTrivialCycle,
+        ] {
+            for lookup_table in [false, true] {
+                let config = RunConfig {
+                    step_convention,
+                    lookup_table,
+                    full_record: true,
+                    ..RunConfig::DEFAULT
+                };
+                for result in scan_all(&session, 1, 2999, &config) {
+                    let path = crate::cpu::walk(result.n());
+                    let mut halvings = path[..path.len() - 1]
+                        .iter()
+                        .filter(|v| v.is_multiple_of(2))
+                        .count() as u32;
+                    // 1 -> 4 -> 2 -> 1 halves twice
+                    if step_convention == StepConvention::ThroughTrivialCycle {
+                        halvings += 2;
+                    }
+                    let n = result.n();
+                    assert_eq!(
+                        result.total_halvings(),
+                        Some(halvings),
+                        "n {n} {step_convention:?} {lookup_table}"
+                    );
+                }
+            }
+        }
+    }
+
+    // Longest run of halvings and of shortcut odd steps in n's trajectory,
+    // from its parities on a host walk
+    fn host_runs(n: u128, through_trivial_cycle: bool) -> (Option<u32>, Option<u32>) {
+        let path = crate::cpu::walk(n);
+        let mut odd: Vec<bool> = path[..path.len() - 1].iter().map(|v| v % 2 == 1).collect();
+        if through_trivial_cycle && path.last() == Some(&1) {
+            odd.extend([true, false, false]);
+        }
+        let longest = |runs: &mut dyn Iterator<Item = bool>| {
+            let (mut longest, mut run) = (0, 0);
+            for extends in runs {
+                run = if extends { run + 1 } else { 0 };
+                longest = u32::max(longest, run);
+            }
+            longest
+        };
+        let max_even = longest(&mut odd.iter().map(|&odd| !odd));
+        // under the shortcut map 3n + 1 and the halving after it are one step
+        let mut shortcut = Vec::new();
+        let mut i = 0;
+        while i < odd.len() {
+            shortcut.push(odd[i]);
+            i += if odd[i] { 2 } else { 1 };
+        }
+        let max_odd = longest(&mut shortcut.into_iter());
+        (Some(max_even), Some(max_odd))
+    }
+
+    #[test]
+    fn runs_match_host() {
+        let big: Vec<u128> = (0..2000)
+            .map(|i| (1 << 90) + i * 7919)
+            .chain([(1 << 100) - 1, u128::MAX, 27])
+            .collect();
+        for &n in &big {
+            let result = crate::cpu_collatz(n);
+            assert_eq!(
+                (result.max_even_run(), result.max_odd_run()),
+                host_runs(n, false),
+                "n {n}"
+            );
+        }
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        for step_convention in [
+            StepConvention::UntilOne,
+            StepConvention::ThroughTrivialCycle,
+        ] {
+            for lookup_table in [false, true] {
+                let config = RunConfig {
+                    step_convention,
+                    lookup_table,
+                    full_record: true,
+                    ..RunConfig::DEFAULT
+                };
+                let through = step_convention == StepConvention::ThroughTrivialCycle;
+                for result in scan_all(&session, 1, 20_000, &config) {
+                    let got = (result.max_even_run(), result.max_odd_run());
+                    let n = result.n();
+                    assert_eq!(
+                        got,
+                        host_runs(n, through),
+                        "n {n} {step_convention:?} {lookup_table}"
+                    );
+                }
+            }
+        }
+        for (result, &n) in crate::session::run_full(&session, &big).iter().zip(&big) {
+            assert_eq!(
+                (result.max_even_run(), result.max_odd_run()),
+                host_runs(n, false),
+                "n {n}"
+            );
+        }
+    }
+
+    #[test]
+    fn numbers_per_thread_matches_one_per_thread() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let cases = [
+            (false, InputLayout::ArrayOfStructs, 1),
+            (true, InputLayout::ArrayOfStructs, u128::MAX - 10_000),
+            (false, InputLayout::PackedU32, 1),
+            (true, InputLayout::StructOfArrays, 1 << 70),
+        ];
+        for (lookup_table, input_layout, start) in cases {
+            let one = RunConfig {
+                chunk_size: 3001,
+                lookup_table,
+                input_layout,
+                ..RunConfig::DEFAULT
+            };
+            let want = scan_all(&session, start, 10_000, &one);
+            // llvmpipe cuts a loop short after 65535 iterations in one
+            // invocation, so stay well below that many steps per thread
+            for numbers_per_thread in [0, 2, 3, 7, 64] {
+                let config = RunConfig {
+                    numbers_per_thread,
+                    ..one
+                };
+                let got = scan_all(&session, start, 10_000, &config);
+                assert_eq!(got, want, "{numbers_per_thread} per thread, start {start}");
+            }
+        }
+    }
+
+    #[test]
+    fn trajectory_sum_matches_host() {
+        assert_eq!(crate::cpu_collatz(27).trajectory_sum(), Some(101_440));
+
+        let Some(session) = test_session() else {
+            return;
+        };
+        for lookup_table in [false, true] {
+            for step_convention in [
+                StepConvention::UntilOne,
+                StepConvention::ThroughTrivialCycle,
+            ] {
+                let config = RunConfig {
+                    lookup_table,
+                    step_convention,
+                    full_record: true,
+                    ..RunConfig::DEFAULT
+                };
+                // the trip round the trivial cycle adds 4 + 2 + 1
+                let extra = match step_convention {
+                    StepConvention::UntilOne => 0,
+                    StepConvention::ThroughTrivialCycle => 7,
+                };
+                for result in scan_all(&session, 1, 20_000, &config) {
+                    let want = crate::cpu::walk(result.n()).iter().sum::<u128>() + extra;
+                    assert_eq!(
+                        result.trajectory_sum(),
+                        Some(want),
+                        "n {} {lookup_table}",
+                        result.n()
+                    );
+                }
+            }
+        }
+
+        // huge starts either saturate or are exact, the same as on the host
+        let config = RunConfig {
+            lookup_table: true,
+            full_record: true,
+            ..RunConfig::DEFAULT
+        };
+        let big = scan_all(&session, u128::MAX / 3 - 5000, 10_000, &config);
+        for result in &big {
+            assert_eq!(
+                result.trajectory_sum(),
+                crate::cpu_collatz(result.n()).trajectory_sum()
+            );
+        }
+        assert!(big
+            .iter()
+            .any(|result| result.trajectory_sum() == Some(u128::MAX)));
+        assert!(big
+            .iter()
+            .any(|result| result.outcome() == crate::Outcome::Overflowed));
+    }
+
+    #[test]
+    fn extras_only_in_full_records() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let short = scan_all(&session, 1, 5000, &RunConfig::DEFAULT);
+        assert!(short
+            .iter()
+            .all(|result| !result.is_full_record() && result.trajectory_sum().is_none()));
+        let config = RunConfig {
+            full_record: true,
+            ..RunConfig::DEFAULT
+        };
+        let full = scan_all(&session, 1, 5000, &config);
+        assert!(full.iter().all(CollatzResult::is_full_record));
+        let host: Vec<_> = (1..=5000).map(crate::cpu_collatz).collect();
+        assert!(crate::results_agree(&full, &host).is_empty());
+        assert!(crate::results_agree(&short, &full).is_empty());
+    }
+
+    #[test]
+    fn saturate_overflow_clamps_and_flags() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        for lookup_table in [false, true] {
+            let plain = RunConfig {
+                lookup_table,
+                full_record: true,
+                ..RunConfig::DEFAULT
+            };
+            let saturating = RunConfig {
+                saturate_overflow: true,
+                ..plain
+            };
+            let start = u128::MAX / 3 - 5000;
+            let overflowing = scan_all(&session, start, 10_000, &plain);
+            let saturated = scan_all(&session, start, 10_000, &saturating);
+            let mut flagged = 0;
+            for (overflowed, clamped) in overflowing.iter().zip(&saturated) {
+                if overflowed.outcome() != crate::Outcome::Overflowed {
+                    assert_eq!(overflowed, clamped);
+                    continue;
+                }
+                flagged += 1;
+                assert_eq!(clamped.outcome(), crate::Outcome::Saturated);
+                assert_eq!(
+                    (clamped.max(), clamped.trajectory_sum()),
+                    (u128::MAX, Some(u128::MAX))
+                );
+                // the clamped step is counted
+                assert_eq!(clamped.steps(), overflowed.steps() + 1);
+                assert_eq!(clamped.steps_shortcut(), overflowed.steps_shortcut());
+                assert_eq!(clamped.height().map(u128::leading_zeros), Some(0));
+            }
+            assert!(flagged > 1000, "{flagged}");
+
+            let mut summary = crate::ConvergenceSummary::default();
+            summary.add(&saturated);
+            assert_eq!((summary.saturated, summary.overflowed), (flagged, 0));
+            let archive = crate::import_sweep(&crate::export_sweep(&saturating, &saturated));
+            let (config, results) = archive.unwrap().into_parts();
+            assert!(config.saturate_overflow);
+            assert_eq!(results, saturated);
+        }
+    }
+
+    #[test]
+    fn struct_of_arrays_matches_array_of_structs() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        for start in [1, (1 << 100) + 12345] {
+            let read = |input_layout| {
+                let config = RunConfig {
+                    input_layout,
+                    chunk_size: 7000,
+                    ..RunConfig::DEFAULT
+                };
+                scan_all(&session, start, 20_000, &config)
+            };
+            let array_of_structs = read(InputLayout::ArrayOfStructs);
+            assert_eq!(array_of_structs[0].n(), start);
+            assert_eq!(
+                read(InputLayout::StructOfArrays),
+                array_of_structs,
+                "start {start}"
+            );
+        }
+    }
+}
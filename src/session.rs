@@ -0,0 +1,667 @@
+use std::cell::{Cell, OnceCell, RefCell};
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::console_log;
+use crate::error::CollatzError;
+use crate::gpu::{
+    buffer_size, create_lookup_buffer, create_pipeline, input_data, max_numbers_per_dispatch,
+    request_device, submit_chunk, submit_with, InputLayout, KernelBuffers, ReadbackStrategy,
+};
+use crate::result::{decode_results, CollatzResult, ResultSource, RESULT_WORDS};
+use crate::scan::{scan_until, RunConfig};
+
+/// A device with the collatz pipeline already built, so runs on it skip the
+/// adapter, device and shader setup
+pub(crate) struct CollatzSession {
+    pub(crate) device: wgpu::Device,
+    pub(crate) queue: wgpu::Queue,
+    pub(crate) pipeline: wgpu::ComputePipeline,
+    // What the adapter said about itself, `None` for a device someone else
+    // set up
+    pub(crate) adapter_info: Option<wgpu::AdapterInfo>,
+    // Buffers kept from the largest `run` so far, `None` while a run has
+    // them out
+    buffers: RefCell<Option<KernelBuffers>>,
+    // How many times `run` has had to allocate buffers
+    allocations: Cell<u32>,
+    // Table for `main_lookup`, uploaded the first time a scan asks for it
+    lookup: OnceCell<wgpu::Buffer>,
+    // Result of `recommend_chunk_size` once it has run
+    chunk_size: Cell<Option<u32>>,
+}
+
+// Chunk sizes `recommend_chunk_size` tries, smallest first, and where the
+// numbers it times start so they take a typical number of steps
+const CALIBRATION_SIZES: [u32; 4] = [1 << 12, 1 << 14, 1 << 16, 1 << 18];
+const CALIBRATION_START: u128 = 1 << 40;
+
+// Scans that check the time between chunks use a fixed chunk size, so
+// neither a calibration run nor an unusually large chunk eats the budget
+const TIMED_CONFIG: RunConfig = RunConfig {
+    chunk_size: crate::RANGE,
+    ..RunConfig::DEFAULT
+};
+
+thread_local! {
+    // Session built by `warmup` or the first call that needs the GPU, reused
+    // by every later call so its kept buffers and lookup table carry over
+    static WARM_SESSION: RefCell<Option<Rc<CollatzSession>>> = const { RefCell::new(None) };
+    // Whether `run` keeps its buffers for the next run, see `set_buffer_reuse`
+    static BUFFER_REUSE: Cell<bool> = const { Cell::new(true) };
+    // How reads wait for their submission, see `set_poll_strategy`
+    static POLL_STRATEGY: Cell<PollStrategy> = const { Cell::new(PollStrategy::Wait) };
+    // Whether output buffers are cleared to catch skipped records, see
+    // `set_readback_checks`
+    static READBACK_CHECKS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How reading a result back waits for the GPU to finish
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PollStrategy {
+    /// Block in `device.poll` until the submission is done. Lowest latency,
+    /// but some native drivers spin a core for the whole wait.
+    #[default]
+    Wait,
+    /// Poll without blocking and sleep `interval_ms` between polls until the
+    /// output has mapped. Up to one interval later per read, but the thread
+    /// is asleep in between, for long sweeps on a shared machine.
+    Sleep { interval_ms: u32 },
+}
+
+impl CollatzSession {
+    pub(crate) async fn new() -> Result<CollatzSession, CollatzError> {
+        let (device, queue, adapter_info) = request_device().await?;
+
+        // some adapters claim compute support and then fail on the first
+        // real pipeline, so build it and run 27 through it before anyone
+        // submits real work. 27 takes 111 steps. `session()` keeps the
+        // session, so this only runs once per thread.
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let session = CollatzSession {
+            adapter_info: Some(adapter_info),
+            ..CollatzSession::from_device(device, queue)
+        };
+        let probe = submit_chunk(
+            &session.device,
+            &session.queue,
+            &session.pipeline,
+            vec![27],
+            InputLayout::ArrayOfStructs,
+            1,
+            None,
+            ReadbackStrategy::Direct,
+            false,
+            1,
+        )
+        .read(&session.device)
+        .await;
+        let invalid = session.device.pop_error_scope().await.is_some();
+        match probe {
+            Ok(results) if !invalid && results.first().map(|r| r.steps()) == Some(111) => {
+                Ok(session)
+            }
+            _ => Err(CollatzError::NoComputeSupport),
+        }
+    }
+
+    // Build the pipeline on a device someone else set up
+    pub(crate) fn from_device(device: wgpu::Device, queue: wgpu::Queue) -> CollatzSession {
+        let pipeline = create_pipeline(&device);
+        CollatzSession {
+            device,
+            queue,
+            pipeline,
+            adapter_info: None,
+            buffers: RefCell::new(None),
+            allocations: Cell::new(0),
+            lookup: OnceCell::new(),
+            chunk_size: Cell::new(None),
+        }
+    }
+
+    // Get the first real run ready ahead of time: buffers big enough for a
+    // RANGE sized run are kept for `run` to reuse, and 27 goes through them
+    // so the driver's first-use work on them is done too
+    pub(crate) async fn warm_up(&self) -> Result<(), CollatzError> {
+        let count = max_numbers_per_dispatch(&self.device).min(crate::RANGE as u64) as usize;
+        let input_size = buffer_size(count, 16)?;
+        let output_size = buffer_size(count, RESULT_WORDS * 4)?;
+        if BUFFER_REUSE.with(Cell::get) {
+            let kept = self.buffers.take();
+            let keep = match kept {
+                Some(kept) if kept.fits(input_size, output_size) => kept,
+                _ => {
+                    self.allocations.set(self.allocations.get() + 1);
+                    KernelBuffers::new(&self.device, input_size, output_size)
+                }
+            };
+            self.buffers.replace(Some(keep));
+        }
+
+        match self.run(&[27]).await?.first().map(|result| result.steps()) {
+            Some(111) => Ok(()),
+            _ => Err(CollatzError::NoComputeSupport),
+        }
+    }
+
+    // Run the collatz shader over every number in `numbers` and decode the output
+    pub(crate) async fn run(&self, numbers: &[u128]) -> Result<Vec<CollatzResult>, CollatzError> {
+        let limit = max_numbers_per_dispatch(&self.device);
+        if numbers.len() as u64 > limit {
+            return Err(CollatzError::TooManyNumbers {
+                count: numbers.len() as u64,
+                limit,
+            });
+        }
+        if numbers.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let input = input_data(numbers, InputLayout::ArrayOfStructs);
+        let output_size = buffer_size(numbers.len(), RESULT_WORDS * 4)?;
+
+        // reuse the kept buffers if they're free and big enough, otherwise
+        // allocate ones that fit this run exactly
+        let buffers = match self.buffers.take() {
+            Some(buffers) if buffers.fits(input.len() as u64, output_size) => buffers,
+            kept => {
+                self.buffers.replace(kept);
+                self.allocations.set(self.allocations.get() + 1);
+                KernelBuffers::new(&self.device, input.len() as u64, output_size)
+            }
+        };
+
+        let words = submit_with(
+            &self.device,
+            &self.queue,
+            &self.pipeline,
+            &buffers,
+            &input,
+            InputLayout::ArrayOfStructs,
+            1,
+            1,
+            output_size,
+            None,
+            1,
+        )
+        .read(&self.device)
+        .await;
+
+        // keep whichever buffers are larger for next time
+        if BUFFER_REUSE.with(Cell::get) {
+            let keep = match self.buffers.take() {
+                Some(kept) if !kept.fits(input.len() as u64, output_size) => buffers,
+                Some(kept) => kept,
+                None => buffers,
+            };
+            self.buffers.replace(Some(keep));
+        } else {
+            self.buffers.take();
+        }
+
+        Ok(decode_results(
+            numbers,
+            bytemuck::cast_slice(&words?),
+            false,
+        )?)
+    }
+
+    // Same as `run`, but each distinct number is only run once, with the
+    // results mapped back to every position in `numbers`. Repeats are
+    // labelled `ResultSource::Derived`.
+    pub(crate) async fn run_distinct(
+        &self,
+        numbers: &[u128],
+    ) -> Result<Vec<CollatzResult>, CollatzError> {
+        let mut distinct = numbers.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() == numbers.len() {
+            return self.run(numbers).await;
+        }
+
+        // the first occurrence of each number keeps the GPU's result, later
+        // ones are copies of it
+        let results = self.run(&distinct).await?;
+        let mut seen = vec![false; distinct.len()];
+        Ok(numbers
+            .iter()
+            .map(|&n| {
+                let i = distinct.partition_point(|&d| d < n);
+                match std::mem::replace(&mut seen[i], true) {
+                    false => results[i],
+                    true => results[i].with_source(ResultSource::Derived),
+                }
+            })
+            .collect())
+    }
+
+    // Chunk size with the best measured throughput on this device out of
+    // CALIBRATION_SIZES, timed with one run each. Only measured the first
+    // time, later calls return the same size.
+    pub(crate) async fn recommend_chunk_size(&self) -> Result<u32, CollatzError> {
+        if let Some(chunk_size) = self.chunk_size.get() {
+            return Ok(chunk_size);
+        }
+
+        let limit = max_numbers_per_dispatch(&self.device);
+        let mut best = (0.0, limit.min(CALIBRATION_SIZES[0] as u64) as u32);
+        for size in CALIBRATION_SIZES
+            .into_iter()
+            .filter(|&size| size as u64 <= limit)
+        {
+            let numbers: Vec<u128> =
+                (CALIBRATION_START..CALIBRATION_START + size as u128).collect();
+            let started = now_ms();
+            self.run(&numbers).await?;
+            // coarse browser clocks can read 0 for a quick run
+            let throughput = size as f64 / (now_ms() - started).max(1.0);
+            if throughput >= best.0 {
+                best = (throughput, size);
+            }
+        }
+
+        self.chunk_size.set(Some(best.1));
+        Ok(best.1)
+    }
+
+    // The lookup table for `main_lookup` pipelines, uploaded once per session
+    pub(crate) fn lookup_table(&self) -> &wgpu::Buffer {
+        self.lookup
+            .get_or_init(|| create_lookup_buffer(&self.device))
+    }
+
+    // Buffer allocations `run` has made so far
+    pub(crate) fn allocations(&self) -> u32 {
+        self.allocations.get()
+    }
+
+    // Results for `count` numbers from `start`, stopping early once
+    // `timeout_ms` has passed. The time is checked after each chunk is read,
+    // so the chunk in progress when it runs out still finishes and a single
+    // chunk can overrun. Whatever was completed is returned, in order, so a
+    // result shorter than `count` means it timed out.
+    pub(crate) async fn run_with_timeout(
+        &self,
+        start: u128,
+        count: u64,
+        timeout_ms: f64,
+    ) -> Result<Vec<CollatzResult>, CollatzError> {
+        let deadline = now_ms() + timeout_ms;
+        let mut results = Vec::new();
+        scan_until(self, start, count, &TIMED_CONFIG, |chunk| {
+            results.extend_from_slice(chunk);
+            if now_ms() >= deadline {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .await?;
+        Ok(results)
+    }
+
+    // The first result from `start` on matching `found`, or `None` if there
+    // was none in `count` numbers or `timeout_ms` ran out first. Time is
+    // checked after each chunk, the same as `run_with_timeout`.
+    pub(crate) async fn find_with_timeout<F>(
+        &self,
+        start: u128,
+        count: u64,
+        timeout_ms: f64,
+        mut found: F,
+    ) -> Result<Option<CollatzResult>, CollatzError>
+    where
+        F: FnMut(&CollatzResult) -> bool,
+    {
+        let deadline = now_ms() + timeout_ms;
+        let mut hit = None;
+        scan_until(self, start, count, &TIMED_CONFIG, |chunk| {
+            hit = chunk.iter().find(|result| found(result)).copied();
+            if hit.is_some() || now_ms() >= deadline {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .await?;
+        Ok(hit)
+    }
+}
+
+// Milliseconds on a clock that only goes forward, only the difference
+// between two calls means anything. `Instant` isn't available on
+// wasm32-unknown-unknown, so that uses the JS clock.
+fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        static EPOCH: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        let epoch = *EPOCH.get_or_init(std::time::Instant::now);
+        epoch.elapsed().as_secs_f64() * 1000.0
+    }
+}
+
+// Wait `ms` milliseconds, letting the browser get on with other work and
+// other GPU clients in the meantime. Goes through setTimeout on wasm32 so it
+// works in workers as well as on the page, and blocks the thread natively.
+pub(crate) async fn sleep_ms(ms: u32) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let promise = js_sys::Promise::new(&mut |resolve, _| {
+            let set_timeout = js_sys::Reflect::get(&js_sys::global(), &"setTimeout".into())
+                .ok()
+                .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+            match set_timeout {
+                Some(set_timeout) => {
+                    let _ = set_timeout.call2(&JsValue::UNDEFINED, &resolve, &ms.into());
+                }
+                // nowhere to wait, carry on straight away
+                None => {
+                    let _ = resolve.call0(&JsValue::UNDEFINED);
+                }
+            }
+        });
+        let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::thread::sleep(std::time::Duration::from_millis(ms as u64));
+    }
+}
+
+// A session on `test_device`, `None` when there is no GPU to test on
+#[cfg(test)]
+pub(crate) fn test_session() -> Option<CollatzSession> {
+    let (device, queue) = crate::gpu::test_device()?;
+    let session = CollatzSession::from_device(device, queue);
+    // calibrating in every test would take longer than most of them
+    session.chunk_size.set(Some(crate::RANGE));
+    Some(session)
+}
+
+// Full records for `numbers`, for tests of the fields short ones leave out
+#[cfg(test)]
+pub(crate) fn run_full(session: &CollatzSession, numbers: &[u128]) -> Vec<CollatzResult> {
+    let pipeline = crate::gpu::create_pipeline_for(
+        &session.device,
+        crate::StepConvention::UntilOne,
+        InputLayout::ArrayOfStructs,
+        false,
+        1,
+        false,
+        true,
+    );
+    let chunk = submit_chunk(
+        &session.device,
+        &session.queue,
+        &pipeline,
+        numbers.to_vec(),
+        InputLayout::ArrayOfStructs,
+        1,
+        None,
+        ReadbackStrategy::Direct,
+        true,
+        1,
+    );
+    pollster::block_on(chunk.read(&session.device)).unwrap()
+}
+
+// The session every call on this thread shares, set up by the first one to
+// need it. A failed setup isn't cached, so the next call tries again.
+pub(crate) async fn session() -> Result<Rc<CollatzSession>, CollatzError> {
+    if let Some(session) = WARM_SESSION.with(|warm| warm.borrow().clone()) {
+        return Ok(session);
+    }
+    let session = Rc::new(CollatzSession::new().await?);
+    // another call may have finished setting one up while this one awaited,
+    // keep whichever got there first
+    Ok(WARM_SESSION.with(|warm| warm.borrow_mut().get_or_insert(session).clone()))
+}
+
+/// Whether runs keep their GPU buffers for the next run of the same size or
+/// smaller, instead of allocating new ones each time. On by default. Turning
+/// it off frees the kept buffers at the end of the next run.
+#[wasm_bindgen]
+pub fn set_buffer_reuse(enabled: bool) {
+    BUFFER_REUSE.with(|reuse| reuse.set(enabled));
+}
+
+/// How reads on this thread wait for the GPU from now on, `Wait` by
+/// default. Only matters natively: in the browser `device.poll` never
+/// blocks and the mapping is awaited on the event loop either way.
+pub fn set_poll_strategy(strategy: PollStrategy) {
+    POLL_STRATEGY.with(|current| current.set(strategy));
+}
+
+pub(crate) fn poll_strategy() -> PollStrategy {
+    POLL_STRATEGY.with(Cell::get)
+}
+
+/// Check every result read back from now on was really written by the
+/// shader. Every dispatch's output buffer is cleared to zeros first, and a
+/// result record that still holds nothing but zeros after the readback
+/// fails the run with `DecodeError::Unwritten` instead of decoding garbage.
+/// Other kernels' outputs are cleared the same way, so a skipped word reads
+/// back as 0 rather than a stale value. Off by default, for debugging
+/// dispatch size bugs: the clear is an extra pass over the output. The crate
+/// has no log levels to hang this off, so it is its own switch, like
+/// `set_buffer_reuse`.
+#[wasm_bindgen]
+pub fn set_readback_checks(enabled: bool) {
+    READBACK_CHECKS.with(|checks| checks.set(enabled));
+}
+
+pub(crate) fn readback_checks() -> bool {
+    READBACK_CHECKS.with(Cell::get)
+}
+
+/// How many times the shared session has had to allocate buffers for a
+/// run, 0 before `warmup` or the first run. Stays put over runs no larger than the largest
+/// so far while buffer reuse is on.
+#[wasm_bindgen]
+pub fn buffer_allocations() -> u32 {
+    WARM_SESSION.with(|warm| {
+        warm.borrow()
+            .as_ref()
+            .map_or(0, |session| session.allocations())
+    })
+}
+
+/// The chunk size scans use unless `RunConfig::chunk_size` says otherwise,
+/// measured on this device the first time it's needed by timing a run at
+/// each of a few sizes and keeping the fastest per number.
+#[wasm_bindgen]
+pub async fn recommended_chunk_size() -> Result<u32, JsValue> {
+    Ok(session().await?.recommend_chunk_size().await?)
+}
+
+/// Acquire the GPU, build the pipeline and run a throwaway dispatch ahead of
+/// time so the first real run doesn't pay for any of it. UIs should call
+/// this once after `init()`, e.g. during page load.
+///
+/// The dispatch goes through buffers sized for a full `do_gpu_collatz` run,
+/// which are then kept for it. On a software adapter (llvmpipe) setting up
+/// took about 15ms, and the first 1000 number run then took about 3ms
+/// without this and 1.8ms with it, the same as every run after. Shader
+/// compilation in browsers is usually far slower than that.
+///
+/// If this fails (e.g. there is no adapter) nothing is cached and later
+/// calls try to set up the GPU themselves. The session is shared with
+/// every later call, so its probe dispatch only ever runs once.
+#[wasm_bindgen]
+pub async fn warmup() -> Result<(), JsValue> {
+    // an already shared session has run its probe, only its buffers need
+    // warming
+    if let Err(e) = session().await?.warm_up().await {
+        WARM_SESSION.with(|warm| *warm.borrow_mut() = None);
+        return Err(e.into());
+    }
+    console_log!("GPU session warmed up");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warmed_session_serves_later_runs() {
+        let Some(warm) = test_session() else { return };
+        let warm = Rc::new(warm);
+        WARM_SESSION.with(|session| *session.borrow_mut() = Some(warm.clone()));
+        let served = pollster::block_on(session()).unwrap();
+        WARM_SESSION.with(|session| *session.borrow_mut() = None);
+        assert!(Rc::ptr_eq(&served, &warm));
+        let results = pollster::block_on(served.run(&[27, 97])).unwrap();
+        assert_eq!((results[0].steps(), results[1].steps()), (111, 118));
+    }
+
+    #[test]
+    fn timeout_returns_completed_prefix() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let started = now_ms();
+        let results = pollster::block_on(session.run_with_timeout(1, 50_000_000, 20.0)).unwrap();
+        // it can overrun by the chunk in flight and the ones queued behind it
+        assert!(now_ms() - started < 10_000.0);
+        assert!(!results.is_empty() && results.len() < 50_000_000);
+        assert!(results.iter().zip(1..).all(|(result, n)| result.n() == n));
+        let all = pollster::block_on(session.run_with_timeout(1, 1000, 60_000.0)).unwrap();
+        assert_eq!(all.len(), 1000);
+    }
+
+    #[test]
+    fn same_size_runs_reuse_buffers() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let run = |numbers: &[u128]| pollster::block_on(session.run(numbers)).unwrap();
+        let numbers: Vec<u128> = (1..=5000).collect();
+        let want = run(&numbers);
+        for _ in 0..20 {
+            assert_eq!(run(&numbers), want);
+        }
+        assert_eq!(session.allocations(), 1);
+        // a smaller run reuses them without reading back the old tail
+        assert_eq!(run(&numbers[99..199]), want[99..199]);
+        assert_eq!(session.allocations(), 1);
+        let more: Vec<u128> = (1..=9000).collect();
+        assert_eq!(run(&more)[..5000], want);
+        run(&numbers);
+        assert_eq!(session.allocations(), 2);
+    }
+
+    #[test]
+    fn finds_next_delay_record() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        // the first n after `record` that takes more steps than it
+        let next_record = |record: u128| {
+            let steps = crate::cpu_collatz(record).steps();
+            let search = session.find_with_timeout(record + 1, 1 << 40, 60_000.0, |result| {
+                result.outcome() == crate::Outcome::Converged && result.steps() > steps
+            });
+            pollster::block_on(search).unwrap().unwrap()
+        };
+        let after_27 = next_record(27);
+        assert_eq!((after_27.n(), after_27.steps()), (54, 112));
+        // 97 takes 118 steps, and the next to take more is 129
+        assert_eq!(next_record(97).n(), 129);
+        // a zero budget stops after the first chunk
+        let none = session.find_with_timeout(1, 1 << 40, 0.0, |_| false);
+        assert!(pollster::block_on(none).unwrap().is_none());
+    }
+
+    #[test]
+    fn distinct_runs_map_back_duplicates() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let numbers = [27, 5, 27, 1, 5, 5, 97, 1 << 100, 27, 1 << 100, 2];
+        let got = pollster::block_on(session.run_distinct(&numbers)).unwrap();
+        let want = pollster::block_on(session.run(&numbers)).unwrap();
+        assert!(crate::results_agree(&got, &want).is_empty());
+        let derived: Vec<usize> = (0..got.len())
+            .filter(|&i| got[i].source() == ResultSource::Derived)
+            .collect();
+        assert_eq!(derived, [2, 4, 5, 8, 9]);
+        let distinct: Vec<u128> = (1..500).collect();
+        let got = pollster::block_on(session.run_distinct(&distinct)).unwrap();
+        assert_eq!(got, pollster::block_on(session.run(&distinct)).unwrap());
+    }
+
+    #[test]
+    fn chunk_size_is_calibrated_once() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        session.chunk_size.set(None);
+        let size = pollster::block_on(session.recommend_chunk_size()).unwrap();
+        assert!(size >= 1 && size as u64 <= max_numbers_per_dispatch(&session.device));
+        assert_eq!(
+            pollster::block_on(session.recommend_chunk_size()).unwrap(),
+            size
+        );
+
+        // the default config scans in chunks of the calibrated size
+        let config = RunConfig::DEFAULT;
+        let count = size as u64 + 7;
+        let mut got = Vec::new();
+        pollster::block_on(crate::scan::scan(&session, 1, count, &config, |results| {
+            got.extend_from_slice(results)
+        }))
+        .unwrap();
+        let want: Vec<_> = (1..=count as u128).map(crate::cpu_collatz).collect();
+        assert!(crate::results_agree(&got, &want).is_empty());
+    }
+
+    #[test]
+    fn warm_up_then_run() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        pollster::block_on(session.warm_up()).unwrap();
+        let numbers: Vec<u128> = (1..=1000).collect();
+        let got = pollster::block_on(session.run(&numbers)).unwrap();
+        let want: Vec<_> = numbers.iter().map(|&n| crate::cpu_collatz(n)).collect();
+        assert!(crate::results_agree(&got, &want).is_empty());
+        // the run went through the buffers warm_up kept
+        assert_eq!(session.allocations(), 1);
+    }
+
+    #[test]
+    fn poll_strategies_agree() {
+        let Some(session) = test_session() else {
+            return;
+        };
+        let numbers: Vec<u128> = (1..50_000).collect();
+        let waited = pollster::block_on(session.run(&numbers)).unwrap();
+        set_poll_strategy(PollStrategy::Sleep { interval_ms: 1 });
+        let slept = pollster::block_on(session.run(&numbers));
+        // a windowed scan waits once per window
+        let config = RunConfig {
+            chunk_size: 7000,
+            readback_strategy: ReadbackStrategy::Windowed,
+            ..RunConfig::DEFAULT
+        };
+        let mut scanned = Vec::new();
+        let scan = crate::scan::scan(&session, 1, 49_999, &config, |results| {
+            scanned.extend_from_slice(results)
+        });
+        let scan = pollster::block_on(scan);
+        set_poll_strategy(PollStrategy::Wait);
+        scan.unwrap();
+        assert_eq!(slept.unwrap(), waited);
+        assert_eq!(scanned, waited);
+        assert_eq!(waited[26].steps(), 111);
+    }
+}
@@ -0,0 +1,191 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{
+    buffer_size, create_signed_pipeline, input_data, max_records_per_dispatch, submit, InputLayout,
+};
+use crate::result::DecodeError;
+use crate::session::CollatzSession;
+
+// Each `signed_orbits` record is cycle (1 word) + tail (1 word) + overflowed (1 word)
+const SIGNED_WORDS: usize = 3;
+
+// The known cycles on Z in the shader's order (see `signed_cycle` in
+// add.wgsl), each named by its member closest to 0
+const KNOWN_CYCLES: [i32; 5] = [0, 1, -1, -5, -17];
+
+/// Where a signed start ends up under 3n + 1 on all of Z.
+///
+/// Every integer checked so far falls into one of five cycles: 0 -> 0,
+/// 1 -> 4 -> 2 -> 1, and three negative ones, -1 -> -2 -> -1,
+/// -5 -> -14 -> -7 -> -20 -> -10 -> -5 and an 18 step cycle through -17.
+///
+/// ```js
+/// const o = (await do_gpu_collatz_signed("-7", 1))[0];
+/// o.cycle;      // -5, the member of its cycle closest to 0
+/// o.tail;       // 0, -7 is on that cycle already
+/// o.overflowed; // false
+/// ```
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignedOrbit {
+    n: i128,
+    cycle: Option<i32>,
+    tail: u32,
+    overflowed: bool,
+}
+
+#[wasm_bindgen]
+impl SignedOrbit {
+    #[wasm_bindgen(getter = start)]
+    pub fn start_string(&self) -> String {
+        self.n.to_string()
+    }
+
+    /// The member closest to 0 of the cycle it ended on: 0, 1, -1, -5 or
+    /// -17. `undefined` if it overflowed or found no known cycle within
+    /// `MAX_STEPS`.
+    #[wasm_bindgen(getter)]
+    pub fn cycle(&self) -> Option<i32> {
+        self.cycle
+    }
+
+    /// Steps before the first value on the cycle, or how many steps it got
+    /// through if there is no cycle
+    #[wasm_bindgen(getter)]
+    pub fn tail(&self) -> u32 {
+        self.tail
+    }
+
+    /// 3n + 1 left the i128 range
+    #[wasm_bindgen(getter)]
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl SignedOrbit {
+    pub fn n(&self) -> i128 {
+        self.n
+    }
+}
+
+// Run the `signed_orbits` entry point over `numbers`, uploaded as their two's
+// complement bits
+pub(crate) async fn signed_orbits(
+    session: &CollatzSession,
+    numbers: &[i128],
+) -> Result<Vec<SignedOrbit>, CollatzError> {
+    let device = &session.device;
+    let limit = max_records_per_dispatch(device, SIGNED_WORDS as u64 * 4);
+    if numbers.len() as u64 > limit {
+        return Err(CollatzError::TooManyNumbers {
+            count: numbers.len() as u64,
+            limit,
+        });
+    }
+
+    let bits: Vec<u128> = numbers.iter().map(|&n| n as u128).collect();
+    let pipeline = create_signed_pipeline(device);
+    let output_size = buffer_size(numbers.len(), SIGNED_WORDS * 4)?;
+    let input = input_data(&bits, InputLayout::ArrayOfStructs);
+    let words = submit(device, &session.queue, &pipeline, &input, 10, output_size)
+        .read(device)
+        .await?;
+    if words.len() != numbers.len() * SIGNED_WORDS {
+        return Err(DecodeError::WrongLength {
+            expected: output_size as usize,
+            actual: words.len() * 4,
+        }
+        .into());
+    }
+
+    Ok(numbers
+        .iter()
+        .zip(words.chunks_exact(SIGNED_WORDS))
+        .map(|(&n, words)| SignedOrbit {
+            n,
+            cycle: KNOWN_CYCLES.get(words[0] as usize).copied(),
+            tail: words[1],
+            overflowed: words[2] != 0,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::MAX_STEPS;
+
+    // Every member of each of KNOWN_CYCLES, in the same order
+    const CYCLE_MEMBERS: [&[i128]; 5] = [
+        &[0],
+        &[1, 4, 2],
+        &[-1, -2],
+        &[-5, -14, -7, -20, -10],
+        &[
+            -17, -50, -25, -74, -37, -110, -55, -164, -82, -41, -122, -61, -182, -91, -272, -136,
+            -68, -34,
+        ],
+    ];
+
+    // Cycle, tail and overflow of n walked on the host
+    fn host_orbit(n: i128) -> (Option<i32>, u32, bool) {
+        let (mut value, mut tail) = (n, 0);
+        loop {
+            if let Some(i) = CYCLE_MEMBERS.iter().position(|m| m.contains(&value)) {
+                return (Some(KNOWN_CYCLES[i]), tail, false);
+            }
+            if tail >= MAX_STEPS {
+                return (None, tail, false);
+            }
+            value = if value % 2 == 0 {
+                value / 2
+            } else {
+                // adding 1 first, so 3n + 1 = i128::MIN doesn't overflow on 3n
+                let next = value.checked_add(1).and_then(|v| v.checked_add(value));
+                match next.and_then(|v| v.checked_add(value)) {
+                    Some(next) => next,
+                    None => return (None, tail, true),
+                }
+            };
+            tail += 1;
+        }
+    }
+
+    #[test]
+    fn signed_cycles_identified() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let mut numbers: Vec<i128> = (-3000..3000).collect();
+        numbers.extend([
+            i128::MIN,
+            i128::MIN + 1,
+            i128::MAX,
+            i128::MAX / 3,
+            -(i128::MAX / 3) - 1,
+            i128::MIN / 3,
+            i128::MIN / 3 - 1,
+            -(1 << 100) - 1,
+        ]);
+        let orbits = pollster::block_on(signed_orbits(&session, &numbers)).unwrap();
+        for orbit in &orbits {
+            let got = (orbit.cycle(), orbit.tail(), orbit.overflowed());
+            assert_eq!(got, host_orbit(orbit.n()), "n {}", orbit.n());
+        }
+        for (n, cycle) in [
+            (-1, -1),
+            (-2, -1),
+            (-5, -5),
+            (-20, -5),
+            (-17, -17),
+            (-272, -17),
+            (0, 0),
+            (4, 1),
+        ] {
+            let orbit = orbits.iter().find(|orbit| orbit.n() == n).unwrap();
+            assert_eq!((orbit.cycle(), orbit.tail()), (Some(cycle), 0));
+        }
+    }
+}
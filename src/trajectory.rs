@@ -0,0 +1,130 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::CollatzError;
+use crate::gpu::{buffer_size, create_trajectory_pipeline, input_data, submit, InputLayout};
+use crate::result::DecodeError;
+use crate::session::CollatzSession;
+
+// The `trace` output starts with length, truncated and overflowed (1 word
+// each), followed by one 4 word value per slot
+const HEADER_WORDS: usize = 3;
+
+/// Every value of one trajectory as computed by the shader, from n itself
+/// down to 1.
+///
+/// ```js
+/// const t = await do_gpu_collatz_full_trajectory("6", 100);
+/// t.values;     // ["6", "3", "10", "5", "16", "8", "4", "2", "1"]
+/// t.truncated;  // false, it all fit in max_len
+/// t.overflowed; // false
+/// ```
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trajectory {
+    values: Vec<u128>,
+    truncated: bool,
+    overflowed: bool,
+}
+
+#[wasm_bindgen]
+impl Trajectory {
+    #[wasm_bindgen(getter = values)]
+    pub fn value_strings(&self) -> Vec<String> {
+        self.values.iter().map(|value| value.to_string()).collect()
+    }
+
+    /// The trajectory went on past `max_len` values, `values` is its start
+    #[wasm_bindgen(getter)]
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// 3n + 1 overflowed 128 bits, the last value is the one it overflowed on
+    #[wasm_bindgen(getter)]
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl Trajectory {
+    pub fn values(&self) -> &[u128] {
+        &self.values
+    }
+}
+
+// Run the `trace` entry point for n with room for `max_len` values
+pub(crate) async fn trace(
+    session: &CollatzSession,
+    n: u128,
+    max_len: u32,
+) -> Result<Trajectory, CollatzError> {
+    let device = &session.device;
+    let output_size = buffer_size(max_len as usize, 16)? + HEADER_WORDS as u64 * 4;
+    let limits = device.limits();
+    if max_len == 0
+        || output_size > limits.max_storage_buffer_binding_size as u64
+        || output_size > limits.max_buffer_size
+    {
+        return Err(CollatzError::InvalidArgument(
+            "max_len must be at least 1 and fit in one storage buffer",
+        ));
+    }
+
+    let pipeline = create_trajectory_pipeline(device);
+    let input = input_data(&[n], InputLayout::ArrayOfStructs);
+    let words = submit(device, &session.queue, &pipeline, &input, 3, output_size)
+        .read(device)
+        .await?;
+
+    if words.len() != output_size as usize / 4 {
+        return Err(DecodeError::WrongLength {
+            expected: output_size as usize,
+            actual: words.len() * 4,
+        }
+        .into());
+    }
+
+    let values = words[HEADER_WORDS..]
+        .chunks_exact(4)
+        .take(words[0] as usize)
+        .map(|parts| crate::u32_array_to_u128(&[parts[0], parts[1], parts[2], parts[3]]))
+        .collect();
+
+    Ok(Trajectory {
+        values,
+        truncated: words[1] != 0,
+        overflowed: words[2] != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::walk;
+
+    #[test]
+    fn trajectory_matches_host() {
+        let Some(session) = crate::session::test_session() else {
+            return;
+        };
+        let trace = |n, max_len| pollster::block_on(trace(&session, n, max_len));
+        for n in [1, 6, 27, 97, 871] {
+            let trajectory = trace(n, 1000).unwrap();
+            assert_eq!(trajectory.values(), walk(n));
+            assert!(!trajectory.truncated() && !trajectory.overflowed());
+        }
+        let cut = trace(27, 10).unwrap();
+        assert_eq!(cut.values(), &walk(27)[..10]);
+        assert!(cut.truncated());
+        // 6 takes 8 steps, exactly filling 9 values
+        let full = trace(6, 9).unwrap();
+        assert_eq!(full.values().len(), 9);
+        assert!(!full.truncated());
+
+        let big = (1 << 127) + 1;
+        let overflowed = trace(big, 10).unwrap();
+        assert_eq!(overflowed.values(), [big]);
+        assert!(overflowed.overflowed());
+        assert!(trace(6, 0).is_err());
+    }
+}